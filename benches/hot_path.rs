@@ -0,0 +1,131 @@
+//! Work done on every focus change, run with `cargo bench --features bench`
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use i3ipc::reply::{Node, NodeBorder, NodeLayout, NodeType, WindowProperty};
+use transparentd::bench::{opacity_cmd, AllWindows, Opacity, RuleConfig, Rules, WindowInfo};
+
+fn node(id: i64, nodetype: NodeType, nodes: Vec<Node>, floating_nodes: Vec<Node>) -> Node {
+    let is_window = match nodetype {
+        NodeType::Con | NodeType::FloatingCon => nodes.is_empty(),
+        _ => false,
+    };
+    Node {
+        focus: Vec::new(),
+        nodes,
+        floating_nodes,
+        id,
+        name: Some(format!("node {}", id)),
+        nodetype,
+        border: NodeBorder::Normal,
+        current_border_width: 0,
+        layout: NodeLayout::SplitH,
+        percent: None,
+        rect: (0, 0, 1920, 1080),
+        window_rect: (0, 0, 1920, 1080),
+        deco_rect: (0, 0, 0, 0),
+        geometry: (0, 0, 1920, 1080),
+        window: if is_window { Some(id as i32) } else { None },
+        window_properties: if is_window {
+            Some(
+                vec![
+                    (WindowProperty::Class, format!("class{}", id % 7)),
+                    (WindowProperty::Instance, format!("instance{}", id % 5)),
+                    (WindowProperty::Title, format!("window {}", id)),
+                ]
+                .into_iter()
+                .collect(),
+            )
+        } else {
+            None
+        },
+        urgent: false,
+        focused: false,
+    }
+}
+
+/// 2 outputs with 10 workspaces each, every workspace holding 2 splits of 10 tiled windows
+/// and 2 floating ones, 880 windows total
+fn synthetic_tree() -> Node {
+    let mut next_id = 0;
+    let mut id = || {
+        next_id += 1;
+        next_id
+    };
+
+    let outputs = (0..2)
+        .map(|_| {
+            let workspaces = (0..10)
+                .map(|_| {
+                    let splits = (0..2)
+                        .map(|_| {
+                            let windows = (0..10)
+                                .map(|_| node(id(), NodeType::Con, vec![], vec![]))
+                                .collect();
+                            node(id(), NodeType::Con, windows, vec![])
+                        })
+                        .collect();
+                    let floating = (0..2)
+                        .map(|_| node(id(), NodeType::FloatingCon, vec![], vec![]))
+                        .collect();
+                    node(id(), NodeType::Workspace, splits, floating)
+                })
+                .collect();
+            node(id(), NodeType::Output, workspaces, vec![])
+        })
+        .collect();
+    node(id(), NodeType::Root, outputs, vec![])
+}
+
+fn traversal(c: &mut Criterion) {
+    let tree = synthetic_tree();
+    c.bench_function("traverse tree", |b| {
+        b.iter(|| AllWindows::new(black_box(tree.clone())).count())
+    });
+    c.bench_function("collect window infos", |b| {
+        b.iter(|| {
+            AllWindows::new(black_box(tree.clone()))
+                .map(|node| WindowInfo::from_node(&node))
+                .collect::<Vec<_>>()
+        })
+    });
+}
+
+fn rule_matching(c: &mut Criterion) {
+    // most windows match none of the rules and get checked against all of them
+    let configs: Vec<_> = (0..20)
+        .map(|i| RuleConfig {
+            class: Some(format!("class{}", i % 7)),
+            instance: Some(format!("instance{}", i)),
+            match_title: None,
+            workspace: None,
+            output: None,
+            focused: None,
+            unfocused: Opacity::new(0.9),
+        })
+        .collect();
+    let rules = Rules::new(&configs, Opacity::min(), Opacity::max());
+    let windows: Vec<_> = AllWindows::new(synthetic_tree())
+        .map(|node| WindowInfo::from_node(&node))
+        .collect();
+    c.bench_function("match rules", |b| {
+        b.iter(|| {
+            black_box(&windows)
+                .iter()
+                .filter_map(|window| rules.opacity_for(window, window.focused))
+                .count()
+        })
+    });
+}
+
+fn command_building(c: &mut Criterion) {
+    let opacity = Opacity::new(0.8).unwrap();
+    let windows: Vec<_> = AllWindows::new(synthetic_tree())
+        .map(|node| (node.id, opacity))
+        .collect();
+    c.bench_function("build opacity command", |b| {
+        b.iter(|| opacity_cmd(black_box(&windows)))
+    });
+}
+
+criterion_group!(benches, traversal, rule_matching, command_building);
+criterion_main!(benches);
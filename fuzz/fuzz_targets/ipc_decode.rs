@@ -0,0 +1,12 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use transparentd::fuzzing::{decode_cmd, read_frame};
+
+// whatever a client writes to the socket must be rejected without panicking or allocating more
+// than the frame limit
+fuzz_target!(|data: &[u8]| {
+    let mut stream = data;
+    while let Ok(Some(frame)) = read_frame(&mut stream) {
+        let _ = decode_cmd(&frame);
+    }
+});
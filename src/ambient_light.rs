@@ -0,0 +1,128 @@
+//! Polls iio ambient light sensors as found on many laptops.
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    thread,
+    time::Duration,
+};
+
+use crossbeam_channel as chan;
+
+use crate::{config::AmbientLightConfig, sensors::Reading};
+
+const SYSFS_IIO: &str = "/sys/bus/iio/devices";
+
+fn is_light_sensor(device: &Path) -> bool {
+    device.join("in_illuminance_input").exists() || device.join("in_illuminance_raw").exists()
+}
+
+fn find_device(name: Option<&str>) -> io::Result<PathBuf> {
+    if let Some(name) = name {
+        return Ok(Path::new(SYSFS_IIO).join(name));
+    }
+
+    for entry in fs::read_dir(SYSFS_IIO)? {
+        let path = entry?.path();
+        if is_light_sensor(&path) {
+            return Ok(path);
+        }
+    }
+
+    Err(io::Error::new(
+        io::ErrorKind::NotFound,
+        "no ambient light sensor found",
+    ))
+}
+
+fn read_number(path: &Path) -> io::Result<f64> {
+    fs::read_to_string(path)?
+        .trim()
+        .parse()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Illuminance in lux
+fn read_lux(device: &Path) -> io::Result<f64> {
+    if let Ok(lux) = read_number(&device.join("in_illuminance_input")) {
+        return Ok(lux);
+    }
+
+    let raw = read_number(&device.join("in_illuminance_raw"))?;
+    let offset = read_number(&device.join("in_illuminance_offset")).unwrap_or(0.0);
+    let scale = read_number(&device.join("in_illuminance_scale")).unwrap_or(1.0);
+    Ok((raw + offset) * scale)
+}
+
+/// Only report changes bigger than `hysteresis` relative to the last reported value so
+/// flickering light doesn't make the opacity flap.
+fn significant_change(last: f64, lux: f64, hysteresis: f64) -> bool {
+    (lux - last).abs() > last.abs().max(1.0) * hysteresis
+}
+
+pub fn spawn_watcher(config: &AmbientLightConfig, tx: chan::Sender<Reading>) {
+    let device = match find_device(config.device.as_ref().map(String::as_str)) {
+        Ok(device) => device,
+        Err(e) => {
+            log::warn!("Can't find ambient light sensor: {}", e);
+            return;
+        }
+    };
+    let interval = Duration::from_secs(config.interval_secs.max(1));
+    let hysteresis = config.hysteresis.max(0.0);
+    log::debug!("Watching ambient light sensor {}", device.display());
+
+    // FIXME: unjoined thread
+    thread::spawn(move || {
+        let mut last: Option<f64> = None;
+        loop {
+            match read_lux(&device) {
+                Ok(lux) => {
+                    if last.map_or(true, |last| significant_change(last, lux, hysteresis)) {
+                        last = Some(lux);
+                        if tx.send(Reading::AmbientLight(lux)).is_err() {
+                            return;
+                        }
+                    }
+                }
+                Err(e) => log::debug!("Can't read {}: {}", device.display(), e),
+            }
+            thread::sleep(interval);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hysteresis_ignores_small_changes() {
+        assert!(!significant_change(100.0, 115.0, 0.2));
+        assert!(!significant_change(100.0, 85.0, 0.2));
+        assert!(significant_change(100.0, 125.0, 0.2));
+        assert!(significant_change(100.0, 75.0, 0.2));
+        // relative to at least 1 lux so darkness doesn't flap either
+        assert!(!significant_change(0.0, 0.1, 0.2));
+        assert!(significant_change(0.0, 0.5, 0.2));
+    }
+
+    #[test]
+    fn scales_raw_readings() {
+        let device = std::env::temp_dir().join(format!("transparentd-iio-{}", std::process::id()));
+        fs::create_dir_all(&device).unwrap();
+        fs::write(device.join("in_illuminance_raw"), "40\n").unwrap();
+        fs::write(device.join("in_illuminance_offset"), "10\n").unwrap();
+        fs::write(device.join("in_illuminance_scale"), "0.5\n").unwrap();
+        let is_sensor = is_light_sensor(&device);
+        let raw = read_lux(&device).unwrap();
+
+        fs::write(device.join("in_illuminance_input"), "300\n").unwrap();
+        let processed = read_lux(&device).unwrap();
+        let _ = fs::remove_dir_all(&device);
+
+        assert!(is_sensor);
+        assert_eq!(raw, 25.0);
+        assert_eq!(processed, 300.0);
+    }
+}
@@ -0,0 +1,338 @@
+use std::{
+    collections::HashMap,
+    fmt::Write,
+    time::{Duration, Instant},
+};
+
+use snafu::Snafu;
+
+use crate::{opacity::Opacity, status::FailingWindow, wm::WmConnection, x11_opacity::X11Opacity};
+
+#[derive(Snafu, Debug)]
+pub enum ApplyError {
+    #[snafu(display("Can't communicate with i3: {}", source))]
+    I3 { source: i3ipc::MessageError },
+
+    #[snafu(display("Can't set window properties: {}", reason))]
+    X11 { reason: String },
+
+    #[snafu(display("Failed to set opacity of {} windows: {}", failed.len(), reason))]
+    Partial { failed: Vec<i64>, reason: String },
+}
+
+impl From<i3ipc::MessageError> for ApplyError {
+    fn from(source: i3ipc::MessageError) -> Self {
+        ApplyError::I3 { source }
+    }
+}
+
+/// Keeps track of the opacity last applied to each window, windows whose commands fail keep
+/// their last known opacity while the rest of the batch goes through.
+#[derive(Default)]
+pub struct Applier {
+    applied: HashMap<i64, Opacity>,
+    failures: HashMap<i64, Failures>,
+    /// Sets the property directly instead of running opacity commands
+    x11: Option<X11Opacity>,
+    /// X11 window of each con_id, only tracked for the x11 backend
+    x11_windows: HashMap<i64, u32>,
+}
+
+/// Consecutive failures before a window gets skipped
+const MAX_FAILURES: u32 = 3;
+const FAILURE_COOLDOWN: Duration = Duration::from_secs(60);
+
+/// Failed opacity commands of a single window, some clients or surfaces never accept them
+#[derive(Debug)]
+struct Failures {
+    count: u32,
+    /// Failures since the last success, unlike `count` not reset by skipping
+    total: u32,
+    last_error: String,
+    skip_until: Option<Instant>,
+}
+
+impl Failures {
+    fn is_skipped(&self, now: Instant) -> bool {
+        self.skip_until.map_or(false, |until| now < until)
+    }
+}
+
+pub fn opacity_cmd(windows: &[(i64, Opacity)]) -> String {
+    // TODO: should be able to calculate cmd length
+    let mut cmd = String::new();
+    for (id, opacity) in windows {
+        write!(cmd, "[con_id={}] opacity {};", id, opacity).unwrap();
+    }
+    cmd
+}
+
+impl Applier {
+    pub fn use_x11(&mut self, x11: X11Opacity) {
+        self.x11 = Some(x11);
+    }
+
+    pub fn uses_x11(&self) -> bool {
+        self.x11.is_some()
+    }
+
+    /// Forgets everything about the windows, e.g. after a restart of the window manager
+    pub fn reset(&mut self) {
+        self.applied.clear();
+        self.failures.clear();
+        self.x11_windows.clear();
+    }
+
+    /// Updates the X11 windows of con_ids, has to be called with the current windows before
+    /// setting their opacity with the x11 backend
+    pub fn track_x11_windows(&mut self, windows: HashMap<i64, u32>) {
+        self.x11_windows = windows;
+    }
+
+    /// Success or error of each window
+    fn send(
+        &self,
+        i3_conn: &mut WmConnection,
+        windows: &[(i64, Opacity)],
+    ) -> Result<Vec<Result<(), String>>, ApplyError> {
+        let x11 = match self.x11 {
+            Some(ref x11) => x11,
+            None => {
+                let reply = i3_conn.set_opacity(windows)?;
+                return Ok(reply
+                    .outcomes
+                    .into_iter()
+                    .map(|outcome| {
+                        if outcome.success {
+                            Ok(())
+                        } else {
+                            Err(outcome.error.unwrap_or_else(|| "unknown error".to_owned()))
+                        }
+                    })
+                    .collect());
+            }
+        };
+
+        let known: Vec<_> = windows
+            .iter()
+            .filter_map(|(id, opacity)| self.x11_windows.get(id).map(|&window| (window, *opacity)))
+            .collect();
+        let mut outcomes = x11
+            .set_opacity(&known)
+            .map_err(|e| ApplyError::X11 {
+                reason: e.to_string(),
+            })?
+            .into_iter();
+        Ok(windows
+            .iter()
+            .map(|(id, _)| {
+                if self.x11_windows.contains_key(id) {
+                    outcomes.next().unwrap()
+                } else {
+                    Err("not an X11 window".to_owned())
+                }
+            })
+            .collect())
+    }
+
+    pub fn set_opacity<I>(
+        &mut self,
+        i3_conn: &mut WmConnection,
+        windows: I,
+    ) -> Result<(), ApplyError>
+    where
+        I: IntoIterator<Item = (i64, Opacity)>,
+    {
+        let now = Instant::now();
+        let windows: Vec<_> = windows
+            .into_iter()
+            .filter(|(id, _)| !self.is_skipped(*id, now))
+            .collect();
+        if windows.is_empty() {
+            return Ok(());
+        }
+
+        let outcomes = self.send(i3_conn, &windows)?;
+
+        let mut failed = Vec::new();
+        let mut reason = None;
+        for (&(id, opacity), outcome) in windows.iter().zip(outcomes) {
+            match outcome {
+                Ok(()) => {
+                    self.failures.remove(&id);
+                    self.applied.insert(id, opacity);
+                }
+                Err(e) => {
+                    log::warn!("[con_id={}] opacity {} failed: {}", id, opacity, e);
+                    if reason.is_none() {
+                        reason = Some(format!("con_id {}: {}", id, e));
+                    }
+                    self.record_failure(id, now, e);
+                    failed.push(id);
+                }
+            }
+        }
+
+        match reason {
+            None => Ok(()),
+            Some(reason) => Err(ApplyError::Partial { failed, reason }),
+        }
+    }
+
+    /// Opacity the window had before transparentd touched it, i3 doesn't report it so unknown
+    /// windows are assumed to be opaque.
+    pub fn previous(&self, id: i64) -> Opacity {
+        self.applied.get(&id).cloned().unwrap_or_else(Opacity::max)
+    }
+
+    fn record_failure(&mut self, id: i64, now: Instant, error: String) {
+        let failures = self.failures.entry(id).or_insert(Failures {
+            count: 0,
+            total: 0,
+            last_error: String::new(),
+            skip_until: None,
+        });
+        failures.count += 1;
+        failures.total += 1;
+        failures.last_error = error;
+        if failures.count >= MAX_FAILURES {
+            log::warn!(
+                "Setting opacity of con_id {} failed {} times, skipping it for {}s",
+                id,
+                failures.count,
+                FAILURE_COOLDOWN.as_secs()
+            );
+            // retried once after the cooldown, back to skipping right away if it fails again
+            failures.count = MAX_FAILURES - 1;
+            failures.skip_until = Some(now + FAILURE_COOLDOWN);
+        }
+    }
+
+    fn is_skipped(&self, id: i64, now: Instant) -> bool {
+        self.failures
+            .get(&id)
+            .map_or(false, |failures| failures.is_skipped(now))
+    }
+
+    /// Windows currently skipped because their opacity commands keep failing
+    pub fn skipped(&self) -> Vec<i64> {
+        let now = Instant::now();
+        let mut skipped: Vec<_> = self
+            .failures
+            .iter()
+            .filter(|(_, failures)| failures.is_skipped(now))
+            .map(|(&id, _)| id)
+            .collect();
+        skipped.sort();
+        skipped
+    }
+
+    /// Windows whose last opacity command failed
+    pub fn failing(&self) -> Vec<FailingWindow> {
+        let mut failing: Vec<_> = self
+            .failures
+            .iter()
+            .map(|(&id, failures)| FailingWindow {
+                id,
+                failures: failures.total,
+                error: failures.last_error.clone(),
+            })
+            .collect();
+        failing.sort_by_key(|window| window.id);
+        failing
+    }
+
+    pub fn forget(&mut self, id: i64) {
+        self.applied.remove(&id);
+        self.failures.remove(&id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        fake_wm::{Batches, FakeWm},
+        i3::test_root,
+    };
+
+    /// Connection whose opacity commands fail for the `failing` windows
+    fn sender(failing: &[i64]) -> (WmConnection, Batches) {
+        let wm = FakeWm {
+            failing: failing.iter().cloned().collect(),
+            ..FakeWm::new(test_root(vec![]))
+        };
+        let batches = wm.batches.clone();
+        (Box::new(wm), batches)
+    }
+
+    fn half() -> Opacity {
+        Opacity::new(0.5).unwrap()
+    }
+
+    #[test]
+    fn applies_every_window() {
+        let (mut conn, batches) = sender(&[]);
+        let mut applier = Applier::default();
+        applier
+            .set_opacity(&mut conn, vec![(1, half()), (2, Opacity::max())])
+            .unwrap();
+        assert_eq!(batches.borrow().len(), 1);
+        assert_eq!(applier.previous(1), half());
+        assert_eq!(applier.previous(2), Opacity::max());
+        assert!(applier.failing().is_empty());
+    }
+
+    #[test]
+    fn keeps_successes_of_partial_failure() {
+        let (mut conn, batches) = sender(&[3]);
+        let mut applier = Applier::default();
+        let windows = vec![(1, half()), (2, half()), (3, half())];
+        match applier.set_opacity(&mut conn, windows) {
+            Err(ApplyError::Partial { ref failed, .. }) if *failed == vec![3] => {}
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(batches.borrow().len(), 1);
+        assert_eq!(applier.previous(1), half());
+        assert_eq!(applier.previous(2), half());
+        assert_eq!(applier.previous(3), Opacity::max());
+        assert_eq!(applier.failing()[0].id, 3);
+    }
+
+    #[test]
+    fn leaves_out_skipped_windows() {
+        let (mut conn, batches) = sender(&[3]);
+        let mut applier = Applier::default();
+        for _ in 0..MAX_FAILURES {
+            let _ = applier.set_opacity(&mut conn, vec![(3, half())]);
+        }
+        assert_eq!(applier.skipped(), vec![3]);
+
+        let windows = vec![(1, half()), (2, half()), (3, half())];
+        applier.set_opacity(&mut conn, windows).unwrap();
+        let batches = batches.borrow();
+        assert_eq!(batches.len(), MAX_FAILURES as usize + 1);
+        assert_eq!(batches.last().unwrap(), &vec![(1, half()), (2, half())]);
+    }
+
+    #[test]
+    fn reports_failing_windows_until_they_succeed() {
+        let (mut failing_conn, _) = sender(&[3]);
+        let mut applier = Applier::default();
+        for _ in 0..2 {
+            let _ = applier.set_opacity(&mut failing_conn, vec![(3, half())]);
+        }
+        assert_eq!(
+            applier.failing(),
+            vec![FailingWindow {
+                id: 3,
+                failures: 2,
+                error: "No matching node".to_owned(),
+            }]
+        );
+
+        let (mut conn, _) = sender(&[]);
+        applier.set_opacity(&mut conn, vec![(3, half())]).unwrap();
+        assert!(applier.failing().is_empty());
+    }
+}
@@ -0,0 +1,103 @@
+//! Polls the display backlight in sysfs, the kernel doesn't send inotify events for it.
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    thread,
+    time::Duration,
+};
+
+use crossbeam_channel as chan;
+
+use crate::{config::BacklightConfig, sensors::Reading};
+
+const SYSFS_BACKLIGHT: &str = "/sys/class/backlight";
+
+/// Changes smaller than this don't get reported
+const MIN_CHANGE: f64 = 0.01;
+
+fn find_device(name: Option<&str>) -> io::Result<PathBuf> {
+    match name {
+        Some(name) => Ok(Path::new(SYSFS_BACKLIGHT).join(name)),
+        None => fs::read_dir(SYSFS_BACKLIGHT)?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no backlight device found"))?
+            .map(|entry| entry.path()),
+    }
+}
+
+fn read_number(path: &Path) -> io::Result<f64> {
+    fs::read_to_string(path)?
+        .trim()
+        .parse()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+fn read_brightness(device: &Path) -> io::Result<f64> {
+    let max = read_number(&device.join("max_brightness"))?;
+    let current = read_number(&device.join("actual_brightness"))
+        .or_else(|_| read_number(&device.join("brightness")))?;
+    if max > 0.0 {
+        Ok((current / max).max(0.0).min(1.0))
+    } else {
+        Ok(1.0)
+    }
+}
+
+pub fn spawn_watcher(config: &BacklightConfig, tx: chan::Sender<Reading>) {
+    let device = match find_device(config.device.as_ref().map(String::as_str)) {
+        Ok(device) => device,
+        Err(e) => {
+            log::warn!("Can't find backlight device: {}", e);
+            return;
+        }
+    };
+    let interval = Duration::from_secs(config.interval_secs.max(1));
+    log::debug!("Watching backlight {}", device.display());
+
+    // FIXME: unjoined thread
+    thread::spawn(move || {
+        let mut last: Option<f64> = None;
+        loop {
+            match read_brightness(&device) {
+                Ok(level) => {
+                    if last.map_or(true, |last| (last - level).abs() >= MIN_CHANGE) {
+                        last = Some(level);
+                        if tx.send(Reading::Backlight(level)).is_err() {
+                            return;
+                        }
+                    }
+                }
+                Err(e) => log::debug!("Can't read backlight {}: {}", device.display(), e),
+            }
+            thread::sleep(interval);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_relative_brightness() {
+        let device =
+            std::env::temp_dir().join(format!("transparentd-backlight-{}", std::process::id()));
+        fs::create_dir_all(&device).unwrap();
+        fs::write(device.join("max_brightness"), "1000\n").unwrap();
+        // some drivers have no actual_brightness
+        fs::write(device.join("brightness"), "250\n").unwrap();
+        let brightness = read_brightness(&device).unwrap();
+
+        fs::write(device.join("actual_brightness"), "1200\n").unwrap();
+        let clamped = read_brightness(&device).unwrap();
+
+        fs::write(device.join("max_brightness"), "0\n").unwrap();
+        let without_max = read_brightness(&device).unwrap();
+        let _ = fs::remove_dir_all(&device);
+
+        assert_eq!(brightness, 0.25);
+        assert_eq!(clamped, 1.0);
+        assert_eq!(without_max, 1.0);
+    }
+}
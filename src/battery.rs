@@ -0,0 +1,76 @@
+//! Polls the battery charge through sysfs.
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    thread,
+    time::Duration,
+};
+
+use crossbeam_channel as chan;
+
+use crate::{config::BatteryConfig, sensors::Reading};
+
+const SYSFS_POWER_SUPPLY: &str = "/sys/class/power_supply";
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct BatteryState {
+    pub percent: u8,
+    pub discharging: bool,
+}
+
+fn read_attr(device: &Path, attr: &str) -> io::Result<String> {
+    Ok(fs::read_to_string(device.join(attr))?.trim().to_owned())
+}
+
+fn find_battery() -> io::Result<PathBuf> {
+    for entry in fs::read_dir(SYSFS_POWER_SUPPLY)? {
+        let path = entry?.path();
+        if read_attr(&path, "type").map_or(false, |kind| kind == "Battery") {
+            return Ok(path);
+        }
+    }
+
+    Err(io::Error::new(io::ErrorKind::NotFound, "no battery found"))
+}
+
+fn read_state(device: &Path) -> io::Result<BatteryState> {
+    let percent = read_attr(device, "capacity")?
+        .parse::<u8>()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let discharging = read_attr(device, "status")? == "Discharging";
+    Ok(BatteryState {
+        percent,
+        discharging,
+    })
+}
+
+pub fn spawn_watcher(config: &BatteryConfig, tx: chan::Sender<Reading>) {
+    let device = match find_battery() {
+        Ok(device) => device,
+        Err(e) => {
+            log::warn!("Can't watch battery: {}", e);
+            return;
+        }
+    };
+    let interval = Duration::from_secs(config.interval_secs.max(1));
+    log::debug!("Watching battery {}", device.display());
+
+    // FIXME: unjoined thread
+    thread::spawn(move || {
+        let mut last = None;
+        loop {
+            match read_state(&device) {
+                Ok(state) if last != Some(state) => {
+                    last = Some(state);
+                    if tx.send(Reading::Battery(state)).is_err() {
+                        return;
+                    }
+                }
+                Ok(_) => {}
+                Err(e) => log::debug!("Can't read {}: {}", device.display(), e),
+            }
+            thread::sleep(interval);
+        }
+    });
+}
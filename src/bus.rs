@@ -0,0 +1,300 @@
+//! Control interface on the session bus for tools that would rather not shell out to the cli.
+//!
+//! The daemon owns `org.foldu.transparentd` and serves `/org/foldu/transparentd` with the methods
+//! `Enable`, `Disable`, `Toggle`, `SetOpacity(d)`, `OpacityUp` and `OpacityDown` as well as the
+//! read only properties `State` and `Opacity`.
+
+use crossbeam_channel as chan;
+
+use crate::{workers::Workers, IpcRequest};
+
+#[cfg(feature = "dbus")]
+pub fn spawn(workers: &mut Workers, tx: chan::Sender<IpcRequest>) {
+    workers.spawn("dbus", move |cancelled| {
+        if let Err(e) = service::serve(&tx, &cancelled) {
+            log::warn!("Can't serve on the session bus: {}", e);
+        }
+    });
+    workers.on_stop(service::wake);
+}
+
+#[cfg(not(feature = "dbus"))]
+pub fn spawn(_: &mut Workers, _: chan::Sender<IpcRequest>) {
+    log::debug!("Built without the dbus feature, not serving on the session bus");
+}
+
+#[cfg(feature = "dbus")]
+mod service {
+    use std::{collections::HashMap, ffi::CString, time::Duration};
+
+    use crossbeam_channel as chan;
+    use dbus::{
+        arg::{RefArg, Variant},
+        blocking::Connection,
+        channel::{MatchingReceiver, Sender},
+        message::MatchRule,
+        strings::ErrorName,
+        Message,
+    };
+
+    use crate::{ipc::Response, opacity::Opacity, workers::Cancelled, Cmd, IpcRequest};
+
+    const NAME: &str = "org.foldu.transparentd";
+    const PATH: &str = "/org/foldu/transparentd";
+    const INTERFACE: &str = "org.foldu.transparentd";
+    const PROPERTIES: &str = "org.freedesktop.DBus.Properties";
+    const INTROSPECTABLE: &str = "org.freedesktop.DBus.Introspectable";
+
+    const INTROSPECTION: &str = r#"<!DOCTYPE node PUBLIC "-//freedesktop//DTD D-BUS Object Introspection 1.0//EN"
+ "http://www.freedesktop.org/standards/dbus/1.0/introspect.dtd">
+<node>
+  <interface name="org.foldu.transparentd">
+    <method name="Enable"/>
+    <method name="Disable"/>
+    <method name="Toggle"/>
+    <method name="SetOpacity">
+      <arg name="opacity" type="d" direction="in"/>
+    </method>
+    <method name="OpacityUp"/>
+    <method name="OpacityDown"/>
+    <property name="State" type="s" access="read"/>
+    <property name="Opacity" type="d" access="read"/>
+  </interface>
+  <interface name="org.freedesktop.DBus.Properties">
+    <method name="Get">
+      <arg name="interface" type="s" direction="in"/>
+      <arg name="name" type="s" direction="in"/>
+      <arg name="value" type="v" direction="out"/>
+    </method>
+    <method name="GetAll">
+      <arg name="interface" type="s" direction="in"/>
+      <arg name="values" type="a{sv}" direction="out"/>
+    </method>
+  </interface>
+  <interface name="org.freedesktop.DBus.Introspectable">
+    <method name="Introspect">
+      <arg name="xml" type="s" direction="out"/>
+    </method>
+  </interface>
+</node>
+"#;
+
+    pub fn serve(tx: &chan::Sender<IpcRequest>, cancelled: &Cancelled) -> Result<(), dbus::Error> {
+        let conn = Connection::new_session()?;
+        conn.request_name(NAME, false, true, true)?;
+
+        let tx = tx.clone();
+        conn.start_receive(
+            MatchRule::new_method_call(),
+            Box::new(move |msg, conn| {
+                if msg.path().map_or(false, |path| &*path == PATH) {
+                    let reply = handle(&msg, &tx);
+                    let _ = conn.send(reply);
+                }
+                true
+            }),
+        );
+
+        while !cancelled.is_cancelled() {
+            conn.process(Duration::from_secs(3600))?;
+        }
+
+        Ok(())
+    }
+
+    /// Pings the service so `serve` notices it should stop
+    pub fn wake() {
+        if let Ok(conn) = Connection::new_session() {
+            let ping = Message::new_method_call(NAME, PATH, "org.freedesktop.DBus.Peer", "Ping");
+            if let Ok(ping) = ping {
+                let _ = conn.send(ping);
+            }
+        }
+    }
+
+    fn error(msg: &Message, name: &'static str, text: &str) -> Message {
+        let text = CString::new(text.replace('\0', "")).unwrap();
+        msg.error(&ErrorName::from(name), &text)
+    }
+
+    fn failed(msg: &Message, text: &str) -> Message {
+        error(msg, "org.freedesktop.DBus.Error.Failed", text)
+    }
+
+    fn request(tx: &chan::Sender<IpcRequest>, cmd: Cmd) -> Response {
+        let (reply_tx, reply_rx) = chan::bounded(1);
+        if tx.send((cmd, reply_tx)).is_err() {
+            return Response::Err("daemon stopped".to_owned());
+        }
+        reply_rx
+            .recv()
+            .unwrap_or_else(|_| Response::Err("daemon stopped".to_owned()))
+    }
+
+    fn properties(
+        msg: &Message,
+        tx: &chan::Sender<IpcRequest>,
+    ) -> Result<HashMap<&'static str, Variant<Box<dyn RefArg>>>, Message> {
+        match request(tx, Cmd::Status { json: false }) {
+            Response::Status(status) => {
+                let mut properties = HashMap::new();
+                properties.insert("State", Variant(Box::new(status.state) as Box<dyn RefArg>));
+                properties.insert(
+                    "Opacity",
+                    Variant(Box::new(status.opacity.value()) as Box<dyn RefArg>),
+                );
+                Ok(properties)
+            }
+            Response::Err(e) => Err(failed(msg, &e)),
+            Response::Ok | Response::Answer(_) => Err(failed(msg, "no status received")),
+        }
+    }
+
+    fn handle(msg: &Message, tx: &chan::Sender<IpcRequest>) -> Message {
+        let interface = msg.interface();
+        let member = msg.member();
+        let (interface, member) = match (interface.as_deref(), member.as_deref()) {
+            (Some(interface), Some(member)) => (interface, member),
+            _ => return error(msg, "org.freedesktop.DBus.Error.UnknownMethod", "no method"),
+        };
+
+        let cmd = match (interface, member) {
+            (INTROSPECTABLE, "Introspect") => return msg.method_reply().append1(INTROSPECTION),
+            ("org.freedesktop.DBus.Peer", "Ping") => return msg.method_reply(),
+            (PROPERTIES, "Get") => {
+                return match msg.read2::<&str, &str>() {
+                    Ok((INTERFACE, name)) => match properties(msg, tx) {
+                        Ok(mut properties) => match properties.remove(name) {
+                            Some(value) => msg.method_reply().append1(value),
+                            None => error(
+                                msg,
+                                "org.freedesktop.DBus.Error.UnknownProperty",
+                                &format!("no property {}", name),
+                            ),
+                        },
+                        Err(e) => e,
+                    },
+                    Ok((interface, _)) => error(
+                        msg,
+                        "org.freedesktop.DBus.Error.UnknownInterface",
+                        &format!("no interface {}", interface),
+                    ),
+                    Err(e) => error(
+                        msg,
+                        "org.freedesktop.DBus.Error.InvalidArgs",
+                        &e.to_string(),
+                    ),
+                };
+            }
+            (PROPERTIES, "GetAll") => {
+                return match properties(msg, tx) {
+                    Ok(properties) => msg.method_reply().append1(properties),
+                    Err(e) => e,
+                };
+            }
+            (INTERFACE, "Enable") => Cmd::Enable,
+            (INTERFACE, "Disable") => Cmd::Disable,
+            (INTERFACE, "Toggle") => Cmd::Toggle,
+            (INTERFACE, "OpacityUp") => Cmd::OpacityUp,
+            (INTERFACE, "OpacityDown") => Cmd::OpacityDown,
+            (INTERFACE, "SetOpacity") => match msg.read1::<f64>().ok().and_then(Opacity::new) {
+                Some(opacity) => Cmd::SetOpacity { opacity },
+                None => {
+                    return error(
+                        msg,
+                        "org.freedesktop.DBus.Error.InvalidArgs",
+                        "expected an opacity between 0.0 and 1.0",
+                    )
+                }
+            },
+            _ => {
+                return error(
+                    msg,
+                    "org.freedesktop.DBus.Error.UnknownMethod",
+                    &format!("no method {}.{}", interface, member),
+                )
+            }
+        };
+
+        match request(tx, cmd) {
+            Response::Err(e) => failed(msg, &e),
+            _ => msg.method_reply(),
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use dbus::message::MessageType;
+
+        use super::*;
+        use crate::status::Status;
+
+        /// Answers requests like the daemon would and returns what it was asked to do
+        fn daemon() -> (
+            chan::Sender<IpcRequest>,
+            std::thread::JoinHandle<Vec<String>>,
+        ) {
+            let (tx, rx) = chan::unbounded::<IpcRequest>();
+            let handle = std::thread::spawn(move || {
+                rx.iter()
+                    .map(|(cmd, reply)| {
+                        let response = match cmd {
+                            Cmd::Status { .. } => Response::Status(Status {
+                                state: "enabled".to_owned(),
+                                pause_reason: None,
+                                opacity: Opacity::new(0.8).unwrap(),
+                                degraded: false,
+                                blacklisted: vec![],
+                                skipped: vec![],
+                                failing: vec![],
+                            }),
+                            _ => Response::Ok,
+                        };
+                        let _ = reply.send(response);
+                        format!("{:?}", cmd)
+                    })
+                    .collect()
+            });
+            (tx, handle)
+        }
+
+        fn call(interface: &str, member: &str) -> Message {
+            Message::new_method_call(NAME, PATH, interface, member).unwrap()
+        }
+
+        #[test]
+        fn maps_methods_to_commands() {
+            let (tx, daemon) = daemon();
+            let reply = handle(&call(INTERFACE, "Toggle"), &tx);
+            assert_eq!(reply.msg_type(), MessageType::MethodReturn);
+            let reply = handle(&call(INTERFACE, "SetOpacity").append1(0.5), &tx);
+            assert_eq!(reply.msg_type(), MessageType::MethodReturn);
+
+            // never reaches the daemon
+            let reply = handle(&call(INTERFACE, "SetOpacity").append1(1.5), &tx);
+            assert_eq!(reply.msg_type(), MessageType::Error);
+            let reply = handle(&call(INTERFACE, "Explode"), &tx);
+            assert_eq!(reply.msg_type(), MessageType::Error);
+
+            drop(tx);
+            assert_eq!(
+                daemon.join().unwrap(),
+                vec!["Toggle", "SetOpacity { opacity: Opacity(0.5) }"]
+            );
+        }
+
+        #[test]
+        fn reads_properties_from_status() {
+            let (tx, daemon) = daemon();
+            let get = call(PROPERTIES, "Get").append2(INTERFACE, "State");
+            let reply = handle(&get, &tx);
+            let state: Variant<String> = reply.read1().unwrap();
+            assert_eq!(state.0, "enabled");
+
+            let get = call(PROPERTIES, "Get").append2(INTERFACE, "Missing");
+            assert_eq!(handle(&get, &tx).msg_type(), MessageType::Error);
+            drop(tx);
+            daemon.join().unwrap();
+        }
+    }
+}
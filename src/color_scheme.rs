@@ -0,0 +1,104 @@
+//! Follows the desktop wide dark/light preference of the XDG settings portal.
+
+use crossbeam_channel as chan;
+
+use crate::sensors::Reading;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ColorScheme {
+    NoPreference,
+    Dark,
+    Light,
+}
+
+impl ColorScheme {
+    fn from_portal(value: u64) -> Self {
+        match value {
+            1 => ColorScheme::Dark,
+            2 => ColorScheme::Light,
+            _ => ColorScheme::NoPreference,
+        }
+    }
+
+    /// Name of the profile that gets activated for this scheme
+    pub fn profile(self) -> Option<&'static str> {
+        match self {
+            ColorScheme::Dark => Some("dark"),
+            ColorScheme::Light => Some("light"),
+            ColorScheme::NoPreference => None,
+        }
+    }
+}
+
+#[cfg(feature = "dbus")]
+pub fn spawn_watcher(tx: chan::Sender<Reading>) {
+    // FIXME: unjoined thread
+    std::thread::spawn(move || {
+        if let Err(e) = portal::watch(tx) {
+            log::warn!("Can't watch color scheme: {}", e);
+        }
+    });
+}
+
+#[cfg(not(feature = "dbus"))]
+pub fn spawn_watcher(_: chan::Sender<Reading>) {
+    log::warn!("transparentd was built without the dbus feature, can't follow the color scheme");
+}
+
+#[cfg(feature = "dbus")]
+mod portal {
+    use std::time::Duration;
+
+    use crossbeam_channel as chan;
+    use dbus::{
+        arg::{RefArg, Variant},
+        blocking::Connection,
+        message::MatchRule,
+    };
+
+    use super::ColorScheme;
+    use crate::sensors::Reading;
+
+    const NAMESPACE: &str = "org.freedesktop.appearance";
+    const KEY: &str = "color-scheme";
+    const SETTINGS: &str = "org.freedesktop.portal.Settings";
+
+    /// The portal wraps values in (sometimes nested) variants
+    fn scheme_value(arg: &dyn RefArg) -> Option<u64> {
+        arg.as_u64()
+            .or_else(|| arg.as_iter()?.next().and_then(scheme_value))
+    }
+
+    pub fn watch(tx: chan::Sender<Reading>) -> Result<(), dbus::Error> {
+        let conn = Connection::new_session()?;
+        let proxy = conn.with_proxy(
+            "org.freedesktop.portal.Desktop",
+            "/org/freedesktop/portal/desktop",
+            Duration::from_secs(5),
+        );
+
+        let (initial,): (Variant<Box<dyn RefArg>>,) =
+            proxy.method_call(SETTINGS, "Read", (NAMESPACE, KEY))?;
+        if let Some(value) = scheme_value(&initial) {
+            let _ = tx.send(Reading::ColorScheme(ColorScheme::from_portal(value)));
+        }
+
+        conn.add_match(
+            MatchRule::new_signal(SETTINGS, "SettingChanged"),
+            move |(namespace, key, value): (String, String, Variant<Box<dyn RefArg>>), _, _| {
+                if namespace == NAMESPACE && key == KEY {
+                    if let Some(value) = scheme_value(&value) {
+                        // the daemon hung up, stop listening
+                        let scheme = ColorScheme::from_portal(value);
+                        return tx.send(Reading::ColorScheme(scheme)).is_ok();
+                    }
+                }
+                true
+            },
+        )?;
+
+        loop {
+            conn.process(Duration::from_secs(3600))?;
+        }
+    }
+}
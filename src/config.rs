@@ -1,68 +1,713 @@
-use cfgen::prelude::*;
-use serde::de::{Deserialize, Deserializer, Visitor};
-use serde_derive::Deserialize;
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+};
 
-#[derive(Debug, Copy, Clone)]
-pub struct Opacity(f64);
+use cfgen::{prelude::*, ConfigLoad};
+use serde_derive::{Deserialize, Serialize};
+use snafu::{ResultExt, Snafu};
 
-impl Opacity {
-    pub fn new(opacity: f64) -> Option<Self> {
-        if opacity >= 0.0 && opacity <= 1.0 {
-            Some(Self(opacity))
+use crate::{hooks::Hooks, opacity::Opacity};
+
+const DEFAULT: &str = "\
+transparency_at_start = true
+opacity = 0.8
+focused_opacity = 1.0
+# unfocused windows on workspaces shown on other outputs, `opacity` when unset
+# other_workspace_opacity = 0.6
+# unfocused windows demanding attention
+# urgent_opacity = 1.0
+# unfocused floating windows, e.g. dialogs and dropdown terminals
+# floating_opacity = 0.9
+opacity_step = 0.05
+min_opacity = 0.0
+max_opacity = 1.0
+# while a window is fullscreen: 'off' keeps dimming, 'pause' restores every window and
+# 'suspend' does too but sends no opacity commands at all until the window leaves fullscreen
+fullscreen = 'off'
+# only dim windows on workspaces with at least this many windows
+min_windows = 0
+# keep this many of the most recently focused windows opaque, including the focused one
+keep_recent_opaque = 0
+# wait this long after a focus change for more before applying, 0 applies right away
+focus_debounce_ms = 0
+# cap on opacity commands sent to the window manager per second, 0 doesn't limit
+max_commands_per_sec = 0
+# whether floating windows get dimmed at all
+dim_floating = true
+# windows carrying this i3/sway mark stay opaque, e.g. after `mark --add opaque`
+# exempt_mark = 'opaque'
+# window classes that are never dimmed, in addition to windows excluded with focus-blacklist
+blacklist = []
+# keep everything opaque while one of these processes runs
+pause_for_processes = []
+process_poll_secs = 5
+# how often to check the config for changes where inotify isn't available, read at startup
+config_poll_secs = 5
+# rhai script defining `fn opacity(window, ctx)`, needs the scripting feature
+# script = 'opacity.rhai'
+# also accept commands on 127.0.0.1:<port>, e.g. from containers, every local user can connect
+# tcp_port = 7383
+# serve GET /status and POST /commands/<name> as JSON on 127.0.0.1:<port>
+# http_port = 7384
+# how opacities get set, 'wm' runs opacity commands through the window manager's ipc,
+# 'x11' sets _NET_WM_WINDOW_OPACITY for compositors like picom, needs the x11 feature
+opacity_backend = 'wm'
+# switch to the dark/light profile with the desktop color scheme, needs the dbus feature
+follow_color_scheme = false
+
+# fade between opacities instead of jumping
+# [fade]
+# duration_ms = 150
+# steps = 6
+
+# fully reveal the window under the resting pointer, needs the x11 feature
+# [hover]
+# delay_ms = 500
+
+# lower every window to the idle opacity after a while without input, needs the x11 feature
+# on wayland run `transparentd idle` and `transparentd active` from swayidle instead
+# [idle]
+# after_secs = 300
+# opacity = 0.5
+
+# grade unfocused windows by how recently they were focused, the last focused one gets
+# `start` and every one before it `step` less, down to the opacity they'd get otherwise
+# [recency]
+# start = 0.9
+# step = 0.1
+
+# lessen the dimming when the display backlight is turned down
+# [backlight]
+# device = 'intel_backlight'
+# min_scale = 0.25
+# interval_secs = 5
+
+# pick the opacity from an ambient light sensor, interpolated between dark and bright
+# [ambient_light]
+# dark_opacity = 0.95
+# bright_opacity = 0.75
+# dark_lux = 10.0
+# bright_lux = 1000.0
+# hysteresis = 0.2
+
+# ease between the night and day profiles following the sun
+# [solar]
+# latitude = 52.5
+# longitude = 13.4
+
+# behavior while running on battery, the tier with the lowest matching threshold wins
+# [battery]
+# interval_secs = 30
+# [[battery.tiers]]
+# below = 30
+# disable = true
+# [[battery.tiers]]
+# below = 15
+# disable = true
+# suspend_events = true
+
+# opacity per application, the first matching rule wins
+# unset opacities fall back to opacity and focused_opacity
+# [[rule]]
+# class = 'mpv'
+# unfocused = 1.0
+# [[rule]]
+# class = 'Alacritty'
+# focused = 0.95
+# unfocused = 0.7
+# [[rule]]
+# match_title = '^Picture-in-Picture$'
+# unfocused = 1.0
+# [[rule]]
+# workspace = 'video'
+# unfocused = 1.0
+# [[rule]]
+# output = 'eDP-1'
+# unfocused = 0.6
+
+# named opacity presets
+# [profiles.dark]
+# opacity = 0.85
+# [profiles.light]
+# opacity = 0.7
+
+# shell commands run on state changes
+[hooks]
+# on_enable = 'notify-send transparentd enabled'
+# on_disable = 'notify-send transparentd disabled'
+# on_profile_change = 'notify-send transparentd \"opacity $TRANSPARENTD_OPACITY\"'
+# on_apply_error = 'notify-send transparentd \"$TRANSPARENTD_ERROR\"'
+";
+
+#[derive(Cfgen, Deserialize, Serialize, Debug)]
+#[cfgen(default = "DEFAULT")]
+pub struct Config {
+    pub transparency_at_start: bool,
+    pub opacity: Opacity,
+    /// Opacity of the focused window
+    #[serde(default = "Opacity::max")]
+    pub focused_opacity: Opacity,
+    /// Opacity of unfocused windows on workspaces shown on other outputs
+    pub other_workspace_opacity: Option<Opacity>,
+    /// Opacity of unfocused urgent windows
+    pub urgent_opacity: Option<Opacity>,
+    /// Opacity of unfocused floating windows
+    pub floating_opacity: Option<Opacity>,
+    /// Increment used when stepping the opacity up or down
+    #[serde(default = "default_opacity_step")]
+    pub opacity_step: f64,
+    /// Lowest opacity transparentd will ever set
+    #[serde(default = "Opacity::min")]
+    pub min_opacity: Opacity,
+    /// Highest opacity transparentd will ever set for unfocused windows
+    #[serde(default = "Opacity::max")]
+    pub max_opacity: Opacity,
+    /// Windows a workspace needs for its windows to get dimmed
+    #[serde(default)]
+    pub min_windows: usize,
+    /// Most recently focused windows shown like the focused one, counting it
+    #[serde(default)]
+    pub keep_recent_opaque: usize,
+    /// Coalesces bursts of focus changes, e.g. while alt-tabbing
+    #[serde(default)]
+    pub focus_debounce_ms: u64,
+    /// Opacity commands per second, 0 for no limit
+    #[serde(default)]
+    pub max_commands_per_sec: u32,
+    /// Floating windows are left alone when disabled
+    #[serde(default = "default_dim_floating")]
+    pub dim_floating: bool,
+    /// What happens while any window is fullscreen
+    #[serde(default)]
+    pub fullscreen: Fullscreen,
+    /// Classes of windows that never get dimmed
+    #[serde(default)]
+    pub blacklist: Vec<String>,
+    /// Mark exempting windows from dimming
+    pub exempt_mark: Option<String>,
+    /// Process names that pause dimming while running
+    #[serde(default)]
+    pub pause_for_processes: Vec<String>,
+    #[serde(default = "default_process_poll")]
+    pub process_poll_secs: u64,
+    /// Interval of checking the config's mtime without inotify, read at startup
+    #[serde(default = "default_config_poll")]
+    pub config_poll_secs: u64,
+    /// Path to a script deciding per window opacity, relative to the config dir
+    pub script: Option<String>,
+    /// Loopback port accepting commands in addition to the unix socket, read at startup
+    pub tcp_port: Option<u16>,
+    /// Loopback port of the HTTP endpoint, read at startup
+    pub http_port: Option<u16>,
+    /// Read at startup
+    #[serde(default)]
+    pub opacity_backend: OpacityBackend,
+    /// Activate the `dark` or `light` profile following the desktop color scheme
+    #[serde(default)]
+    pub follow_color_scheme: bool,
+    #[serde(default)]
+    pub profiles: HashMap<String, Profile>,
+    #[serde(default, rename = "rule")]
+    pub rules: Vec<RuleConfig>,
+    pub backlight: Option<BacklightConfig>,
+    pub ambient_light: Option<AmbientLightConfig>,
+    pub solar: Option<SolarConfig>,
+    pub battery: Option<BatteryConfig>,
+    pub hover: Option<HoverConfig>,
+    pub idle: Option<IdleConfig>,
+    pub recency: Option<RecencyConfig>,
+    pub fade: Option<FadeConfig>,
+    #[serde(default)]
+    pub hooks: Hooks,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum OpacityBackend {
+    /// Opacity commands sent through i3 or sway ipc
+    Wm,
+    /// `_NET_WM_WINDOW_OPACITY` set on the client windows
+    X11,
+}
+
+impl Default for OpacityBackend {
+    fn default() -> Self {
+        OpacityBackend::Wm
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Fullscreen {
+    /// Fullscreen windows change nothing
+    Off,
+    /// Restore all windows to full opacity
+    Pause,
+    /// Restore all windows and stop sending opacity commands until no window is fullscreen
+    Suspend,
+}
+
+impl Default for Fullscreen {
+    fn default() -> Self {
+        Fullscreen::Off
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Profile {
+    pub opacity: Opacity,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct RuleConfig {
+    /// X11 class, or app_id of native wayland clients on sway
+    pub class: Option<String>,
+    pub instance: Option<String>,
+    /// Regex searched for in the window title
+    pub match_title: Option<String>,
+    /// Workspace name or number
+    pub workspace: Option<String>,
+    /// Output name like `eDP-1`
+    pub output: Option<String>,
+    /// Opacity of matching windows while unfocused, the global opacity when unset
+    #[serde(alias = "opacity")]
+    pub unfocused: Option<Opacity>,
+    /// Opacity of matching windows while focused, `focused_opacity` when unset
+    pub focused: Option<Opacity>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct HoverConfig {
+    /// How long the pointer has to rest on a window before it gets revealed
+    #[serde(default = "default_hover_delay")]
+    pub delay_ms: u64,
+    #[serde(default = "default_hover_poll")]
+    pub poll_ms: u64,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct IdleConfig {
+    /// Seconds without input until the session counts as idle
+    #[serde(default = "default_idle_after")]
+    pub after_secs: u64,
+    /// Opacity of every window, focused or not, while idle
+    #[serde(default = "default_idle_opacity")]
+    pub opacity: Opacity,
+    #[serde(default = "default_idle_poll")]
+    pub poll_ms: u64,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct RecencyConfig {
+    /// Opacity of the most recently focused unfocused window
+    pub start: Opacity,
+    /// How much less opaque each window focused before that gets
+    #[serde(default = "default_recency_step")]
+    pub step: f64,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct FadeConfig {
+    #[serde(default = "default_fade_duration")]
+    pub duration_ms: u64,
+    /// Opacity commands sent per fade, fewer than 2 turns fading off
+    #[serde(default = "default_fade_steps")]
+    pub steps: u32,
+}
+
+fn default_recency_step() -> f64 {
+    0.1
+}
+
+fn default_fade_duration() -> u64 {
+    150
+}
+
+fn default_fade_steps() -> u32 {
+    6
+}
+
+fn default_idle_after() -> u64 {
+    300
+}
+
+fn default_idle_opacity() -> Opacity {
+    Opacity::new(0.5).unwrap()
+}
+
+fn default_idle_poll() -> u64 {
+    1000
+}
+
+fn default_hover_delay() -> u64 {
+    500
+}
+
+fn default_hover_poll() -> u64 {
+    100
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct BacklightConfig {
+    /// Device in /sys/class/backlight, the first one found gets used when unset
+    pub device: Option<String>,
+    /// Fraction of the dimming that's left at zero brightness
+    #[serde(default = "default_min_scale")]
+    pub min_scale: f64,
+    #[serde(default = "default_backlight_interval")]
+    pub interval_secs: u64,
+}
+
+fn default_min_scale() -> f64 {
+    0.25
+}
+
+fn default_backlight_interval() -> u64 {
+    5
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct AmbientLightConfig {
+    /// Device in /sys/bus/iio/devices, the first light sensor found gets used when unset
+    pub device: Option<String>,
+    pub dark_opacity: Opacity,
+    pub bright_opacity: Opacity,
+    #[serde(default = "default_dark_lux")]
+    pub dark_lux: f64,
+    #[serde(default = "default_bright_lux")]
+    pub bright_lux: f64,
+    /// Relative change in illuminance needed before the opacity gets adjusted
+    #[serde(default = "default_hysteresis")]
+    pub hysteresis: f64,
+    #[serde(default = "default_ambient_light_interval")]
+    pub interval_secs: u64,
+}
+
+fn default_dark_lux() -> f64 {
+    10.0
+}
+
+fn default_bright_lux() -> f64 {
+    1000.0
+}
+
+fn default_hysteresis() -> f64 {
+    0.2
+}
+
+fn default_ambient_light_interval() -> u64 {
+    2
+}
+
+impl AmbientLightConfig {
+    /// Interpolates logarithmically between the dark and bright opacity, that's roughly how
+    /// brightness is perceived.
+    pub fn opacity(&self, lux: f64) -> Opacity {
+        const MIN_LUX: f64 = 0.001;
+        let dark = self.dark_lux.max(MIN_LUX).ln();
+        let bright = self.bright_lux.max(MIN_LUX).ln();
+        let t = if bright > dark {
+            (lux.max(MIN_LUX).ln() - dark) / (bright - dark)
+        } else if lux >= self.bright_lux {
+            1.0
         } else {
-            None
+            0.0
+        };
+        self.dark_opacity.lerp(self.bright_opacity, t)
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct SolarConfig {
+    pub latitude: f64,
+    pub longitude: f64,
+    /// Sun elevation in degrees above which it's fully day
+    #[serde(default = "default_day_elevation")]
+    pub day_elevation: f64,
+    /// Sun elevation in degrees below which it's fully night
+    #[serde(default = "default_night_elevation")]
+    pub night_elevation: f64,
+}
+
+fn default_day_elevation() -> f64 {
+    3.0
+}
+
+fn default_night_elevation() -> f64 {
+    -6.0
+}
+
+impl SolarConfig {
+    /// 0.0 at night, 1.0 at day, in between during dusk and dawn
+    pub fn day_fraction(&self, elevation: f64) -> f64 {
+        if self.day_elevation <= self.night_elevation {
+            return if elevation >= self.day_elevation {
+                1.0
+            } else {
+                0.0
+            };
         }
+        ((elevation - self.night_elevation) / (self.day_elevation - self.night_elevation))
+            .max(0.0)
+            .min(1.0)
     }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct BatteryConfig {
+    #[serde(default = "default_battery_interval")]
+    pub interval_secs: u64,
+    #[serde(default)]
+    pub tiers: Vec<BatteryTier>,
+}
+
+fn default_battery_interval() -> u64 {
+    30
+}
 
-    pub fn max() -> Self {
-        Self(1.0)
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct BatteryTier {
+    /// Tier applies while discharging below this percentage
+    pub below: u8,
+    /// Profile to use while the tier is active
+    pub profile: Option<String>,
+    /// Restore full opacity and stop dimming
+    #[serde(default)]
+    pub disable: bool,
+    /// Stop processing window events entirely, implies `disable`
+    #[serde(default)]
+    pub suspend_events: bool,
+}
+
+impl BatteryConfig {
+    pub fn active_tier(&self, percent: u8, discharging: bool) -> Option<&BatteryTier> {
+        if !discharging {
+            return None;
+        }
+        self.tiers
+            .iter()
+            .filter(|tier| percent < tier.below)
+            .min_by_key(|tier| tier.below)
     }
 }
 
-impl std::fmt::Display for Opacity {
-    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(fmt, "{:3}", self.0)
+impl BatteryTier {
+    pub fn disables_dimming(&self) -> bool {
+        self.disable || self.suspend_events
     }
 }
 
-struct OpacityVisitor;
+impl BacklightConfig {
+    /// How much of the dimming to keep at the given brightness
+    pub fn dim_scale(&self, brightness: f64) -> f64 {
+        let min_scale = self.min_scale.max(0.0).min(1.0);
+        min_scale + (1.0 - min_scale) * brightness
+    }
+}
+
+pub fn default_opacity_step() -> f64 {
+    0.05
+}
+
+fn default_dim_floating() -> bool {
+    true
+}
+
+fn default_process_poll() -> u64 {
+    5
+}
+
+fn default_config_poll() -> u64 {
+    5
+}
+
+#[derive(Snafu, Debug)]
+pub enum LoadError {
+    #[snafu(display("{}", source))]
+    Xdg { source: cfgen::Error },
+
+    #[snafu(display("Can't read {}: {}", path.display(), source))]
+    ReadFile { path: PathBuf, source: io::Error },
+
+    #[snafu(display("Can't write default config to {}: {}", path.display(), source))]
+    WriteDefault { path: PathBuf, source: io::Error },
+
+    #[snafu(display("Invalid config {}: {}", path.display(), source))]
+    ParseFile {
+        path: PathBuf,
+        source: toml::de::Error,
+    },
+}
+
+/// Where the config lives, the XDG config dir unless overridden with `--config`
+#[derive(Debug, Clone, Default)]
+pub struct ConfigPath(Option<PathBuf>);
+
+impl ConfigPath {
+    /// Relative paths are resolved against the working directory
+    pub fn new(custom: Option<PathBuf>) -> Self {
+        ConfigPath(custom.map(|path| match std::env::current_dir() {
+            Ok(dir) => dir.join(path),
+            Err(_) => path,
+        }))
+    }
+
+    pub fn path(&self) -> PathBuf {
+        self.0.clone().unwrap_or_else(Config::path)
+    }
 
-impl<'de> Visitor<'de> for OpacityVisitor {
-    type Value = Opacity;
-    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
-        formatter.write_str("an opacity value between 0.0 and 1.0")
+    pub fn load(&self) -> Result<Config, LoadError> {
+        match self.0 {
+            Some(ref path) => parse_file(path),
+            None => Config::load().context(Xdg),
+        }
     }
 
-    fn visit_f64<E>(self, value: f64) -> Result<Self::Value, E>
-    where
-        E: serde::de::Error,
-    {
-        Opacity::new(value).ok_or_else(|| {
-            E::custom(format!(
-                "float out of range: {}, must be between 0.0 and 1.0",
-                value
-            ))
-        })
+    /// Like `load`, but writes the default config first if there is none. Returns whether it
+    /// did.
+    pub fn load_or_write_default(&self) -> Result<(bool, Config), LoadError> {
+        let path = match self.0 {
+            Some(ref path) => path,
+            None => {
+                let (load, config) = Config::load_or_write_default().context(Xdg)?;
+                return Ok((matches!(load, ConfigLoad::DefaultWritten), config));
+            }
+        };
+        if path.exists() {
+            return Ok((false, parse_file(path)?));
+        }
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).context(WriteDefault { path })?;
+        }
+        fs::write(path, DEFAULT).context(WriteDefault { path })?;
+        Ok((true, parse_file(path)?))
     }
 }
 
-impl<'de> Deserialize<'de> for Opacity {
-    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
-    where
-        D: Deserializer<'de>,
-    {
-        deserializer.deserialize_f64(OpacityVisitor)
+fn parse_file(path: &Path) -> Result<Config, LoadError> {
+    let content = fs::read_to_string(path).context(ReadFile { path })?;
+    toml::from_str(&content).context(ParseFile { path })
+}
+
+impl Config {
+    /// Mistakes that parse fine but get ignored or misbehave at runtime. `config_path` is where
+    /// this config was loaded from, the script is resolved relative to it.
+    pub fn problems(&self, config_path: &Path) -> Vec<String> {
+        let mut problems = Vec::new();
+        if self.min_opacity > self.max_opacity {
+            problems.push(format!(
+                "min_opacity {} is above max_opacity {}",
+                self.min_opacity, self.max_opacity
+            ));
+        }
+        if !self.opacity_step_is_valid() {
+            problems.push(format!(
+                "opacity_step {} is outside of (0, 1]",
+                self.opacity_step
+            ));
+        }
+        for (i, rule) in self.rules.iter().enumerate() {
+            let criteria = [
+                &rule.class,
+                &rule.instance,
+                &rule.match_title,
+                &rule.workspace,
+                &rule.output,
+            ];
+            if criteria.iter().all(|criterion| criterion.is_none()) {
+                problems.push(format!("rule {}: no criteria, it never matches", i + 1));
+            }
+            if let Some(ref title) = rule.match_title {
+                if let Err(e) = regex::Regex::new(title) {
+                    problems.push(format!("rule {}: invalid match_title: {}", i + 1, e));
+                }
+            }
+        }
+        if let Some(ref script) = self.script {
+            let path = config_path.parent().unwrap_or(config_path).join(script);
+            if !cfg!(feature = "scripting") {
+                problems.push("script is set, but scripting support isn't compiled in".to_owned());
+            } else if !path.is_file() {
+                problems.push(format!("script {} doesn't exist", path.display()));
+            }
+        }
+        if self.opacity_backend == OpacityBackend::X11 && !cfg!(feature = "x11") {
+            problems.push("opacity_backend is 'x11', but x11 support isn't compiled in".to_owned());
+        }
+        problems
+    }
+
+    /// Whether `opacity_step` lies in (0, 1], NaN doesn't
+    pub fn opacity_step_is_valid(&self) -> bool {
+        self.opacity_step > 0.0 && self.opacity_step <= 1.0
+    }
+
+    /// The configured opacity restricted to `min_opacity` and `max_opacity`
+    pub fn bounded_opacity(&self) -> Opacity {
+        self.opacity.clamp(self.min_opacity, self.max_opacity)
+    }
+
+    /// Opacities of all profiles restricted to `min_opacity` and `max_opacity`
+    pub fn profile_opacities(&self) -> HashMap<String, Opacity> {
+        self.profiles
+            .iter()
+            .map(|(name, profile)| {
+                let opacity = profile.opacity.clamp(self.min_opacity, self.max_opacity);
+                (name.clone(), opacity)
+            })
+            .collect()
     }
 }
 
-const DEFAULT: &str = "\
-transparency_at_start = true
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_problems() {
+        let config: Config = toml::from_str(
+            "transparency_at_start = true
 opacity = 0.8
-";
+min_opacity = 0.9
+max_opacity = 0.5
 
-#[derive(Cfgen, Deserialize, Debug)]
-#[cfgen(default = "DEFAULT")]
-pub struct Config {
-    pub transparency_at_start: bool,
-    pub opacity: Opacity,
+[[rule]]
+unfocused = 1.0
+
+[[rule]]
+match_title = '('
+",
+        )
+        .unwrap();
+        let problems = config.problems(Path::new("/nonexistent/transparentd.toml"));
+        assert_eq!(problems.len(), 3, "{:?}", problems);
+        assert!(problems[0].starts_with("min_opacity 0.9"));
+        assert_eq!(problems[1], "rule 1: no criteria, it never matches");
+        assert!(problems[2].starts_with("rule 2: invalid match_title"));
+    }
+
+    #[test]
+    fn nan_opacity_step_is_a_problem() {
+        let mut config: Config =
+            toml::from_str("transparency_at_start = true\nopacity = 0.8\n").unwrap();
+        config.opacity_step = std::f64::NAN;
+        let problems = config.problems(Path::new("/nonexistent/transparentd.toml"));
+        assert_eq!(problems, vec!["opacity_step NaN is outside of (0, 1]"]);
+    }
+
+    #[test]
+    fn custom_path_gets_default_written() {
+        let dir = std::env::temp_dir().join(format!("transparentd-config-{}", std::process::id()));
+        let config_path = ConfigPath::new(Some(dir.join("transparentd.toml")));
+
+        let (written, config) = config_path.load_or_write_default().unwrap();
+        assert!(written);
+        let (written, reloaded) = config_path.load_or_write_default().unwrap();
+        assert!(!written);
+        assert_eq!(config.opacity, reloaded.opacity);
+
+        let _ = fs::remove_dir_all(dir);
+    }
 }
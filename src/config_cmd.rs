@@ -0,0 +1,163 @@
+//! `transparentd config <cmd>`, works on the config file without asking the daemon
+
+use std::path::{Path, PathBuf};
+
+use serde_derive::{Deserialize, Serialize};
+use snafu::{ResultExt, Snafu};
+use structopt::StructOpt;
+
+use crate::{
+    config::{self, ConfigPath},
+    ipc::{self, Response},
+    Cmd,
+};
+
+#[derive(StructOpt, Serialize, Deserialize, Debug, Clone)]
+pub enum ConfigCmd {
+    /// Parse and validate the config, exits with 1 if anything is wrong with it
+    #[structopt(name = "check")]
+    Check,
+
+    /// Print the config with defaults filled in and the --socket and --tcp overrides, followed
+    /// by the running daemon's live values
+    #[structopt(name = "dump")]
+    Dump,
+}
+
+#[derive(Snafu, Debug)]
+pub enum Error {
+    #[snafu(display("{}", source))]
+    Load { source: config::LoadError },
+
+    #[snafu(display("Found {} problem(s) in {}", count, path.display()))]
+    Problems { count: usize, path: PathBuf },
+
+    #[snafu(display("Can't print as TOML: {}", source))]
+    ToToml { source: toml::ser::Error },
+}
+
+/// Settings given on the command line or through the environment instead of the config file
+#[derive(Serialize, Debug)]
+struct Overrides<'a> {
+    /// `--socket` or `TRANSPARENTD_SOCKET`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    socket: Option<&'a Path>,
+    /// `--tcp` or `TRANSPARENTD_TCP_PORT`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tcp: Option<u16>,
+}
+
+/// `tcp_port` and `socket` reach the daemon for `dump`
+pub fn run(
+    cmd: ConfigCmd,
+    config_path: &ConfigPath,
+    tcp_port: Option<u16>,
+    socket: Option<&Path>,
+) -> Result<(), Error> {
+    match cmd {
+        ConfigCmd::Check => check(config_path),
+        ConfigCmd::Dump => dump(config_path, tcp_port, socket),
+    }
+}
+
+fn check(config_path: &ConfigPath) -> Result<(), Error> {
+    let path = config_path.path();
+    let config = config_path.load().context(Load)?;
+    let problems = config.problems(&path);
+    for problem in &problems {
+        eprintln!("{}: {}", path.display(), problem);
+    }
+    if problems.is_empty() {
+        println!("{} is valid", path.display());
+        Ok(())
+    } else {
+        Err(Error::Problems {
+            count: problems.len(),
+            path,
+        })
+    }
+}
+
+fn dump(
+    config_path: &ConfigPath,
+    tcp_port: Option<u16>,
+    socket: Option<&Path>,
+) -> Result<(), Error> {
+    let config = config_path.load().context(Load)?;
+    println!("# {}", config_path.path().display());
+    print!("{}", to_toml(&config).context(ToToml)?);
+
+    // commented out like the daemon's values, they aren't config keys
+    let overrides = to_toml(&Overrides {
+        socket,
+        tcp: tcp_port,
+    })
+    .context(ToToml)?;
+    if !overrides.is_empty() {
+        println!("\n# command line and environment");
+        for line in overrides.lines() {
+            println!("# {}", line);
+        }
+    }
+
+    // commented out so the output stays a loadable config
+    if let Ok(Response::Status(status)) =
+        ipc::send_cmd(Cmd::Status { json: false }, tcp_port, socket)
+    {
+        println!("\n# running daemon");
+        for line in to_toml(&status).context(ToToml)?.lines() {
+            println!("# {}", line);
+        }
+    }
+    Ok(())
+}
+
+fn to_toml<T: serde::Serialize>(value: &T) -> Result<String, toml::ser::Error> {
+    // going through a Value puts tables behind plain values like TOML requires
+    toml::to_string(&toml::Value::try_from(value)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    #[test]
+    fn dump_loads_again() {
+        let config: Config = toml::from_str(
+            "transparency_at_start = true
+opacity = 0.8
+
+[[rule]]
+class = 'mpv'
+unfocused = 1.0
+
+[hover]
+delay_ms = 300
+",
+        )
+        .unwrap();
+        let dumped = to_toml(&config).unwrap();
+        let reloaded: Config = toml::from_str(&dumped).unwrap();
+        assert_eq!(reloaded.opacity, config.opacity);
+        assert_eq!(reloaded.rules[0].class.as_ref().unwrap(), "mpv");
+        assert_eq!(reloaded.hover.unwrap().delay_ms, 300);
+    }
+
+    #[test]
+    fn dumps_given_overrides() {
+        let overrides = Overrides {
+            socket: Some(Path::new("/tmp/transparentd.sock")),
+            tcp: Some(7777),
+        };
+        assert_eq!(
+            to_toml(&overrides).unwrap(),
+            "socket = \"/tmp/transparentd.sock\"\ntcp = 7777\n"
+        );
+        let overrides = Overrides {
+            socket: None,
+            tcp: None,
+        };
+        assert_eq!(to_toml(&overrides).unwrap(), "");
+    }
+}
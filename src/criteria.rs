@@ -0,0 +1,245 @@
+//! i3 style criteria like `class="Firefox" title="YouTube"` for picking windows from the command
+//! line. Like in i3 every value except `con_id` is a regex that may match anywhere.
+
+use std::{fmt, str::FromStr};
+
+use regex::Regex;
+use serde::{
+    de::{self, Deserialize, Deserializer, Visitor},
+    Serialize, Serializer,
+};
+use snafu::{ResultExt, Snafu};
+
+use crate::i3::WindowInfo;
+
+#[derive(Debug, Clone, Default)]
+pub struct Criteria {
+    class: Option<Regex>,
+    instance: Option<Regex>,
+    title: Option<Regex>,
+    window_role: Option<Regex>,
+    con_id: Option<i64>,
+}
+
+#[derive(Snafu, Debug)]
+pub enum ParseCriteriaError {
+    #[snafu(display("No criteria given, expected e.g. class=\"Firefox\""))]
+    Empty,
+
+    #[snafu(display(
+        "Unknown criterion {}, expected class, instance, title, window_role or con_id",
+        key
+    ))]
+    UnknownKey { key: String },
+
+    #[snafu(display("Criterion {} is missing a value", key))]
+    MissingValue { key: String },
+
+    #[snafu(display("Unterminated quote in the value of {}", key))]
+    Unterminated { key: String },
+
+    #[snafu(display("Invalid regex for {}: {}", key, source))]
+    InvalidRegex { key: String, source: regex::Error },
+
+    #[snafu(display("Invalid con_id {}: {}", value, source))]
+    InvalidConId {
+        value: String,
+        source: std::num::ParseIntError,
+    },
+}
+
+impl Criteria {
+    /// Every given criterion has to match
+    pub fn matches(&self, window: &WindowInfo) -> bool {
+        let regexes = [
+            (&self.class, &window.class),
+            (&self.instance, &window.instance),
+            (&self.title, &window.title),
+            (&self.window_role, &window.role),
+        ];
+        self.con_id.map_or(true, |id| id == window.id)
+            && regexes.iter().all(|(re, value)| match (re, value) {
+                (Some(re), Some(value)) => re.is_match(value),
+                (Some(_), None) => false,
+                (None, _) => true,
+            })
+    }
+}
+
+/// Reads one value, quoted with `"` or running up to the next whitespace, returning it and the
+/// rest of the input
+fn split_value<'a>(key: &str, s: &'a str) -> Result<(String, &'a str), ParseCriteriaError> {
+    let quoted = match s.strip_prefix('"') {
+        Some(quoted) => quoted,
+        None => {
+            let end = s.find(char::is_whitespace).unwrap_or(s.len());
+            return Ok((s[..end].to_owned(), &s[end..]));
+        }
+    };
+    let mut value = String::new();
+    let mut chars = quoted.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return Ok((value, &quoted[i + 1..])),
+            '\\' => match chars.next() {
+                // keep escapes meant for the regex, only quotes need unescaping
+                Some((_, '"')) => value.push('"'),
+                Some((_, c)) => {
+                    value.push('\\');
+                    value.push(c);
+                }
+                None => break,
+            },
+            c => value.push(c),
+        }
+    }
+    Err(ParseCriteriaError::Unterminated {
+        key: key.to_owned(),
+    })
+}
+
+/// Accepts criteria with or without the surrounding brackets, e.g. `[class="^mpv$" title=foo]`
+impl FromStr for Criteria {
+    type Err = ParseCriteriaError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let mut rest = s
+            .strip_prefix('[')
+            .and_then(|s| s.strip_suffix(']'))
+            .unwrap_or(s);
+        let mut criteria = Criteria::default();
+        let mut empty = true;
+        loop {
+            rest = rest.trim_start();
+            if rest.is_empty() {
+                break;
+            }
+            let eq = rest.find('=');
+            let key = rest[..eq.unwrap_or(rest.len())].trim();
+            let after = match eq {
+                Some(eq) if !rest[eq + 1..].trim().is_empty() => &rest[eq + 1..],
+                _ => return MissingValue { key }.fail(),
+            };
+            let (value, after) = split_value(key, after.trim_start())?;
+            rest = after;
+            let regex = |value: &str| Regex::new(value).context(InvalidRegex { key });
+            match key {
+                "class" => criteria.class = Some(regex(&value)?),
+                "instance" => criteria.instance = Some(regex(&value)?),
+                "title" => criteria.title = Some(regex(&value)?),
+                "window_role" => criteria.window_role = Some(regex(&value)?),
+                "con_id" => {
+                    criteria.con_id = Some(value.parse().context(InvalidConId { value: &value })?)
+                }
+                _ => return UnknownKey { key }.fail(),
+            }
+            empty = false;
+        }
+        if empty {
+            return Empty.fail();
+        }
+        Ok(criteria)
+    }
+}
+
+/// Normalized, so criteria differing only in spacing or order print the same
+impl fmt::Display for Criteria {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let regexes = [
+            ("class", &self.class),
+            ("instance", &self.instance),
+            ("title", &self.title),
+            ("window_role", &self.window_role),
+        ];
+        let mut parts = Vec::new();
+        for (key, re) in regexes.iter() {
+            if let Some(re) = re {
+                parts.push(format!("{}=\"{}\"", key, re.as_str().replace('"', "\\\"")));
+            }
+        }
+        if let Some(id) = self.con_id {
+            parts.push(format!("con_id={}", id));
+        }
+        f.write_str(&parts.join(" "))
+    }
+}
+
+impl PartialEq for Criteria {
+    fn eq(&self, other: &Self) -> bool {
+        self.to_string() == other.to_string()
+    }
+}
+
+struct CriteriaVisitor;
+
+impl<'de> Visitor<'de> for CriteriaVisitor {
+    type Value = Criteria;
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("i3 style criteria like class=\"Firefox\"")
+    }
+
+    fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        value.parse().map_err(E::custom)
+    }
+}
+
+impl<'de> Deserialize<'de> for Criteria {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_str(CriteriaVisitor)
+    }
+}
+
+impl Serialize for Criteria {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn window(class: &str, title: &str) -> WindowInfo {
+        WindowInfo {
+            id: 7,
+            class: Some(class.to_owned()),
+            title: Some(title.to_owned()),
+            ..WindowInfo::default()
+        }
+    }
+
+    #[test]
+    fn parses_and_matches() {
+        let criteria: Criteria = r#"[class="^Firefox$" title="You\"Tube"]"#.parse().unwrap();
+        assert!(criteria.matches(&window("Firefox", "a You\"Tube video")));
+        assert!(!criteria.matches(&window("Firefox", "YouTube")));
+        assert!(!criteria.matches(&window("firefox", "You\"Tube")));
+
+        let criteria: Criteria = "class=mpv con_id=7".parse().unwrap();
+        assert!(criteria.matches(&window("mpv", "")));
+        assert_eq!(criteria.to_string(), r#"class="mpv" con_id=7"#);
+        assert_eq!(criteria, r#"con_id=7  class="mpv""#.parse().unwrap());
+    }
+
+    #[test]
+    fn rejects_broken_criteria() {
+        assert!("".parse::<Criteria>().is_err());
+        assert!("[]".parse::<Criteria>().is_err());
+        assert!("class".parse::<Criteria>().is_err());
+        assert!("class=".parse::<Criteria>().is_err());
+        assert!("class=\"mpv".parse::<Criteria>().is_err());
+        assert!("shape=round".parse::<Criteria>().is_err());
+        assert!("class=(".parse::<Criteria>().is_err());
+        assert!("con_id=abc".parse::<Criteria>().is_err());
+    }
+}
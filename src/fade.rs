@@ -0,0 +1,206 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use crossbeam_channel as chan;
+
+use crate::{config::FadeConfig, opacity::Opacity};
+
+/// A window moving from one opacity to another in fixed steps
+#[derive(Debug, Clone, Copy)]
+struct Animation {
+    from: Opacity,
+    to: Opacity,
+    step: u32,
+}
+
+/// Turns opacity changes into a series of intermediate opacities.
+/// Every window has at most one animation, retargeting a window that's still fading starts the
+/// new animation from wherever the old one currently is so quick focus changes don't make it jump.
+#[derive(Debug)]
+pub struct Fader {
+    steps: u32,
+    interval: Duration,
+    animations: HashMap<i64, Animation>,
+    ticker: chan::Receiver<Instant>,
+}
+
+impl Default for Fader {
+    fn default() -> Self {
+        Self {
+            steps: 0,
+            interval: Duration::from_secs(0),
+            animations: HashMap::new(),
+            ticker: chan::never(),
+        }
+    }
+}
+
+impl Animation {
+    fn current(&self, steps: u32) -> Opacity {
+        self.from
+            .lerp(self.to, f64::from(self.step) / f64::from(steps))
+    }
+
+    fn is_done(&self, steps: u32) -> bool {
+        self.step >= steps
+    }
+}
+
+impl Fader {
+    /// Fading is off without a config or with fewer than two steps
+    pub fn configure(&mut self, config: Option<&FadeConfig>) {
+        let (steps, duration) = match config {
+            Some(config) if config.steps > 1 => {
+                (config.steps, Duration::from_millis(config.duration_ms))
+            }
+            _ => (0, Duration::from_secs(0)),
+        };
+        let interval = if steps > 0 {
+            duration / steps
+        } else {
+            Duration::from_secs(0)
+        };
+        if steps == self.steps && interval == self.interval {
+            return;
+        }
+
+        self.steps = steps;
+        self.interval = interval;
+        self.ticker = if steps > 0 {
+            // zero duration ticks would spin
+            chan::tick(interval.max(Duration::from_millis(1)))
+        } else {
+            chan::never()
+        };
+        self.animations.clear();
+    }
+
+    /// Ticks when the next frame is due, never while nothing is fading
+    pub fn ticks(&self) -> chan::Receiver<Instant> {
+        if self.animations.is_empty() {
+            chan::never()
+        } else {
+            self.ticker.clone()
+        }
+    }
+
+    /// Starts fading windows whose target changed, `current` is the opacity a window had before.
+    /// Returns the opacities to apply right now, newly started animations already take their
+    /// first step.
+    pub fn retarget<F>(&mut self, targets: Vec<(i64, Opacity)>, current: F) -> Vec<(i64, Opacity)>
+    where
+        F: Fn(i64) -> Opacity,
+    {
+        if self.steps == 0 {
+            return targets;
+        }
+
+        let steps = self.steps;
+        targets
+            .into_iter()
+            .map(|(id, target)| {
+                let from = match self.animations.get(&id) {
+                    Some(animation) if animation.to == target => {
+                        return (id, animation.current(steps));
+                    }
+                    Some(animation) => animation.current(steps),
+                    None => current(id),
+                };
+                if from == target {
+                    self.animations.remove(&id);
+                    return (id, target);
+                }
+                let animation = Animation {
+                    from,
+                    to: target,
+                    step: 1,
+                };
+                if animation.is_done(steps) {
+                    self.animations.remove(&id);
+                } else {
+                    self.animations.insert(id, animation);
+                }
+                (id, animation.current(steps))
+            })
+            .collect()
+    }
+
+    /// Advances every animation by one step
+    pub fn frame(&mut self) -> Vec<(i64, Opacity)> {
+        let steps = self.steps;
+        let frame = self
+            .animations
+            .iter_mut()
+            .map(|(&id, animation)| {
+                animation.step += 1;
+                (id, animation.current(steps))
+            })
+            .collect();
+        self.animations
+            .retain(|_, animation| !animation.is_done(steps));
+        frame
+    }
+
+    /// Drops all animations, e.g. when every window gets restored at once
+    pub fn stop(&mut self) {
+        self.animations.clear();
+    }
+
+    pub fn forget(&mut self, id: i64) {
+        self.animations.remove(&id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fader(steps: u32) -> Fader {
+        let mut fader = Fader::default();
+        fader.configure(Some(&FadeConfig {
+            duration_ms: 100,
+            steps,
+        }));
+        fader
+    }
+
+    fn opacity(value: f64) -> Opacity {
+        Opacity::new(value).unwrap()
+    }
+
+    #[test]
+    fn fades_in_steps() {
+        let mut fader = fader(4);
+        let frame = fader.retarget(vec![(1, opacity(0.6))], |_| Opacity::max());
+        assert_eq!(frame, vec![(1, opacity(0.9))]);
+        assert_eq!(fader.frame(), vec![(1, opacity(0.8))]);
+        assert_eq!(fader.frame(), vec![(1, opacity(0.7))]);
+        assert_eq!(fader.frame(), vec![(1, opacity(0.6))]);
+        assert!(fader.frame().is_empty());
+    }
+
+    #[test]
+    fn retargeting_continues_from_current_opacity() {
+        let mut fader = fader(4);
+        fader.retarget(vec![(1, opacity(0.6))], |_| Opacity::max());
+        assert_eq!(fader.frame(), vec![(1, opacity(0.8))]);
+
+        // same target again doesn't restart or advance the animation
+        let frame = fader.retarget(vec![(1, opacity(0.6))], |_| Opacity::max());
+        assert_eq!(frame, vec![(1, opacity(0.8))]);
+
+        // fading back starts at 0.8 instead of jumping to 0.6 first
+        let frame = fader.retarget(vec![(1, Opacity::max())], |_| opacity(0.6));
+        assert_eq!(frame, vec![(1, opacity(0.85))]);
+    }
+
+    #[test]
+    fn disabled_without_config() {
+        let mut fader = Fader::default();
+        let targets = vec![(1, opacity(0.6))];
+        assert_eq!(fader.retarget(targets.clone(), |_| Opacity::max()), targets);
+        assert!(fader.frame().is_empty());
+    }
+}
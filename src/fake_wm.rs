@@ -0,0 +1,120 @@
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    rc::Rc,
+};
+
+use i3ipc::{
+    reply::{CommandOutcome, CommandReply, Node, Workspace, Workspaces},
+    MessageError, Subscription,
+};
+
+use crate::{
+    opacity::Opacity,
+    wm::{Events, Tree, WmBackend, WmConnection},
+    Error,
+};
+
+/// Opacity each window was successfully set to
+pub type Applied = Rc<RefCell<HashMap<i64, Opacity>>>;
+/// Every batch of opacity commands in the order they were sent
+pub type Batches = Rc<RefCell<Vec<Vec<(i64, Opacity)>>>>;
+/// Payloads of the sent tick events
+pub type Ticks = Rc<RefCell<Vec<String>>>;
+
+/// Window manager with a fixed tree remembering the opacities it was told to set
+pub struct FakeWm {
+    pub tree: Node,
+    pub applied: Applied,
+    pub batches: Batches,
+    pub ticks: Ticks,
+    /// Marks of each container
+    pub marks: HashMap<i64, Vec<String>>,
+    pub fullscreen: HashSet<i64>,
+    /// Windows whose opacity commands fail
+    pub failing: HashSet<i64>,
+    /// Names of the workspaces shown on an output
+    pub visible: Vec<String>,
+}
+
+impl FakeWm {
+    pub fn new(tree: Node) -> Self {
+        FakeWm {
+            tree,
+            applied: Applied::default(),
+            batches: Batches::default(),
+            ticks: Ticks::default(),
+            marks: HashMap::new(),
+            fullscreen: HashSet::new(),
+            failing: HashSet::new(),
+            visible: Vec::new(),
+        }
+    }
+}
+
+/// Connection to a `FakeWm` serving `tree` and the opacities it gets told to set
+pub fn fake_wm(tree: Node) -> (WmConnection, Applied) {
+    let wm = FakeWm::new(tree);
+    let applied = wm.applied.clone();
+    (Box::new(wm), applied)
+}
+
+impl WmBackend for FakeWm {
+    fn get_tree(&mut self) -> Result<Tree, MessageError> {
+        Ok(Tree {
+            root: self.tree.clone(),
+            marks: self.marks.clone(),
+            fullscreen: self.fullscreen.clone(),
+        })
+    }
+
+    fn get_workspaces(&mut self) -> Result<Workspaces, MessageError> {
+        Ok(Workspaces {
+            workspaces: self
+                .visible
+                .iter()
+                .map(|name| Workspace {
+                    num: -1,
+                    name: name.clone(),
+                    visible: true,
+                    focused: false,
+                    urgent: false,
+                    rect: (0, 0, 0, 0),
+                    output: String::new(),
+                })
+                .collect(),
+        })
+    }
+
+    fn set_opacity(&mut self, windows: &[(i64, Opacity)]) -> Result<CommandReply, MessageError> {
+        self.batches.borrow_mut().push(windows.to_vec());
+        let mut applied = self.applied.borrow_mut();
+        let outcomes = windows
+            .iter()
+            .map(|&(id, opacity)| {
+                if self.failing.contains(&id) {
+                    CommandOutcome {
+                        success: false,
+                        error: Some("No matching node".to_owned()),
+                    }
+                } else {
+                    applied.insert(id, opacity);
+                    CommandOutcome {
+                        success: true,
+                        error: None,
+                    }
+                }
+            })
+            .collect();
+        Ok(CommandReply { outcomes })
+    }
+
+    fn send_tick(&mut self, payload: &str) -> Result<(), MessageError> {
+        self.ticks.borrow_mut().push(payload.to_owned());
+        Ok(())
+    }
+
+    fn listen(&self, _: &[Subscription]) -> Result<Events, Error> {
+        Ok(Box::new(std::iter::empty()))
+    }
+}
@@ -0,0 +1,107 @@
+use std::{
+    process::{Command, Stdio},
+    thread,
+};
+
+use serde_derive::{Deserialize, Serialize};
+
+/// User supplied shell commands run on state changes
+#[derive(Deserialize, Serialize, Debug, Default, Clone)]
+#[serde(default)]
+pub struct Hooks {
+    pub on_enable: Option<String>,
+    pub on_disable: Option<String>,
+    pub on_profile_change: Option<String>,
+    pub on_apply_error: Option<String>,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub enum Hook {
+    Enable,
+    Disable,
+    ProfileChange,
+    ApplyError,
+}
+
+impl Hooks {
+    fn get(&self, hook: Hook) -> Option<&str> {
+        match hook {
+            Hook::Enable => self.on_enable.as_ref(),
+            Hook::Disable => self.on_disable.as_ref(),
+            Hook::ProfileChange => self.on_profile_change.as_ref(),
+            Hook::ApplyError => self.on_apply_error.as_ref(),
+        }
+        .map(String::as_str)
+    }
+
+    /// Runs the hook through `sh -c` without waiting for it, `env` gets passed as additional
+    /// environment variables.
+    pub fn run(&self, hook: Hook, env: &[(&str, String)]) {
+        let cmd = match self.get(hook) {
+            Some(cmd) => cmd,
+            None => return,
+        };
+
+        log::debug!("Running {:?} hook: {}", hook, cmd);
+        let child = Command::new("sh")
+            .arg("-c")
+            .arg(cmd)
+            .envs(env.iter().cloned())
+            .stdin(Stdio::null())
+            .spawn();
+
+        match child {
+            // reap it in the background so hooks can't stall the event loop
+            Ok(mut child) => {
+                thread::spawn(move || match child.wait() {
+                    Ok(status) if !status.success() => {
+                        log::warn!("{:?} hook exited with {}", hook, status)
+                    }
+                    Ok(_) => {}
+                    Err(e) => log::warn!("Can't wait for {:?} hook: {}", hook, e),
+                });
+            }
+            Err(e) => log::warn!("Can't run {:?} hook: {}", hook, e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, time::Duration};
+
+    use super::*;
+
+    #[test]
+    fn runs_configured_hook_with_env() {
+        let path = std::env::temp_dir().join(format!("transparentd-hook-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let hooks = Hooks {
+            on_profile_change: Some(format!(
+                "printf %s \"$TRANSPARENTD_OPACITY\" > '{}'",
+                path.display()
+            )),
+            ..Hooks::default()
+        };
+
+        // unconfigured hooks are skipped
+        hooks.run(Hook::Enable, &[]);
+        hooks.run(
+            Hook::ProfileChange,
+            &[("TRANSPARENTD_OPACITY", "0.7".to_owned())],
+        );
+
+        let mut written = None;
+        for _ in 0..100 {
+            match fs::read_to_string(&path) {
+                Ok(ref contents) if !contents.is_empty() => {
+                    written = Some(contents.clone());
+                    break;
+                }
+                _ => thread::sleep(Duration::from_millis(50)),
+            }
+        }
+        let _ = fs::remove_file(&path);
+        assert_eq!(written.as_ref().map(String::as_str), Some("0.7"));
+    }
+}
@@ -0,0 +1,320 @@
+//! Minimal HTTP/1.0 endpoint on localhost for tools that can't speak the unix socket protocol.
+//!
+//! `GET /status` returns the status as JSON, `POST /commands/<name>` runs a command like
+//! `toggle`. POSTs need `Content-Type: application/json` so browsers have to do a CORS preflight
+//! (which never succeeds) before a random web page can send commands, the Host header is checked
+//! to defeat DNS rebinding.
+
+use std::{
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{Ipv4Addr, TcpListener, TcpStream},
+    time::Duration,
+};
+
+use crossbeam_channel as chan;
+use serde_json::json;
+use snafu::{ResultExt, Snafu};
+use structopt::StructOpt;
+
+use crate::{
+    ipc::Response, log_limit::LogLimiter, query::Query, workers::Workers, Cmd, IpcRequest,
+};
+
+#[derive(Snafu, Debug)]
+pub enum Error {
+    #[snafu(display("Can't listen on 127.0.0.1:{}: {}", port, source))]
+    Bind { port: u16, source: io::Error },
+}
+
+/// Requests are a request line and a few headers, anything longer is refused
+const MAX_HEADER_LINES: usize = 32;
+const MAX_LINE_LEN: usize = 1024;
+
+pub fn spawn(workers: &mut Workers, port: u16, tx: chan::Sender<IpcRequest>) -> Result<(), Error> {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port)).context(Bind { port })?;
+
+    workers.spawn("http", move |cancelled| {
+        let mut warnings = LogLimiter::default();
+        for stream in listener.incoming() {
+            // woken up by a connection on stop
+            if cancelled.is_cancelled() {
+                return;
+            }
+            let res = stream.and_then(|stream| handle(stream, port, &tx));
+            if let Err(e) = res {
+                warnings.warn(format_args!("http: {}", e));
+            }
+        }
+    });
+    workers.on_stop(move || {
+        let _ = TcpStream::connect((Ipv4Addr::LOCALHOST, port));
+    });
+
+    Ok(())
+}
+
+struct Request {
+    method: String,
+    path: String,
+    host: Option<String>,
+    content_type: Option<String>,
+}
+
+fn read_line<R: BufRead>(reader: &mut R) -> io::Result<String> {
+    let mut line = String::new();
+    reader
+        .by_ref()
+        .take(MAX_LINE_LEN as u64)
+        .read_line(&mut line)?;
+    if !line.ends_with('\n') {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "request line too long or truncated",
+        ));
+    }
+    Ok(line.trim_end().to_owned())
+}
+
+fn read_request<R: BufRead>(reader: &mut R) -> io::Result<Request> {
+    let request_line = read_line(reader)?;
+    let mut parts = request_line.split_whitespace();
+    let (method, path) = match (parts.next(), parts.next()) {
+        (Some(method), Some(path)) => (method.to_owned(), path.to_owned()),
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "malformed request line",
+            ))
+        }
+    };
+
+    let mut request = Request {
+        method,
+        path,
+        host: None,
+        content_type: None,
+    };
+    for _ in 0..MAX_HEADER_LINES {
+        let line = read_line(reader)?;
+        if line.is_empty() {
+            return Ok(request);
+        }
+        let mut header = line.splitn(2, ':');
+        let name = header.next().unwrap_or("").trim().to_ascii_lowercase();
+        let value = header.next().unwrap_or("").trim().to_owned();
+        match name.as_str() {
+            "host" => request.host = Some(value),
+            "content-type" => request.content_type = Some(value),
+            _ => {}
+        }
+    }
+
+    Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        "too many headers",
+    ))
+}
+
+fn respond(stream: &mut TcpStream, status: &str, body: &serde_json::Value) -> io::Result<()> {
+    let body = body.to_string();
+    write!(
+        stream,
+        "HTTP/1.0 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )
+}
+
+fn is_local_host(host: &str, port: u16) -> bool {
+    ["127.0.0.1", "localhost"]
+        .iter()
+        .any(|name| host == *name || host == format!("{}:{}", name, port))
+}
+
+/// Rejected request with the status and error body to answer it with
+type Refusal = (&'static str, serde_json::Value);
+
+/// Command a request asks for
+fn route(request: &Request, port: u16) -> Result<Cmd, Refusal> {
+    if !request
+        .host
+        .as_ref()
+        .map_or(false, |host| is_local_host(host, port))
+    {
+        return Err(("403 Forbidden", json!({ "error": "bad host header" })));
+    }
+
+    match (request.method.as_str(), request.path.as_str()) {
+        ("GET", "/status") => Ok(Cmd::Status { json: true }),
+        ("GET", path) if path.trim_start_matches('/').parse::<Query>().is_ok() => Ok(Cmd::Get {
+            query: path.trim_start_matches('/').parse().unwrap(),
+            json: true,
+        }),
+        ("POST", path) if path.starts_with("/commands/") => {
+            let is_json = request
+                .content_type
+                .as_ref()
+                .map_or(false, |ty| ty.starts_with("application/json"));
+            if !is_json {
+                return Err((
+                    "415 Unsupported Media Type",
+                    json!({ "error": "expected Content-Type: application/json" }),
+                ));
+            }
+            let name = &path["/commands/".len()..];
+            Cmd::from_iter_safe(vec!["transparentd", name]).map_err(|_| {
+                (
+                    "404 Not Found",
+                    json!({ "error": format!("unknown command {}", name) }),
+                )
+            })
+        }
+        _ => Err(("404 Not Found", json!({ "error": "not found" }))),
+    }
+}
+
+fn handle(mut stream: TcpStream, port: u16, tx: &chan::Sender<IpcRequest>) -> io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_millis(500)))?;
+    let request = read_request(&mut BufReader::new(&stream))?;
+
+    let cmd = match route(&request, port) {
+        Ok(cmd) => cmd,
+        Err((status, body)) => return respond(&mut stream, status, &body),
+    };
+
+    let (reply_tx, reply_rx) = chan::bounded(1);
+    let response = match tx.send((cmd, reply_tx)) {
+        Ok(()) => reply_rx
+            .recv()
+            .unwrap_or_else(|_| Response::Err("daemon stopped".to_owned())),
+        Err(_) => Response::Err("daemon stopped".to_owned()),
+    };
+    match response {
+        Response::Ok => respond(&mut stream, "200 OK", &json!({ "ok": true })),
+        Response::Status(status) => respond(&mut stream, "200 OK", &json!(status)),
+        Response::Answer(answer) => respond(&mut stream, "200 OK", &json!(answer)),
+        Response::Err(e) => respond(
+            &mut stream,
+            "500 Internal Server Error",
+            &json!({ "error": e }),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PORT: u16 = 8123;
+
+    fn request(raw: &str) -> Request {
+        read_request(&mut raw.as_bytes()).unwrap()
+    }
+
+    fn status(raw: &str) -> &'static str {
+        route(&request(raw), PORT).unwrap_err().0
+    }
+
+    #[test]
+    fn parses_request() {
+        let request = request(
+            "POST /commands/toggle HTTP/1.0\r\nHost: localhost:8123\r\nCONTENT-TYPE: application/json\r\nAccept: */*\r\n\r\n",
+        );
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path, "/commands/toggle");
+        assert_eq!(request.host.as_deref(), Some("localhost:8123"));
+        assert_eq!(request.content_type.as_deref(), Some("application/json"));
+    }
+
+    #[test]
+    fn refuses_oversized_requests() {
+        let long_line = format!("GET /{} HTTP/1.0\r\n\r\n", "a".repeat(MAX_LINE_LEN));
+        assert!(read_request(&mut long_line.as_bytes()).is_err());
+
+        let many_headers = format!(
+            "GET /status HTTP/1.0\r\n{}\r\n",
+            "X-A: b\r\n".repeat(MAX_HEADER_LINES + 1)
+        );
+        assert!(read_request(&mut many_headers.as_bytes()).is_err());
+
+        assert!(read_request(&mut "GET /status HTTP/1.0\r\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn rejects_foreign_host_headers() {
+        // a rebound DNS name still reaches 127.0.0.1 but carries its own Host header
+        assert_eq!(
+            status("GET /status HTTP/1.0\r\nHost: evil.example:8123\r\n\r\n"),
+            "403 Forbidden"
+        );
+        assert_eq!(
+            status("GET /status HTTP/1.0\r\nHost: localhost:9999\r\n\r\n"),
+            "403 Forbidden"
+        );
+        assert_eq!(status("GET /status HTTP/1.0\r\n\r\n"), "403 Forbidden");
+
+        for host in &["localhost", "localhost:8123", "127.0.0.1", "127.0.0.1:8123"] {
+            let raw = format!("GET /status HTTP/1.0\r\nHost: {}\r\n\r\n", host);
+            assert!(route(&request(&raw), PORT).is_ok(), "{}", host);
+        }
+    }
+
+    #[test]
+    fn commands_need_json_content_type() {
+        assert_eq!(
+            status("POST /commands/toggle HTTP/1.0\r\nHost: localhost\r\n\r\n"),
+            "415 Unsupported Media Type"
+        );
+        // what a cross-origin form can send without a preflight
+        assert_eq!(
+            status(
+                "POST /commands/toggle HTTP/1.0\r\nHost: localhost\r\nContent-Type: text/plain\r\n\r\n"
+            ),
+            "415 Unsupported Media Type"
+        );
+
+        let cmd = route(
+            &request(
+                "POST /commands/toggle HTTP/1.0\r\nHost: localhost\r\nContent-Type: application/json; charset=utf-8\r\n\r\n",
+            ),
+            PORT,
+        );
+        assert!(matches!(cmd, Ok(Cmd::Toggle)));
+    }
+
+    #[test]
+    fn routes_requests() {
+        let routed = |method_path: &str| {
+            let raw = format!(
+                "{} HTTP/1.0\r\nHost: localhost\r\nContent-Type: application/json\r\n\r\n",
+                method_path
+            );
+            route(&request(&raw), PORT)
+        };
+
+        assert!(matches!(
+            routed("GET /status"),
+            Ok(Cmd::Status { json: true })
+        ));
+        assert!(matches!(
+            routed("GET /opacity"),
+            Ok(Cmd::Get {
+                query: Query::Opacity,
+                json: true,
+            })
+        ));
+        assert!(matches!(routed("POST /commands/disable"), Ok(Cmd::Disable)));
+
+        assert_eq!(routed("GET /nothing").unwrap_err().0, "404 Not Found");
+        assert_eq!(routed("POST /status").unwrap_err().0, "404 Not Found");
+        assert_eq!(
+            routed("GET /commands/toggle").unwrap_err().0,
+            "404 Not Found"
+        );
+        assert_eq!(
+            routed("POST /commands/explode").unwrap_err().0,
+            "404 Not Found"
+        );
+    }
+}
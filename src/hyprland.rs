@@ -0,0 +1,621 @@
+//! Hyprland support through its own ipc, `.socket.sock` answers requests and `.socket2.sock`
+//! streams events. Replies get converted into i3ipc's types just like sway's.
+//!
+//! Hyprland has no tick events so ticks only reach listeners inside this process, tick commands
+//! from other processes and `transparentd subscribe` don't work on it.
+
+use std::{
+    collections::{HashMap, HashSet},
+    env,
+    fmt::Write as _,
+    io::{self, BufRead, BufReader, Read, Write},
+    os::unix::net::UnixStream,
+    path::PathBuf,
+    sync::Mutex,
+    time::Duration,
+};
+
+use crossbeam_channel as chan;
+use i3ipc::{
+    event::inner::{WindowChange, WorkspaceChange},
+    reply::{
+        CommandOutcome, CommandReply, Node, NodeBorder, NodeLayout, NodeType, WindowProperty,
+        Workspace, Workspaces,
+    },
+    EstablishError, MessageError, Subscription,
+};
+use lazy_static::lazy_static;
+use serde::de::DeserializeOwned;
+use serde_derive::Deserialize;
+use snafu::ResultExt;
+
+use crate::{
+    opacity::Opacity,
+    wm::{Events, Tree, WmBackend, WmEvent},
+    Error, I3Connect,
+};
+
+/// How long listeners block on the event socket before looking for ticks
+const TICK_POLL: Duration = Duration::from_millis(200);
+
+lazy_static! {
+    /// Ticks for every listener subscribed to them
+    static ref TICKS: Mutex<Vec<chan::Sender<String>>> = Mutex::new(Vec::new());
+}
+
+/// Hyprland sets `HYPRLAND_INSTANCE_SIGNATURE` for everything it starts
+pub fn detect() -> bool {
+    env::var_os("HYPRLAND_INSTANCE_SIGNATURE").is_some()
+}
+
+fn socket_path(name: &str) -> io::Result<PathBuf> {
+    let signature = env::var_os("HYPRLAND_INSTANCE_SIGNATURE").ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            "HYPRLAND_INSTANCE_SIGNATURE is not set",
+        )
+    })?;
+    // newer versions moved the sockets from /tmp into the runtime dir
+    let runtime = env::var_os("XDG_RUNTIME_DIR")
+        .map(|dir| PathBuf::from(dir).join("hypr").join(&signature).join(name));
+    Ok(match runtime {
+        Some(path) if path.exists() => path,
+        _ => PathBuf::from("/tmp/hypr").join(&signature).join(name),
+    })
+}
+
+fn invalid_data<E>(e: E) -> MessageError
+where
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    MessageError::Receive(io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Window addresses are printed as hex, e.g. `0x55d1c8a7e2b0`, they double as con_ids
+fn parse_address(address: &str) -> Option<i64> {
+    let address = address.trim();
+    i64::from_str_radix(address.trim_start_matches("0x"), 16).ok()
+}
+
+#[derive(Deserialize, Debug)]
+struct WorkspaceRef {
+    id: i64,
+}
+
+#[derive(Deserialize, Debug)]
+struct Monitor {
+    name: String,
+    x: i32,
+    y: i32,
+    width: i32,
+    height: i32,
+    #[serde(default = "default_scale")]
+    scale: f64,
+    focused: bool,
+    #[serde(rename = "activeWorkspace")]
+    active_workspace: WorkspaceRef,
+}
+
+fn default_scale() -> f64 {
+    1.0
+}
+
+impl Monitor {
+    /// Position and size in layout coordinates like the ones of clients
+    fn rect(&self) -> (i32, i32, i32, i32) {
+        let scale = if self.scale > 0.0 { self.scale } else { 1.0 };
+        (
+            self.x,
+            self.y,
+            (f64::from(self.width) / scale).round() as i32,
+            (f64::from(self.height) / scale).round() as i32,
+        )
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct HyprWorkspace {
+    id: i64,
+    name: String,
+    monitor: String,
+    #[serde(default)]
+    hasfullscreen: bool,
+}
+
+#[derive(Deserialize, Debug)]
+struct Client {
+    address: String,
+    at: (i32, i32),
+    size: (i32, i32),
+    workspace: WorkspaceRef,
+    floating: bool,
+    class: String,
+    title: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct ActiveWindow {
+    /// `j/activewindow` answers `{}` without a focused window
+    address: Option<String>,
+}
+
+pub struct Connection {
+    path: PathBuf,
+}
+
+impl Connection {
+    pub fn connect() -> Result<Self, EstablishError> {
+        let path = socket_path(".socket.sock").map_err(EstablishError::SocketError)?;
+        // every request gets its own connection, this only checks Hyprland is there
+        UnixStream::connect(&path).map_err(EstablishError::SocketError)?;
+        Ok(Self { path })
+    }
+
+    fn request(&self, request: &str) -> Result<String, MessageError> {
+        let mut stream = UnixStream::connect(&self.path).map_err(MessageError::Send)?;
+        stream
+            .write_all(request.as_bytes())
+            .map_err(MessageError::Send)?;
+        let mut reply = String::new();
+        stream
+            .read_to_string(&mut reply)
+            .map_err(MessageError::Receive)?;
+        Ok(reply)
+    }
+
+    fn query<T: DeserializeOwned>(&self, request: &str) -> Result<T, MessageError> {
+        serde_json::from_str(&self.request(request)?).map_err(invalid_data)
+    }
+}
+
+impl WmBackend for Connection {
+    fn get_tree(&mut self) -> Result<Tree, MessageError> {
+        let monitors = self.query("j/monitors")?;
+        let workspaces: Vec<HyprWorkspace> = self.query("j/workspaces")?;
+        let clients = self.query("j/clients")?;
+        let active: ActiveWindow = self.query("j/activewindow")?;
+        let focused = active.address.as_ref().and_then(|a| parse_address(a));
+        let fullscreen: HashSet<_> = workspaces
+            .iter()
+            .filter(|workspace| workspace.hasfullscreen)
+            .map(|workspace| workspace.name.clone())
+            .collect();
+
+        let mut tree = Tree::new(self::tree(monitors, workspaces, clients, focused));
+        tree.fullscreen = tree
+            .root
+            .nodes
+            .iter()
+            .flat_map(|output| &output.nodes)
+            .filter(|workspace| {
+                workspace
+                    .name
+                    .as_ref()
+                    .map_or(false, |name| fullscreen.contains(name))
+            })
+            .map(|workspace| workspace.id)
+            .collect();
+        Ok(tree)
+    }
+
+    fn get_workspaces(&mut self) -> Result<Workspaces, MessageError> {
+        let monitors: Vec<Monitor> = self.query("j/monitors")?;
+        let workspaces: Vec<HyprWorkspace> = self.query("j/workspaces")?;
+        Ok(Workspaces {
+            workspaces: workspaces
+                .into_iter()
+                .map(|workspace| {
+                    let monitor = monitors
+                        .iter()
+                        .find(|monitor| monitor.name == workspace.monitor);
+                    let visible = monitor
+                        .map_or(false, |monitor| monitor.active_workspace.id == workspace.id);
+                    Workspace {
+                        num: workspace.id as i32,
+                        visible,
+                        focused: visible && monitor.map_or(false, |monitor| monitor.focused),
+                        urgent: false,
+                        rect: monitor.map_or((0, 0, 0, 0), Monitor::rect),
+                        output: workspace.monitor,
+                        name: workspace.name,
+                    }
+                })
+                .collect(),
+        })
+    }
+
+    /// Sets `alpha` and `alphainactive` so Hyprland doesn't dim inactive windows on top of the
+    /// opacity transparentd picked
+    fn set_opacity(&mut self, windows: &[(i64, Opacity)]) -> Result<CommandReply, MessageError> {
+        let mut batch = String::from("[[BATCH]]");
+        for (id, opacity) in windows {
+            for prop in &["alpha", "alphainactive"] {
+                write!(
+                    batch,
+                    "dispatch setprop address:0x{:x} {} {};",
+                    id, prop, opacity
+                )
+                .unwrap();
+            }
+        }
+        let reply = self.request(&batch)?;
+        Ok(command_reply(&reply, windows.len()))
+    }
+
+    fn send_tick(&mut self, payload: &str) -> Result<(), MessageError> {
+        send_tick(payload);
+        Ok(())
+    }
+
+    fn listen(&self, subscriptions: &[Subscription]) -> Result<Events, Error> {
+        let stream = socket_path(".socket2.sock")
+            .and_then(UnixStream::connect)
+            .and_then(|stream| {
+                stream.set_read_timeout(Some(TICK_POLL))?;
+                Ok(stream)
+            })
+            .map_err(EstablishError::SocketError)
+            .context(I3Connect)?;
+
+        let (tick_tx, ticks) = chan::unbounded();
+        if subscriptions
+            .iter()
+            .any(|subscription| matches!(subscription, Subscription::Tick))
+        {
+            TICKS.lock().unwrap().push(tick_tx);
+        }
+
+        Ok(Box::new(EventStream {
+            reader: BufReader::new(stream),
+            line: Vec::new(),
+            ticks,
+            windows: subscriptions
+                .iter()
+                .any(|subscription| matches!(subscription, Subscription::Window)),
+            workspaces: subscriptions
+                .iter()
+                .any(|subscription| matches!(subscription, Subscription::Workspace)),
+            outputs: subscriptions
+                .iter()
+                .any(|subscription| matches!(subscription, Subscription::Output)),
+        }))
+    }
+}
+
+/// Hyprland concatenates the replies of batched commands, every window got two commands
+fn command_reply(reply: &str, windows: usize) -> CommandReply {
+    let replies: Vec<_> = reply
+        .split("\n\n")
+        .map(str::trim)
+        .filter(|reply| !reply.is_empty())
+        .collect();
+    let outcome = |replies: &[&str]| match replies.iter().find(|reply| **reply != "ok") {
+        None => CommandOutcome {
+            success: true,
+            error: None,
+        },
+        Some(error) => CommandOutcome {
+            success: false,
+            error: Some((*error).to_owned()),
+        },
+    };
+
+    let outcomes = if replies.len() == windows * 2 {
+        replies.chunks(2).map(outcome).collect()
+    } else {
+        // can't tell which window a reply belongs to
+        std::iter::repeat(outcome(&replies)).take(windows).collect()
+    };
+    CommandReply { outcomes }
+}
+
+fn container(id: i64, name: String, nodetype: NodeType, rect: (i32, i32, i32, i32)) -> Node {
+    Node {
+        focus: Vec::new(),
+        nodes: Vec::new(),
+        floating_nodes: Vec::new(),
+        id,
+        name: Some(name),
+        nodetype,
+        border: NodeBorder::None,
+        current_border_width: 0,
+        layout: NodeLayout::SplitH,
+        percent: None,
+        rect,
+        window_rect: rect,
+        deco_rect: (0, 0, 0, 0),
+        geometry: rect,
+        window: None,
+        window_properties: None,
+        urgent: false,
+        focused: false,
+    }
+}
+
+/// Builds an i3 like tree of outputs, workspaces and windows.
+/// Outputs and workspaces get negative ids, window addresses are positive.
+fn tree(
+    monitors: Vec<Monitor>,
+    workspaces: Vec<HyprWorkspace>,
+    clients: Vec<Client>,
+    focused: Option<i64>,
+) -> Node {
+    let mut ids = (1..).map(|id: i64| -id);
+    let mut outputs: Vec<_> = monitors
+        .iter()
+        .map(|monitor| {
+            container(
+                ids.next().unwrap(),
+                monitor.name.clone(),
+                NodeType::Output,
+                monitor.rect(),
+            )
+        })
+        .collect();
+
+    let mut by_id = HashMap::with_capacity(workspaces.len());
+    for workspace in workspaces {
+        let output = match outputs
+            .iter()
+            .position(|output| output.name.as_ref() == Some(&workspace.monitor))
+        {
+            Some(output) => output,
+            None => continue,
+        };
+        let rect = outputs[output].rect;
+        let node = container(
+            ids.next().unwrap(),
+            workspace.name,
+            NodeType::Workspace,
+            rect,
+        );
+        by_id.insert(workspace.id, (output, node));
+    }
+
+    for client in clients {
+        let id = match parse_address(&client.address) {
+            Some(id) => id,
+            None => continue,
+        };
+        let (_, workspace) = match by_id.get_mut(&client.workspace.id) {
+            Some(workspace) => workspace,
+            None => continue,
+        };
+        let rect = (client.at.0, client.at.1, client.size.0, client.size.1);
+        let nodetype = if client.floating {
+            NodeType::FloatingCon
+        } else {
+            NodeType::Con
+        };
+        let mut node = container(id, client.title.clone(), nodetype, rect);
+        let mut properties = HashMap::new();
+        properties.insert(WindowProperty::Title, client.title);
+        properties.insert(WindowProperty::Class, client.class);
+        node.window_properties = Some(properties);
+        node.focused = focused == Some(id);
+        if client.floating {
+            workspace.floating_nodes.push(node);
+        } else {
+            workspace.nodes.push(node);
+        }
+    }
+
+    let mut workspaces: Vec<_> = by_id.into_iter().map(|(_, workspace)| workspace).collect();
+    workspaces.sort_by_key(|(_, workspace)| workspace.name.clone());
+    for (output, workspace) in workspaces {
+        outputs[output].nodes.push(workspace);
+    }
+
+    let rect = outputs.iter().fold((0, 0, 0, 0), |(_, _, w, h), output| {
+        let (x, y, width, height) = output.rect;
+        (0, 0, w.max(x + width), h.max(y + height))
+    });
+    let mut root = container(0, "root".to_owned(), NodeType::Root, rect);
+    root.nodes = outputs;
+    root
+}
+
+/// Hands a tick to every listener in this process
+fn send_tick(payload: &str) {
+    TICKS
+        .lock()
+        .unwrap()
+        .retain(|tx| tx.send(payload.to_owned()).is_ok());
+}
+
+/// Converts a line like `closewindow>>55d1c8a7e2b0` of the event socket
+fn event(line: &str) -> WmEvent {
+    let mut parts = line.splitn(2, ">>");
+    let (name, data) = match (parts.next(), parts.next()) {
+        (Some(name), Some(data)) => (name, data),
+        _ => return WmEvent::Other,
+    };
+    let address = data.split(',').next().and_then(parse_address);
+    let window = |change| match address {
+        Some(id) => WmEvent::Window {
+            change,
+            id,
+            container: None,
+        },
+        None => WmEvent::Other,
+    };
+    let workspace = |change| WmEvent::Workspace {
+        change,
+        current: None,
+    };
+    match name {
+        // empty when focus moved to an empty workspace, i3 sends no window event for that and
+        // 0 would be the root container
+        "activewindowv2" if address.is_none() => workspace(WorkspaceChange::Focus),
+        "activewindowv2" => window(WindowChange::Focus),
+        "openwindow" => window(WindowChange::New),
+        "closewindow" => window(WindowChange::Close),
+        "movewindow" => window(WindowChange::Move),
+        "windowtitle" => window(WindowChange::Title),
+        "changefloatingmode" => window(WindowChange::Floating),
+        // doesn't say which window
+        "fullscreen" => WmEvent::Window {
+            change: WindowChange::FullscreenMode,
+            id: 0,
+            container: None,
+        },
+        "workspace" | "focusedmon" => workspace(WorkspaceChange::Focus),
+        "moveworkspace" => workspace(WorkspaceChange::Move),
+        "createworkspace" => workspace(WorkspaceChange::Init),
+        "destroyworkspace" => workspace(WorkspaceChange::Empty),
+        "renameworkspace" => workspace(WorkspaceChange::Rename),
+        "monitoradded" | "monitorremoved" => WmEvent::Output,
+        _ => WmEvent::Other,
+    }
+}
+
+struct EventStream {
+    reader: BufReader<UnixStream>,
+    line: Vec<u8>,
+    ticks: chan::Receiver<String>,
+    windows: bool,
+    workspaces: bool,
+    outputs: bool,
+}
+
+impl EventStream {
+    fn wanted(&self, event: &WmEvent) -> bool {
+        match event {
+            WmEvent::Window { .. } => self.windows,
+            WmEvent::Workspace { .. } => self.workspaces,
+            WmEvent::Output => self.outputs,
+            _ => false,
+        }
+    }
+}
+
+impl Iterator for EventStream {
+    type Item = Result<WmEvent, MessageError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Ok(payload) = self.ticks.try_recv() {
+                return Some(Ok(WmEvent::Tick(payload)));
+            }
+            match self.reader.read_until(b'\n', &mut self.line) {
+                Ok(0) => {
+                    return Some(Err(MessageError::Receive(
+                        io::ErrorKind::UnexpectedEof.into(),
+                    )))
+                }
+                Ok(_) if self.line.ends_with(b"\n") => {
+                    let event = event(String::from_utf8_lossy(&self.line).trim_end());
+                    self.line.clear();
+                    if self.wanted(&event) {
+                        return Some(Ok(event));
+                    }
+                }
+                Ok(_) => {}
+                // the partial line stays in the buffer
+                Err(ref e)
+                    if e.kind() == io::ErrorKind::WouldBlock
+                        || e.kind() == io::ErrorKind::TimedOut => {}
+                Err(e) => return Some(Err(MessageError::Receive(e))),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::i3::AllWindows;
+
+    #[test]
+    fn parses_events() {
+        match event("closewindow>>55d1c8a7e2b0") {
+            WmEvent::Window {
+                change: WindowChange::Close,
+                id,
+                ..
+            } => assert_eq!(id, 0x55d1_c8a7_e2b0),
+            e => panic!("unexpected {:?}", e),
+        }
+        match event("activewindowv2>>55d1c8a7e2b0") {
+            WmEvent::Window {
+                change: WindowChange::Focus,
+                id,
+                ..
+            } => assert_eq!(id, 0x55d1_c8a7_e2b0),
+            e => panic!("unexpected {:?}", e),
+        }
+        assert!(matches!(
+            event("workspace>>2"),
+            WmEvent::Workspace {
+                change: WorkspaceChange::Focus,
+                ..
+            }
+        ));
+        assert!(matches!(event("monitoradded>>HDMI-A-1"), WmEvent::Output));
+        assert!(matches!(event("submap>>resize"), WmEvent::Other));
+    }
+
+    #[test]
+    fn empty_active_window_focuses_no_window() {
+        for line in &["activewindowv2>>", "activewindowv2>>,"] {
+            assert!(matches!(
+                event(line),
+                WmEvent::Workspace {
+                    change: WorkspaceChange::Focus,
+                    ..
+                }
+            ));
+        }
+    }
+
+    #[test]
+    fn pairs_batch_replies() {
+        let reply = command_reply("ok\n\nok\n\nok\n\nwindow not found", 2);
+        let success: Vec<_> = reply.outcomes.iter().map(|o| o.success).collect();
+        assert_eq!(success, vec![true, false]);
+        assert_eq!(
+            reply.outcomes[1].error.as_ref().map(String::as_str),
+            Some("window not found")
+        );
+
+        let reply = command_reply("ok", 3);
+        assert!(reply.outcomes.iter().all(|outcome| outcome.success));
+        assert_eq!(reply.outcomes.len(), 3);
+    }
+
+    #[test]
+    fn builds_tree_from_clients() {
+        let monitors = serde_json::from_str(
+            r#"[{"id": 0, "name": "eDP-1", "x": 0, "y": 0, "width": 2880, "height": 1800,
+                 "scale": 2.0, "focused": true, "activeWorkspace": {"id": 1}}]"#,
+        )
+        .unwrap();
+        let workspaces = serde_json::from_str(
+            r#"[{"id": 1, "name": "1", "monitor": "eDP-1"},
+                {"id": 2, "name": "2", "monitor": "eDP-1"}]"#,
+        )
+        .unwrap();
+        let clients = serde_json::from_str(
+            r#"[{"address": "0x10", "at": [0, 0], "size": [1440, 900], "floating": false,
+                 "workspace": {"id": 1, "name": "1"}, "class": "kitty", "title": "vim"},
+                {"address": "0x20", "at": [10, 10], "size": [300, 200], "floating": true,
+                 "workspace": {"id": 2, "name": "2"}, "class": "pavucontrol", "title": "Volume"}]"#,
+        )
+        .unwrap();
+
+        let tree = tree(monitors, workspaces, clients, Some(0x10));
+        assert_eq!(tree.nodes[0].rect, (0, 0, 1440, 900));
+
+        let windows: Vec<_> = AllWindows::new(tree.clone()).collect();
+        assert_eq!(windows.len(), 2);
+        assert!(windows
+            .iter()
+            .any(|window| window.id == 0x10 && window.focused));
+
+        let tiled: Vec<_> = AllWindows::new(tree)
+            .without_floating()
+            .map(|window| window.id)
+            .collect();
+        assert_eq!(tiled, vec![0x10]);
+    }
+}
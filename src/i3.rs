@@ -1,37 +1,659 @@
-use i3ipc::reply::Node;
+use std::collections::{HashMap, HashSet};
+
+use i3ipc::reply::{Node, NodeLayout, NodeType, WindowProperty};
 
 pub const PROBABLE_AMOUNT_OF_WINDOWS: usize = 16;
 
+/// Depth first iterator over all client windows in a tree, containers like outputs, workspaces
+/// and splits are traversed but not yielded.
 pub struct AllWindows {
     stack: Vec<Node>,
+    floating: bool,
 }
 
-pub trait I3Ext {
-    fn iter_windows(&mut self) -> Result<AllWindows, i3ipc::MessageError>;
-    fn get_focused_window(&mut self) -> Result<Option<i64>, i3ipc::MessageError>;
+/// Whether the node is a client window.
+/// X11 clients have a window id, native wayland clients on sway only show up as leaf containers.
+fn is_window(node: &Node) -> bool {
+    match node.nodetype {
+        NodeType::Con | NodeType::FloatingCon => {
+            node.window.is_some() || (node.nodes.is_empty() && node.floating_nodes.is_empty())
+        }
+        _ => false,
+    }
 }
 
-impl I3Ext for i3ipc::I3Connection {
-    fn iter_windows(&mut self) -> Result<AllWindows, i3ipc::MessageError> {
+/// i3's internal `__i3` output holding the scratchpad
+fn is_internal(node: &Node) -> bool {
+    match node.nodetype {
+        NodeType::Output => node.name.as_ref().map_or(false, |name| name == "__i3"),
+        _ => false,
+    }
+}
+
+impl AllWindows {
+    pub fn new(root: Node) -> Self {
         let mut stack = Vec::with_capacity(PROBABLE_AMOUNT_OF_WINDOWS);
-        stack.push(self.get_tree()?);
-        Ok(AllWindows { stack })
+        stack.push(root);
+        Self {
+            stack,
+            floating: true,
+        }
+    }
+
+    /// Skips floating containers and everything in them
+    pub fn without_floating(mut self) -> Self {
+        self.floating = false;
+        self
     }
+}
+
+/// Identifying properties of a client window
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct WindowInfo {
+    pub id: i64,
+    pub focused: bool,
+    pub class: Option<String>,
+    pub instance: Option<String>,
+    pub title: Option<String>,
+    pub role: Option<String>,
+    /// Set for native wayland clients on sway, i3ipc doesn't deserialize it so it's always
+    /// `None` for now
+    pub app_id: Option<String>,
+    /// Name of the workspace the window is on, only filled in when rules need it
+    pub workspace: Option<String>,
+    /// Name of the output the window is on, only filled in when rules need it
+    pub output: Option<String>,
+}
 
-    fn get_focused_window(&mut self) -> Result<Option<i64>, i3ipc::MessageError> {
-        Ok(self
-            .iter_windows()?
-            .find(|node| node.focused)
-            .map(|node| node.id))
+impl WindowInfo {
+    pub fn from_node(node: &Node) -> Self {
+        let property = |prop| {
+            node.window_properties
+                .as_ref()
+                .and_then(|props| props.get(&prop))
+                .cloned()
+        };
+        Self {
+            id: node.id,
+            focused: node.focused,
+            class: property(WindowProperty::Class),
+            instance: property(WindowProperty::Instance),
+            // wayland clients have no window properties but the container name is the title
+            title: property(WindowProperty::Title).or_else(|| node.name.clone()),
+            role: property(WindowProperty::WindowRole),
+            app_id: None,
+            workspace: None,
+            output: None,
+        }
     }
 }
 
 impl Iterator for AllWindows {
     type Item = Node;
     fn next(&mut self) -> Option<Self::Item> {
-        self.stack.pop().map(|node| {
-            self.stack.extend(node.nodes.clone());
-            node
-        })
+        while let Some(node) = self.stack.pop() {
+            if !is_internal(&node) {
+                self.stack.extend(node.nodes.clone());
+                if self.floating {
+                    self.stack.extend(node.floating_nodes.clone());
+                }
+            }
+            if is_window(&node) {
+                return Some(node);
+            }
+        }
+        None
+    }
+}
+
+/// Where a window is shown
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Location {
+    pub workspace: Option<String>,
+    pub output: Option<String>,
+}
+
+/// Workspace and output names of all windows in the tree by con_id
+pub fn locations(root: &Node) -> HashMap<i64, Location> {
+    let mut locations = HashMap::with_capacity(PROBABLE_AMOUNT_OF_WINDOWS);
+    let mut stack = vec![(root, None)];
+    while let Some((node, output)) = stack.pop() {
+        if is_internal(node) {
+            continue;
+        }
+        match (&node.nodetype, &node.name) {
+            (NodeType::Output, Some(name)) => {
+                stack.extend(node.nodes.iter().map(|child| (child, Some(name))));
+            }
+            (NodeType::Workspace, Some(name)) => {
+                for window in AllWindows::new(node.clone()) {
+                    let location = Location {
+                        workspace: Some(name.clone()),
+                        output: output.cloned(),
+                    };
+                    locations.insert(window.id, location);
+                }
+            }
+            _ => stack.extend(node.nodes.iter().map(|child| (child, output))),
+        }
+    }
+    locations
+}
+
+/// con_ids of all windows inside floating containers
+pub fn floating_windows(root: &Node) -> HashSet<i64> {
+    let tiled: HashSet<_> = AllWindows::new(root.clone())
+        .without_floating()
+        .map(|node| node.id)
+        .collect();
+    AllWindows::new(root.clone())
+        .map(|node| node.id)
+        .filter(|id| !tiled.contains(id))
+        .collect()
+}
+
+/// Number of windows on each workspace
+pub fn workspace_sizes(locations: &HashMap<i64, Location>) -> HashMap<String, usize> {
+    let mut sizes = HashMap::new();
+    for workspace in locations
+        .values()
+        .filter_map(|location| location.workspace.as_ref())
+    {
+        *sizes.entry(workspace.clone()).or_insert(0) += 1;
+    }
+    sizes
+}
+
+/// Name of the workspace holding the focus, found through the focused window or the focus
+/// chain if a container without windows is focused
+pub fn focused_workspace(root: &Node, locations: &HashMap<i64, Location>) -> Option<String> {
+    if let Some(window) = AllWindows::new(root.clone()).find(|node| node.focused) {
+        return locations.get(&window.id)?.workspace.clone();
+    }
+    // the focus chain of a cached tree isn't updated, a focused empty workspace is flagged
+    let mut stack = vec![root];
+    while let Some(node) = stack.pop() {
+        if is_internal(node) {
+            continue;
+        }
+        if let NodeType::Workspace = node.nodetype {
+            if node.focused {
+                return node.name.clone();
+            }
+            continue;
+        }
+        stack.extend(&node.nodes);
+    }
+    let mut node = root;
+    loop {
+        if let NodeType::Workspace = node.nodetype {
+            return node.name.clone();
+        }
+        let next = node.focus.first()?;
+        node = node
+            .nodes
+            .iter()
+            .chain(&node.floating_nodes)
+            .find(|child| child.id == *next)?;
+    }
+}
+
+/// X11 window ids of all windows in the tree by con_id, native wayland windows have none
+pub fn x11_windows(root: &Node) -> HashMap<i64, u32> {
+    AllWindows::new(root.clone())
+        .filter_map(|node| node.window.map(|window| (node.id, window as u32)))
+        .collect()
+}
+
+/// Moves the focus flag to the window, or the empty workspace, with the given con_id.
+/// Returns false if there's no such window shown, the tree is outdated then. Windows in the
+/// scratchpad don't count, getting focused means they were just shown.
+pub fn set_focused(root: &mut Node, id: i64) -> bool {
+    let mut found = false;
+    let mut stack = vec![root];
+    while let Some(node) = stack.pop() {
+        if is_internal(node) {
+            continue;
+        }
+        node.focused = node.id == id;
+        found |= node.focused;
+        stack.extend(node.nodes.iter_mut());
+        stack.extend(node.floating_nodes.iter_mut());
+    }
+    found
+}
+
+/// con_id of the focused container in `node`, e.g. a workspace sent along with an event
+pub fn focused_within(node: &Node) -> Option<i64> {
+    if node.focused {
+        return Some(node.id);
+    }
+    node.nodes
+        .iter()
+        .chain(&node.floating_nodes)
+        .find_map(focused_within)
+}
+
+/// Takes over name, window properties and urgency of the window sent along with an event.
+/// Returns false if the window isn't in the tree.
+pub fn update_window(root: &mut Node, window: &Node) -> bool {
+    let mut stack = vec![root];
+    while let Some(node) = stack.pop() {
+        if node.id == window.id {
+            node.name = window.name.clone();
+            node.window_properties = window.window_properties.clone();
+            node.urgent = window.urgent;
+            return true;
+        }
+        stack.extend(node.nodes.iter_mut());
+        stack.extend(node.floating_nodes.iter_mut());
+    }
+    false
+}
+
+/// Marks every window in a focused container as focused, the container itself gets focused with
+/// i3's `focus parent` and isn't a window
+pub fn focus_descendants(root: &mut Node) {
+    fn focus_all(node: &mut Node) {
+        node.focused = true;
+        for child in node.nodes.iter_mut().chain(node.floating_nodes.iter_mut()) {
+            focus_all(child);
+        }
+    }
+    let mut stack = vec![root];
+    while let Some(node) = stack.pop() {
+        let container = match node.nodetype {
+            NodeType::Con | NodeType::FloatingCon | NodeType::Workspace => !is_window(node),
+            _ => false,
+        };
+        if node.focused && container {
+            focus_all(node);
+            return;
+        }
+        stack.extend(node.nodes.iter_mut());
+        stack.extend(node.floating_nodes.iter_mut());
+    }
+}
+
+/// con_ids of the container with the given con_id and everything in it, empty if there's no
+/// such container
+pub fn ids_within(root: &Node, id: i64) -> HashSet<i64> {
+    fn collect(node: &Node, ids: &mut HashSet<i64>) {
+        ids.insert(node.id);
+        for child in node.nodes.iter().chain(&node.floating_nodes) {
+            collect(child, ids);
+        }
+    }
+    let mut ids = HashSet::new();
+    let mut stack = vec![root];
+    while let Some(node) = stack.pop() {
+        if node.id == id {
+            collect(node, &mut ids);
+            break;
+        }
+        stack.extend(node.nodes.iter().chain(&node.floating_nodes));
+    }
+    ids
+}
+
+/// Whether the window with the given con_id is hidden in the scratchpad
+pub fn in_scratchpad(root: &Node, id: i64) -> bool {
+    fn contains(node: &Node, id: i64) -> bool {
+        node.id == id
+            || node
+                .nodes
+                .iter()
+                .chain(&node.floating_nodes)
+                .any(|child| contains(child, id))
+    }
+    root.nodes
+        .iter()
+        .any(|output| is_internal(output) && contains(output, id))
+}
+
+/// Removes the container with the given con_id and everything in it
+pub fn remove_window(root: &mut Node, id: i64) {
+    let mut stack = vec![root];
+    while let Some(node) = stack.pop() {
+        node.nodes.retain(|child| child.id != id);
+        node.floating_nodes.retain(|child| child.id != id);
+        stack.extend(node.nodes.iter_mut());
+        stack.extend(node.floating_nodes.iter_mut());
+    }
+}
+
+fn rect_contains(rect: (i32, i32, i32, i32), x: i32, y: i32) -> bool {
+    let (rx, ry, width, height) = rect;
+    x >= rx && x < rx + width && y >= ry && y < ry + height
+}
+
+/// Visible window in `node` at the given position, floating windows are on top and only the
+/// focused child of tabbed or stacked containers is visible.
+fn visible_window_at(node: &Node, x: i32, y: i32) -> Option<i64> {
+    if let Some(id) = node
+        .floating_nodes
+        .iter()
+        .rev()
+        .find_map(|floating| visible_window_at(floating, x, y))
+    {
+        return Some(id);
+    }
+    if !rect_contains(node.rect, x, y) {
+        return None;
+    }
+    if is_window(node) {
+        return Some(node.id);
+    }
+    match node.layout {
+        NodeLayout::Tabbed | NodeLayout::Stacked => {
+            let visible = node.focus.first()?;
+            node.nodes
+                .iter()
+                .find(|child| child.id == *visible)
+                .and_then(|child| visible_window_at(child, x, y))
+        }
+        _ => node
+            .nodes
+            .iter()
+            .find_map(|child| visible_window_at(child, x, y)),
+    }
+}
+
+/// The window under the given root coordinates, searching only the visible workspaces
+pub fn window_at(root: &Node, visible_workspaces: &HashSet<String>, x: i32, y: i32) -> Option<i64> {
+    if is_internal(root) {
+        return None;
+    }
+    if let NodeType::Workspace = root.nodetype {
+        let visible = root
+            .name
+            .as_ref()
+            .map_or(false, |name| visible_workspaces.contains(name));
+        return if visible {
+            visible_window_at(root, x, y)
+        } else {
+            None
+        };
+    }
+    root.nodes
+        .iter()
+        .find_map(|node| window_at(node, visible_workspaces, x, y))
+}
+
+/// Bare client window for tests
+#[cfg(test)]
+pub fn test_node(id: i64, nodes: Vec<Node>, floating_nodes: Vec<Node>) -> Node {
+    use i3ipc::reply::NodeBorder;
+
+    Node {
+        focus: Vec::new(),
+        nodes,
+        floating_nodes,
+        id,
+        name: None,
+        nodetype: NodeType::Con,
+        border: NodeBorder::Normal,
+        current_border_width: 0,
+        layout: NodeLayout::SplitH,
+        percent: None,
+        rect: (0, 0, 0, 0),
+        window_rect: (0, 0, 0, 0),
+        deco_rect: (0, 0, 0, 0),
+        geometry: (0, 0, 0, 0),
+        window: Some(id as i32),
+        window_properties: None,
+        urgent: false,
+        focused: false,
+    }
+}
+
+/// Named workspace holding `windows` for tests
+#[cfg(test)]
+pub fn test_workspace(id: i64, name: &str, windows: Vec<Node>) -> Node {
+    let mut workspace = test_node(id, windows, vec![]);
+    workspace.nodetype = NodeType::Workspace;
+    workspace.name = Some(name.to_owned());
+    workspace.window = None;
+    workspace
+}
+
+/// Root container holding `children`, windows or workspaces, for tests
+#[cfg(test)]
+pub fn test_root(children: Vec<Node>) -> Node {
+    let mut root = test_node(0, children, vec![]);
+    root.nodetype = NodeType::Root;
+    root.window = None;
+    root
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{test_node as node, test_workspace as workspace, *};
+
+    #[test]
+    fn traverses_floating_nodes() {
+        let tree = node(
+            1,
+            vec![node(
+                2,
+                vec![node(3, vec![], vec![])],
+                vec![node(4, vec![], vec![])],
+            )],
+            vec![node(5, vec![], vec![node(6, vec![], vec![])])],
+        );
+
+        let mut ids: Vec<_> = AllWindows::new(tree.clone()).map(|node| node.id).collect();
+        ids.sort();
+        assert_eq!(ids, vec![1, 2, 3, 4, 5, 6]);
+
+        let mut ids: Vec<_> = AllWindows::new(tree)
+            .without_floating()
+            .map(|node| node.id)
+            .collect();
+        ids.sort();
+        assert_eq!(ids, vec![1, 2, 3]);
+
+        let mut ids: Vec<_> = floating_windows(&tree).into_iter().collect();
+        ids.sort();
+        assert_eq!(ids, vec![4, 5, 6]);
+    }
+
+    #[test]
+    fn skips_containers_and_internals() {
+        let mut workspace = node(2, vec![node(3, vec![], vec![])], vec![]);
+        workspace.nodetype = NodeType::Workspace;
+        let mut internal = node(4, vec![node(5, vec![], vec![])], vec![]);
+        internal.nodetype = NodeType::Output;
+        internal.name = Some("__i3".to_owned());
+        let mut split = node(6, vec![node(7, vec![], vec![])], vec![]);
+        split.window = None;
+        let root = test_root(vec![workspace, internal, split]);
+
+        let mut ids: Vec<_> = AllWindows::new(root).map(|node| node.id).collect();
+        ids.sort();
+        assert_eq!(ids, vec![3, 7]);
+    }
+
+    #[test]
+    fn finds_locations_of_windows() {
+        let mut web = node(
+            2,
+            vec![node(3, vec![], vec![])],
+            vec![node(4, vec![], vec![])],
+        );
+        web.nodetype = NodeType::Workspace;
+        web.name = Some("1: web".to_owned());
+        let mut scratch = node(5, vec![node(6, vec![], vec![])], vec![]);
+        scratch.nodetype = NodeType::Workspace;
+        scratch.name = Some("__i3_scratch".to_owned());
+        let mut internal = node(7, vec![scratch], vec![]);
+        internal.nodetype = NodeType::Output;
+        internal.name = Some("__i3".to_owned());
+        let mut output = node(8, vec![web], vec![]);
+        output.nodetype = NodeType::Output;
+        output.name = Some("eDP-1".to_owned());
+        let root = test_root(vec![output, internal]);
+
+        let locations = locations(&root);
+        let web = Location {
+            workspace: Some("1: web".to_owned()),
+            output: Some("eDP-1".to_owned()),
+        };
+        assert_eq!(locations.len(), 2);
+        assert_eq!(locations[&3], web);
+        assert_eq!(locations[&4], web);
+    }
+
+    #[test]
+    fn window_info_from_properties() {
+        let mut window = node(1, vec![], vec![]);
+        window.name = Some("container".to_owned());
+        window.window_properties = Some(
+            vec![
+                (WindowProperty::Class, "Firefox".to_owned()),
+                (WindowProperty::Instance, "Navigator".to_owned()),
+                (WindowProperty::Title, "Mozilla Firefox".to_owned()),
+            ]
+            .into_iter()
+            .collect(),
+        );
+
+        let info = WindowInfo::from_node(&window);
+        assert_eq!(info.class, Some("Firefox".to_owned()));
+        assert_eq!(info.instance, Some("Navigator".to_owned()));
+        assert_eq!(info.title, Some("Mozilla Firefox".to_owned()));
+        assert_eq!(info.role, None);
+
+        window.window_properties = None;
+        let info = WindowInfo::from_node(&window);
+        assert_eq!(info.title, Some("container".to_owned()));
+    }
+
+    #[test]
+    fn finds_visible_window_at_point() {
+        let mut left = node(3, vec![], vec![]);
+        left.rect = (0, 0, 50, 100);
+        let mut hidden_tab = node(4, vec![], vec![]);
+        hidden_tab.rect = (50, 0, 50, 100);
+        let mut shown_tab = node(5, vec![], vec![]);
+        shown_tab.rect = (50, 0, 50, 100);
+        let mut tabs = node(6, vec![hidden_tab, shown_tab], vec![]);
+        tabs.window = None;
+        tabs.layout = NodeLayout::Tabbed;
+        tabs.focus = vec![5, 4];
+        tabs.rect = (50, 0, 50, 100);
+        let mut floating = node(7, vec![], vec![]);
+        floating.rect = (40, 40, 20, 20);
+
+        let mut workspace = node(2, vec![left, tabs], vec![floating]);
+        workspace.nodetype = NodeType::Workspace;
+        workspace.name = Some("1".to_owned());
+        workspace.rect = (0, 0, 100, 100);
+        let mut other = workspace.clone();
+        other.name = Some("2".to_owned());
+        let root = test_root(vec![other, workspace]);
+
+        let visible: HashSet<_> = vec!["1".to_owned()].into_iter().collect();
+        assert_eq!(window_at(&root, &visible, 10, 10), Some(3));
+        assert_eq!(window_at(&root, &visible, 80, 10), Some(5));
+        assert_eq!(window_at(&root, &visible, 45, 45), Some(7));
+        assert_eq!(window_at(&root, &HashSet::new(), 10, 10), None);
+    }
+
+    #[test]
+    fn updates_focus_and_removes_windows() {
+        let mut tree = node(
+            1,
+            vec![node(2, vec![], vec![]), node(3, vec![], vec![])],
+            vec![node(4, vec![], vec![])],
+        );
+        tree.nodes[0].focused = true;
+
+        assert!(set_focused(&mut tree, 4));
+        let focused: Vec<_> = AllWindows::new(tree.clone())
+            .filter(|node| node.focused)
+            .map(|node| node.id)
+            .collect();
+        assert_eq!(focused, vec![4]);
+        assert!(!set_focused(&mut tree, 5));
+
+        remove_window(&mut tree, 2);
+        remove_window(&mut tree, 4);
+        let mut ids: Vec<_> = AllWindows::new(tree).map(|node| node.id).collect();
+        ids.sort();
+        assert_eq!(ids, vec![1, 3]);
+    }
+
+    #[test]
+    fn finds_focused_workspace() {
+        let mut root = test_root(vec![
+            workspace(2, "1", vec![node(3, vec![], vec![])]),
+            workspace(4, "2", vec![node(5, vec![], vec![])]),
+        ]);
+        let locations = locations(&root);
+
+        root.nodes[1].nodes[0].focused = true;
+        assert_eq!(focused_workspace(&root, &locations), Some("2".to_owned()));
+
+        // an empty container on the first workspace is focused
+        root.nodes[1].nodes[0].focused = false;
+        root.focus = vec![2, 4];
+        assert_eq!(focused_workspace(&root, &locations), Some("1".to_owned()));
+    }
+
+    #[test]
+    fn scratchpad_windows_are_hidden() {
+        let output = |id, name: &str, windows| {
+            let mut output = node(id, windows, vec![]);
+            output.nodetype = NodeType::Output;
+            output.name = Some(name.to_owned());
+            output.window = None;
+            output
+        };
+        let mut root = test_root(vec![
+            output(2, "__i3", vec![node(3, vec![], vec![])]),
+            output(4, "eDP-1", vec![node(5, vec![], vec![])]),
+        ]);
+
+        assert!(in_scratchpad(&root, 3));
+        assert!(!in_scratchpad(&root, 5));
+        // focusing a window from the scratchpad means the cached tree is outdated
+        assert!(!set_focused(&mut root, 3));
+        assert!(set_focused(&mut root, 5));
+    }
+
+    #[test]
+    fn collects_ids_within_container() {
+        let split = node(
+            2,
+            vec![node(3, vec![], vec![])],
+            vec![node(4, vec![], vec![])],
+        );
+        let root = node(1, vec![split, node(5, vec![], vec![])], vec![]);
+
+        let mut within: Vec<_> = ids_within(&root, 2).into_iter().collect();
+        within.sort();
+        assert_eq!(within, vec![2, 3, 4]);
+        assert!(ids_within(&root, 6).is_empty());
+    }
+
+    #[test]
+    fn focused_container_focuses_its_windows() {
+        let mut split = node(
+            2,
+            vec![node(3, vec![], vec![]), node(4, vec![], vec![])],
+            vec![],
+        );
+        split.window = None;
+        split.focused = true;
+        let mut root = test_root(vec![split, node(5, vec![], vec![])]);
+
+        focus_descendants(&mut root);
+        let mut focused: Vec<_> = AllWindows::new(root)
+            .filter(|node| node.focused)
+            .map(|node| node.id)
+            .collect();
+        focused.sort();
+        assert_eq!(focused, vec![3, 4]);
     }
 }
@@ -0,0 +1,68 @@
+//! Polls the XScreenSaver extension for the time since the last input and reports when the
+//! session goes idle or becomes active again.
+//! Wayland compositors don't offer this through IPC, there `swayidle` (or anything else speaking
+//! `ext-idle-notify`) runs `transparentd idle` and `transparentd active` instead.
+
+use std::time::Duration;
+
+use crossbeam_channel as chan;
+
+use crate::{config::IdleConfig, sensors::Reading};
+
+#[cfg(feature = "x11")]
+pub fn spawn_watcher(config: &IdleConfig, tx: chan::Sender<Reading>) {
+    let poll = Duration::from_millis(config.poll_ms.max(100));
+    let timeout = Duration::from_secs(config.after_secs);
+
+    // FIXME: unjoined thread
+    std::thread::spawn(move || {
+        if let Err(e) = x11::watch(poll, timeout, tx) {
+            log::warn!("Can't track idle time: {}", e);
+        }
+    });
+}
+
+#[cfg(not(feature = "x11"))]
+pub fn spawn_watcher(_: &IdleConfig, _: chan::Sender<Reading>) {
+    log::info!(
+        "transparentd was built without the x11 feature, run `transparentd idle` and \
+         `transparentd active` from swayidle to dim idle sessions"
+    );
+}
+
+#[cfg(feature = "x11")]
+mod x11 {
+    use std::{error::Error, thread, time::Duration};
+
+    use crossbeam_channel as chan;
+    use x11rb::protocol::screensaver::ConnectionExt;
+
+    use crate::sensors::Reading;
+
+    pub fn watch(
+        poll: Duration,
+        timeout: Duration,
+        tx: chan::Sender<Reading>,
+    ) -> Result<(), Box<dyn Error>> {
+        let (conn, screen) = x11rb::connect(None)?;
+        let root = conn.setup().roots[screen].root;
+
+        let mut idle = false;
+        loop {
+            let reply = conn.screensaver_query_info(root)?.reply()?;
+            let since_input = Duration::from_millis(u64::from(reply.ms_since_user_input));
+            if (since_input >= timeout) != idle {
+                idle = !idle;
+                if tx.send(Reading::Idle(idle)).is_err() {
+                    return Ok(());
+                }
+            }
+            // input ends idleness right away, but poll less while nothing happens
+            thread::sleep(if idle {
+                poll
+            } else {
+                poll.max(timeout - since_input)
+            });
+        }
+    }
+}
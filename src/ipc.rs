@@ -1,32 +1,129 @@
 use std::{
-    fs, io,
-    os::unix::net::{UnixListener, UnixStream},
+    env, fs,
+    io::{self, Read, Write},
+    net::{Ipv4Addr, TcpListener, TcpStream},
+    os::unix::{
+        ffi::OsStrExt,
+        net::{UnixListener, UnixStream},
+    },
     path::{Path, PathBuf},
-    time::Duration,
+    thread,
+    time::{Duration, Instant},
 };
 
 use fs2::FileExt;
 use lazy_static::lazy_static;
+use serde_derive::{Deserialize, Serialize};
 use snafu::{ResultExt, Snafu};
 
-use crate::Cmd;
+use crate::{query::Answer, status::Status, systemd, Cmd};
 
 #[derive(Snafu, Debug)]
 pub enum Error {
     #[snafu(display("Can't connect to server: {}", source))]
     Connect { source: io::Error },
 
+    #[snafu(display("Can't listen on 127.0.0.1:{}: {}", port, source))]
+    Bind { port: u16, source: io::Error },
+
     #[snafu(display("IO error while doing ipc: {}", source))]
     Io { source: io::Error },
 
-    #[snafu(display("transparentd already running {}", source))]
-    AlreadyRunning { source: io::Error },
+    #[snafu(display("Can't lock {}: {}", path.display(), source))]
+    Lock { path: PathBuf, source: io::Error },
+
+    #[snafu(display(
+        "transparentd is already running{}",
+        pid.map(|pid| format!(" with pid {}", pid)).unwrap_or_default()
+    ))]
+    AlreadyRunning { pid: Option<u32> },
+
+    #[snafu(display(
+        "transparentd with pid {} holds the lock but doesn't answer on {}, stop it or use \
+         another --socket",
+        pid,
+        socket.display()
+    ))]
+    Unresponsive { pid: u32, socket: PathBuf },
 
     #[snafu(display("Can't create socket dir: {}", source))]
     Mkdir { source: io::Error },
 
     #[snafu(display("Failed to serialize/deserialize cbor: {}", source))]
     Cbor { source: serde_cbor::error::Error },
+
+    #[snafu(display("Frame of {} bytes exceeds the limit of {} bytes", len, MAX_FRAME_LEN))]
+    FrameTooLarge { len: u32 },
+
+    #[snafu(display("Connection closed before receiving a response"))]
+    NoResponse,
+
+    #[snafu(display("Command failed: {}", message))]
+    Rejected { message: String },
+
+    #[snafu(display(
+        "Version mismatch: client is {} (protocol {}), daemon is {} (protocol {})",
+        client.version,
+        client.protocol,
+        daemon.version,
+        daemon.protocol
+    ))]
+    VersionMismatch { client: Hello, daemon: Hello },
+
+    #[snafu(display("Client didn't send a handshake, it's probably older than this daemon"))]
+    MissingHandshake,
+
+    #[snafu(display(
+        "Daemon refused the handshake, it's probably running a different version: {}",
+        message
+    ))]
+    HandshakeRefused { message: String },
+}
+
+impl Error {
+    /// Nothing listens on the socket
+    pub fn is_not_running(&self) -> bool {
+        match self {
+            Error::Connect { source } => match source.kind() {
+                io::ErrorKind::NotFound | io::ErrorKind::ConnectionRefused => true,
+                _ => false,
+            },
+            _ => false,
+        }
+    }
+}
+
+/// Commands are tiny, anything bigger than this is garbage
+const MAX_FRAME_LEN: u32 = 64 * 1024;
+
+/// Bump whenever `Cmd`, `Response` or the framing change in an incompatible way
+const PROTOCOL_VERSION: u32 = 2;
+
+/// First frame of every connection, answered with `Response::Ok` if the daemon speaks the same
+/// protocol or `Response::Err` before it hangs up.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Hello {
+    protocol: u32,
+    /// Only for error messages
+    version: String,
+}
+
+impl Hello {
+    fn current() -> Self {
+        Self {
+            protocol: PROTOCOL_VERSION,
+            version: env!("CARGO_PKG_VERSION").to_owned(),
+        }
+    }
+}
+
+/// Sent back for every command received
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum Response {
+    Ok,
+    Err(String),
+    Status(Status),
+    Answer(Answer),
 }
 
 lazy_static! {
@@ -37,13 +134,69 @@ lazy_static! {
             .unwrap()
             .to_owned()
     };
-    static ref SOCK_PATH: PathBuf = { RUN_DIR.join("ipc.sock") };
-    static ref LOCKFILE_PATH: PathBuf = { RUN_DIR.join(".lockfile") };
+    static ref SOCK_PATH: PathBuf = { RUN_DIR.join(instance_name("ipc", ".sock")) };
+    static ref LOCKFILE_PATH: PathBuf = { RUN_DIR.join(instance_name(".lockfile", "")) };
+    /// Where the systemd socket unit listens, clients fall back to it
+    static ref SHARED_SOCK_PATH: PathBuf = { RUN_DIR.join("ipc.sock") };
+}
+
+/// Variables identifying the window manager instance, the first one that's set wins
+const INSTANCE_VARS: &[&str] = &[
+    "SWAYSOCK",
+    "I3SOCK",
+    "HYPRLAND_INSTANCE_SIGNATURE",
+    "DISPLAY",
+];
+
+/// `{prefix}-{hash}{suffix}` so daemons for nested or parallel sessions don't fight over the
+/// same socket, `{prefix}{suffix}` outside of any session.
+fn instance_name(prefix: &str, suffix: &str) -> String {
+    let instance = INSTANCE_VARS
+        .iter()
+        .filter_map(|var| env::var_os(var))
+        .find(|value| !value.is_empty());
+    match instance {
+        Some(value) => format!("{}-{:016x}{}", prefix, fnv1a(value.as_bytes()), suffix),
+        None => format!("{}{}", prefix, suffix),
+    }
+}
+
+/// Stable across builds unlike `DefaultHasher`, client and daemon might be different binaries
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// The lockfile guarding a socket given with `--socket`
+fn lockfile_for(socket: &Path) -> PathBuf {
+    let mut lockfile = socket.as_os_str().to_owned();
+    lockfile.push(".lock");
+    lockfile.into()
+}
+
+/// The given socket, otherwise the instance's own or the one the systemd socket unit listens on
+fn connect_socket(socket: Option<&Path>) -> Result<UnixStream, io::Error> {
+    if let Some(socket) = socket {
+        return UnixStream::connect(socket);
+    }
+    UnixStream::connect(&*SOCK_PATH).or_else(|e| match e.kind() {
+        io::ErrorKind::NotFound | io::ErrorKind::ConnectionRefused
+            if *SOCK_PATH != *SHARED_SOCK_PATH =>
+        {
+            UnixStream::connect(&*SHARED_SOCK_PATH).map_err(|_| e)
+        }
+        _ => Err(e),
+    })
 }
 
 pub struct IpcServer {
     listener: UnixListener,
     timeout: Duration,
+    socket: PathBuf,
+    lockfile: PathBuf,
+    /// A socket passed by systemd belongs to the socket unit
+    owns_socket: bool,
     _lock: FileLock,
 }
 
@@ -52,29 +205,101 @@ pub struct FileLock {
 }
 
 impl FileLock {
+    /// Takes the lock and writes our pid into the file so later instances can tell who has it
     pub fn lock<P>(path: P) -> Result<Self, io::Error>
     where
         P: AsRef<Path>,
     {
-        let fd = fs::OpenOptions::new()
+        let mut fd = fs::OpenOptions::new()
             .create(true)
-            .append(true)
+            .read(true)
+            .write(true)
             .open(path)?;
         fd.try_lock_exclusive()?;
+        fd.set_len(0)?;
+        write!(fd, "{}", std::process::id())?;
         Ok(Self { _fd: fd })
     }
+
+    /// Pid of the process that took the lock last
+    fn holder(path: &Path) -> Option<u32> {
+        fs::read_to_string(path).ok()?.trim().parse().ok()
+    }
+}
+
+fn is_contended(e: &io::Error) -> bool {
+    e.raw_os_error() == fs2::lock_contended_error().raw_os_error()
+}
+
+/// Locks the instance, taking over a lock whose daemon is gone while something else still keeps
+/// the file open, e.g. a leaked descriptor in a process it spawned.
+fn lock_instance(lockfile: &Path, socket: &Path) -> Result<FileLock, Error> {
+    match FileLock::lock(lockfile) {
+        Ok(lock) => return Ok(lock),
+        Err(ref e) if is_contended(e) => {}
+        Err(e) => return Err(e).context(Lock { path: lockfile }),
+    }
+
+    let pid = FileLock::holder(lockfile);
+    if UnixStream::connect(socket).is_ok() {
+        return Err(Error::AlreadyRunning { pid });
+    }
+    match pid {
+        Some(pid) if Path::new("/proc").join(pid.to_string()).exists() => {
+            Err(Error::Unresponsive {
+                pid,
+                socket: socket.to_owned(),
+            })
+        }
+        Some(pid) => {
+            log::warn!(
+                "Removing stale lockfile {} of exited daemon with pid {}",
+                lockfile.display(),
+                pid
+            );
+            fs::remove_file(lockfile).context(Lock { path: lockfile })?;
+            FileLock::lock(lockfile).context(Lock { path: lockfile })
+        }
+        None => Err(Error::AlreadyRunning { pid }),
+    }
 }
 
 impl IpcServer {
-    pub fn new(timeout: Duration) -> Result<Self, Error> {
-        fs::create_dir_all(Path::new(&*SOCK_PATH).parent().unwrap()).context(Mkdir)?;
-        let lock = FileLock::lock(&*LOCKFILE_PATH).context(AlreadyRunning)?;
-        let _ = fs::remove_file(&*SOCK_PATH);
-        let listener = UnixListener::bind(&*SOCK_PATH).context(Io)?;
+    /// Listens on `socket` or the default path for this window manager instance
+    pub fn new(timeout: Duration, socket: Option<PathBuf>) -> Result<Self, Error> {
+        let (socket, lockfile) = match socket {
+            Some(socket) => {
+                let lockfile = lockfile_for(&socket);
+                (socket, lockfile)
+            }
+            None => (SOCK_PATH.clone(), LOCKFILE_PATH.clone()),
+        };
+        if let Some(dir) = socket.parent() {
+            fs::create_dir_all(dir).context(Mkdir)?;
+        }
+        let lock = lock_instance(&lockfile, &socket)?;
+        let (listener, socket, owns_socket) = match systemd::activated_listener() {
+            Some(listener) => {
+                log::info!("Using the ipc socket passed by systemd");
+                let socket = listener
+                    .local_addr()
+                    .ok()
+                    .and_then(|addr| addr.as_pathname().map(Path::to_owned))
+                    .unwrap_or(socket);
+                (listener, socket, false)
+            }
+            None => {
+                let _ = fs::remove_file(&socket);
+                (UnixListener::bind(&socket).context(Io)?, socket, true)
+            }
+        };
 
         Ok(Self {
             listener,
             timeout,
+            socket,
+            lockfile,
+            owns_socket,
             _lock: lock,
         })
     }
@@ -85,6 +310,43 @@ impl IpcServer {
             timeout: self.timeout,
         }
     }
+
+    pub fn socket(&self) -> &Path {
+        &self.socket
+    }
+}
+
+/// Removes the socket and lockfile once the daemon stops serving
+impl Drop for IpcServer {
+    fn drop(&mut self) {
+        if self.owns_socket {
+            let _ = fs::remove_file(&self.socket);
+        }
+        let _ = fs::remove_file(&self.lockfile);
+    }
+}
+
+/// Blocks until the daemon listening on `socket` released its lock or `timeout` passed
+pub fn wait_for_exit(socket: Option<&Path>, timeout: Duration) -> Result<(), Error> {
+    let lockfile = socket.map_or_else(|| LOCKFILE_PATH.clone(), lockfile_for);
+    let deadline = Instant::now() + timeout;
+    loop {
+        match FileLock::lock(&lockfile) {
+            Ok(_) => return Ok(()),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) if Instant::now() >= deadline => return Err(e).context(Lock { path: lockfile }),
+            Err(_) => thread::sleep(Duration::from_millis(50)),
+        }
+    }
+}
+
+/// Unblocks a server waiting for connections so it notices it should stop
+pub fn wake_server(socket: &Path) {
+    let _ = connect_socket(Some(socket));
+}
+
+pub fn wake_tcp_server(port: u16) {
+    let _ = TcpStream::connect((Ipv4Addr::LOCALHOST, port));
 }
 
 pub struct Incoming<'a> {
@@ -92,13 +354,13 @@ pub struct Incoming<'a> {
     timeout: Duration,
 }
 
-type StreamItem = Result<Cmd, Error>;
+type StreamItem = Result<Connection<UnixStream>, Error>;
 
 impl Incoming<'_> {
     fn accept(&mut self) -> StreamItem {
         let (stream, _) = self.listener.accept().context(Io)?;
         stream.set_read_timeout(Some(self.timeout)).context(Io)?;
-        serde_cbor::from_reader(stream).eager_context(Cbor)
+        Ok(Connection { stream })
     }
 }
 
@@ -110,7 +372,374 @@ impl Iterator for Incoming<'_> {
     }
 }
 
-pub fn send_cmd(cmd: Cmd) -> Result<(), Error> {
-    let mut sock = UnixStream::connect(&*SOCK_PATH).context(Connect)?;
-    serde_cbor::to_writer(&mut sock, &cmd).eager_context(Cbor)
+/// Listens on a loopback TCP port for clients that can't reach the unix socket, e.g. from inside
+/// containers. Unlike the socket it's reachable by every local user.
+pub struct TcpServer {
+    listener: TcpListener,
+    timeout: Duration,
+}
+
+impl TcpServer {
+    pub fn new(port: u16, timeout: Duration) -> Result<Self, Error> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port)).context(Bind { port })?;
+        Ok(Self { listener, timeout })
+    }
+
+    pub fn incoming(&self) -> impl Iterator<Item = Result<Connection<TcpStream>, Error>> + '_ {
+        self.listener.incoming().map(move |stream| {
+            let stream = stream.context(Io)?;
+            stream.set_read_timeout(Some(self.timeout)).context(Io)?;
+            Ok(Connection { stream })
+        })
+    }
+}
+
+/// A client connection carrying any number of commands, each answered with a `Response`.
+pub struct Connection<S> {
+    stream: S,
+}
+
+impl<S: Read + Write> Connection<S> {
+    /// Checks the client's `Hello`, returns `false` if it hung up without sending anything.
+    /// A client speaking another protocol is told so before the error is returned.
+    pub fn handshake(&mut self) -> Result<bool, Error> {
+        let frame = match read_frame(&mut self.stream)? {
+            Some(frame) => frame,
+            None => return Ok(false),
+        };
+        let daemon = Hello::current();
+        let result = match serde_cbor::from_slice::<Hello>(&frame) {
+            Ok(ref client) if client.protocol == daemon.protocol => Ok(true),
+            Ok(client) => Err(Error::VersionMismatch { client, daemon }),
+            Err(_) => Err(Error::MissingHandshake),
+        };
+        match result {
+            Ok(_) => self.respond(&Response::Ok)?,
+            Err(ref e) => self.respond(&Response::Err(e.to_string()))?,
+        }
+        result
+    }
+
+    /// Next command from the client, `None` once it hung up or stayed quiet for longer than
+    /// the timeout.
+    pub fn next_cmd(&mut self) -> Result<Option<Cmd>, Error> {
+        match read_frame(&mut self.stream)? {
+            Some(frame) => decode_cmd(&frame).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    pub fn respond(&mut self, response: &Response) -> Result<(), Error> {
+        write_frame(&mut self.stream, response)
+    }
+}
+
+/// Reads a frame consisting of a big endian u32 length followed by that many bytes.
+/// Returns `None` when the peer closed the connection or timed out between frames.
+pub fn read_frame<R: Read>(mut reader: R) -> Result<Option<Vec<u8>>, Error> {
+    let mut header = [0u8; 4];
+    match reader.read_exact(&mut header) {
+        Ok(()) => {}
+        Err(ref e)
+            if e.kind() == io::ErrorKind::UnexpectedEof
+                || e.kind() == io::ErrorKind::WouldBlock
+                || e.kind() == io::ErrorKind::TimedOut =>
+        {
+            return Ok(None);
+        }
+        Err(e) => return Err(e).context(Io),
+    }
+
+    let len = u32::from_be_bytes(header);
+    if len > MAX_FRAME_LEN {
+        return Err(Error::FrameTooLarge { len });
+    }
+    let mut frame = vec![0u8; len as usize];
+    reader.read_exact(&mut frame).context(Io)?;
+    Ok(Some(frame))
+}
+
+pub fn decode_cmd(frame: &[u8]) -> Result<Cmd, Error> {
+    serde_cbor::from_slice(frame).eager_context(Cbor)
+}
+
+fn write_frame<W, T>(mut writer: W, value: &T) -> Result<(), Error>
+where
+    W: Write,
+    T: serde::Serialize,
+{
+    let frame = serde_cbor::to_vec(value).eager_context(Cbor)?;
+    writer
+        .write_all(&(frame.len() as u32).to_be_bytes())
+        .context(Io)?;
+    writer.write_all(&frame).context(Io)
+}
+
+/// Client side of a connection, commands sent through the same client reuse the connection.
+pub struct Client<S> {
+    stream: S,
+}
+
+impl Client<UnixStream> {
+    pub fn connect(socket: Option<&Path>) -> Result<Self, Error> {
+        let stream = connect_socket(socket).context(Connect)?;
+        Self::handshake(stream)
+    }
+}
+
+impl Client<TcpStream> {
+    pub fn connect_tcp(port: u16) -> Result<Self, Error> {
+        let stream = TcpStream::connect((Ipv4Addr::LOCALHOST, port)).context(Connect)?;
+        Self::handshake(stream)
+    }
+}
+
+impl<S: Read + Write> Client<S> {
+    fn handshake(mut stream: S) -> Result<Self, Error> {
+        write_frame(&mut stream, &Hello::current())?;
+        let frame = read_frame(&mut stream)?.ok_or(Error::NoResponse)?;
+        // a daemon from before the handshake tries to decode the hello as a command and fails
+        match serde_cbor::from_slice(&frame) {
+            Ok(Response::Ok) => Ok(Self { stream }),
+            Ok(Response::Err(message)) => Err(Error::HandshakeRefused { message }),
+            _ => Err(Error::HandshakeRefused {
+                message: "unexpected reply".to_owned(),
+            }),
+        }
+    }
+
+    pub fn send(&mut self, cmd: Cmd) -> Result<Response, Error> {
+        write_frame(&mut self.stream, &cmd)?;
+        let frame = read_frame(&mut self.stream)?.ok_or(Error::NoResponse)?;
+        match serde_cbor::from_slice(&frame).eager_context(Cbor)? {
+            Response::Err(message) => Err(Error::Rejected { message }),
+            response => Ok(response),
+        }
+    }
+}
+
+/// Sends a single command through the unix socket or the loopback port if one is given
+pub fn send_cmd(cmd: Cmd, tcp_port: Option<u16>, socket: Option<&Path>) -> Result<Response, Error> {
+    match tcp_port {
+        Some(port) => Client::connect_tcp(port)?.send(cmd),
+        None => Client::connect(socket)?.send(cmd),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scratch_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("transparentd-test-{}-{}", std::process::id(), name))
+    }
+
+    #[test]
+    fn lock_reports_unresponsive_holder() {
+        let lockfile = scratch_path("unresponsive.lock");
+        let _held = FileLock::lock(&lockfile).unwrap();
+        match lock_instance(&lockfile, &scratch_path("unresponsive.sock")) {
+            Err(Error::Unresponsive { pid, .. }) => assert_eq!(pid, std::process::id()),
+            res => panic!("unexpected result {:?}", res.map(|_| ())),
+        }
+        let _ = fs::remove_file(&lockfile);
+    }
+
+    #[test]
+    fn lock_taken_over_from_exited_holder() {
+        let lockfile = scratch_path("stale.lock");
+        let _leaked = FileLock::lock(&lockfile).unwrap();
+        // no process can have this pid, pid_max is at most 2^22
+        fs::write(&lockfile, u32::max_value().to_string()).unwrap();
+        assert!(lock_instance(&lockfile, &scratch_path("stale.sock")).is_ok());
+        assert_eq!(FileLock::holder(&lockfile), Some(std::process::id()));
+        let _ = fs::remove_file(&lockfile);
+    }
+
+    #[test]
+    fn fnv1a_is_stable() {
+        assert_eq!(fnv1a(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(fnv1a(b"a"), 0xaf63_dc4c_8601_ec8c);
+    }
+
+    fn serve(mut conn: Connection<UnixStream>) -> std::thread::JoinHandle<Result<bool, Error>> {
+        std::thread::spawn(move || conn.handshake())
+    }
+
+    #[test]
+    fn handshake_accepts_same_protocol() {
+        let (client, server) = UnixStream::pair().unwrap();
+        let server = serve(Connection { stream: server });
+        assert!(Client::handshake(client).is_ok());
+        assert!(server.join().unwrap().unwrap());
+    }
+
+    #[test]
+    fn handshake_rejects_other_protocol() {
+        let (mut client, server) = UnixStream::pair().unwrap();
+        let server = serve(Connection { stream: server });
+        let hello = Hello {
+            protocol: PROTOCOL_VERSION + 1,
+            version: "99.0.0".to_owned(),
+        };
+        write_frame(&mut client, &hello).unwrap();
+        let frame = read_frame(&mut client).unwrap().unwrap();
+        match serde_cbor::from_slice(&frame).unwrap() {
+            Response::Err(message) => assert!(message.starts_with("Version mismatch")),
+            response => panic!("unexpected response {:?}", response),
+        }
+        match server.join().unwrap() {
+            Err(Error::VersionMismatch { client, .. }) => assert_eq!(client, hello),
+            result => panic!("unexpected result {:?}", result),
+        }
+    }
+
+    #[test]
+    fn frames_carry_con_ids() {
+        let mut buf = Vec::new();
+        write_frame(&mut buf, &Cmd::BlacklistId { id: 94_371_823 }).unwrap();
+        let frame = read_frame(&buf[..]).unwrap().unwrap();
+        assert!(matches!(
+            decode_cmd(&frame).unwrap(),
+            Cmd::BlacklistId { id: 94_371_823 }
+        ));
+    }
+
+    #[test]
+    fn handshake_rejects_bare_commands() {
+        let (mut client, server) = UnixStream::pair().unwrap();
+        let server = serve(Connection { stream: server });
+        write_frame(&mut client, &Cmd::Toggle).unwrap();
+        assert!(read_frame(&mut client).unwrap().is_some());
+        match server.join().unwrap() {
+            Err(Error::MissingHandshake) => {}
+            result => panic!("unexpected result {:?}", result),
+        }
+    }
+
+    #[test]
+    fn answers_several_commands_per_connection() {
+        let (client, server) = UnixStream::pair().unwrap();
+        let server = std::thread::spawn(move || {
+            let mut conn = Connection { stream: server };
+            assert!(conn.handshake().unwrap());
+            let mut received = Vec::new();
+            while let Some(cmd) = conn.next_cmd().unwrap() {
+                received.push(format!("{:?}", cmd));
+                conn.respond(&Response::Ok).unwrap();
+            }
+            received
+        });
+
+        let mut client = Client::handshake(client).unwrap();
+        for cmd in &[Cmd::Disable, Cmd::Toggle, Cmd::Enable] {
+            assert!(matches!(client.send(cmd.clone()).unwrap(), Response::Ok));
+        }
+        drop(client);
+        assert_eq!(server.join().unwrap(), vec!["Disable", "Toggle", "Enable"]);
+    }
+
+    #[test]
+    fn refuses_oversized_frames() {
+        let header = (MAX_FRAME_LEN + 1).to_be_bytes();
+        assert!(matches!(
+            read_frame(&header[..]),
+            Err(Error::FrameTooLarge { .. })
+        ));
+        // hanging up between frames ends the connection cleanly
+        assert!(read_frame(&[][..]).unwrap().is_none());
+    }
+
+    #[test]
+    fn accepts_commands_over_loopback_tcp() {
+        let server = TcpServer::new(0, Duration::from_secs(5)).unwrap();
+        let addr = server.listener.local_addr().unwrap();
+        assert!(addr.ip().is_loopback());
+
+        let server = std::thread::spawn(move || {
+            let mut conn = server.incoming().next().unwrap().unwrap();
+            assert!(conn.handshake().unwrap());
+            let cmd = conn.next_cmd().unwrap().unwrap();
+            conn.respond(&Response::Ok).unwrap();
+            cmd
+        });
+        let response = send_cmd(Cmd::Toggle, Some(addr.port()), None).unwrap();
+        assert!(matches!(response, Response::Ok));
+        assert!(matches!(server.join().unwrap(), Cmd::Toggle));
+    }
+
+    #[test]
+    fn removes_socket_and_lockfile_on_drop() {
+        let socket = scratch_path("cleanup.sock");
+        let server = IpcServer::new(Duration::from_secs(1), Some(socket.clone())).unwrap();
+        let lockfile = lockfile_for(&socket);
+        assert!(socket.exists());
+        assert!(lockfile.exists());
+
+        drop(server);
+        assert!(!socket.exists());
+        assert!(!lockfile.exists());
+    }
+
+    #[test]
+    fn listens_on_given_socket() {
+        let socket = scratch_path("given").join("transparentd.sock");
+        let server = IpcServer::new(Duration::from_secs(5), Some(socket.clone())).unwrap();
+        assert_eq!(server.socket(), socket.as_path());
+        assert!(lockfile_for(&socket).exists());
+        match IpcServer::new(Duration::from_secs(5), Some(socket.clone())) {
+            Err(Error::AlreadyRunning { pid }) => assert_eq!(pid, Some(std::process::id())),
+            res => panic!("unexpected result {:?}", res.map(|_| ())),
+        }
+
+        let server = std::thread::spawn(move || {
+            // skips the probe of the refused instance
+            let mut conn = server
+                .incoming()
+                .map(Result::unwrap)
+                .find_map(|mut conn| match conn.handshake() {
+                    Ok(true) => Some(conn),
+                    _ => None,
+                })
+                .unwrap();
+            let cmd = conn.next_cmd().unwrap().unwrap();
+            conn.respond(&Response::Ok).unwrap();
+            cmd
+        });
+        let response = send_cmd(Cmd::Toggle, None, Some(&socket)).unwrap();
+        assert!(matches!(response, Response::Ok));
+        assert!(matches!(server.join().unwrap(), Cmd::Toggle));
+        let _ = fs::remove_dir(socket.parent().unwrap());
+    }
+
+    #[test]
+    fn missing_socket_means_not_running() {
+        let socket = scratch_path("missing.sock");
+        let err = send_cmd(Cmd::Toggle, None, Some(&socket)).unwrap_err();
+        assert!(err.is_not_running());
+
+        // a socket nobody listens on anymore
+        drop(UnixListener::bind(&socket).unwrap());
+        let err = send_cmd(Cmd::Toggle, None, Some(&socket)).unwrap_err();
+        assert!(err.is_not_running());
+        let _ = fs::remove_file(&socket);
+
+        let err = Error::HandshakeRefused {
+            message: "Version mismatch".to_owned(),
+        };
+        assert!(!err.is_not_running());
+    }
+
+    #[test]
+    fn waiting_for_exit_times_out_while_locked() {
+        let socket = scratch_path("lingering.sock");
+        let server = IpcServer::new(Duration::from_secs(5), Some(socket.clone())).unwrap();
+        match wait_for_exit(Some(&socket), Duration::from_millis(100)) {
+            Err(Error::Lock { path, .. }) => assert_eq!(path, lockfile_for(&socket)),
+            res => panic!("unexpected result {:?}", res),
+        }
+        drop(server);
+        assert!(wait_for_exit(Some(&socket), Duration::from_millis(100)).is_ok());
+        let _ = fs::remove_file(lockfile_for(&socket));
+    }
 }
@@ -0,0 +1,3833 @@
+mod ambient_light;
+mod apply;
+mod backlight;
+mod battery;
+mod bus;
+mod color_scheme;
+mod config;
+mod config_cmd;
+mod criteria;
+mod fade;
+#[cfg(test)]
+mod fake_wm;
+mod hooks;
+mod http;
+mod hyprland;
+mod i3;
+mod idle;
+mod ipc;
+mod log_limit;
+mod mangen;
+mod opacity;
+mod output;
+mod persist;
+mod pointer;
+mod process_watch;
+mod query;
+mod rate_limit;
+mod rules;
+mod safe_mode;
+mod script;
+mod sensors;
+mod solar;
+mod state;
+mod status;
+mod subscribe;
+mod systemd;
+mod wm;
+mod workers;
+mod x11_opacity;
+
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    ffi::OsStr,
+    io::{Read, Write},
+    os::unix::{
+        io::{AsRawFd, RawFd},
+        net::UnixStream,
+        process::CommandExt,
+    },
+    path::{Path, PathBuf},
+    process,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use cfgen::prelude::*;
+use crossbeam_channel as chan;
+use crossbeam_channel::select;
+use i3ipc::{reply::Node, Subscription};
+use serde_derive::{Deserialize, Serialize};
+use snafu::{ResultExt, Snafu};
+use structopt::StructOpt;
+
+use crate::{
+    apply::{Applier, ApplyError},
+    battery::BatteryState,
+    color_scheme::ColorScheme,
+    config::{
+        AmbientLightConfig, BacklightConfig, BatteryConfig, BatteryTier, Config, ConfigPath,
+        Fullscreen, HoverConfig, IdleConfig, OpacityBackend, RecencyConfig, SolarConfig,
+    },
+    config_cmd::ConfigCmd,
+    criteria::Criteria,
+    fade::Fader,
+    hooks::{Hook, Hooks},
+    i3::{AllWindows, Location, WindowInfo, PROBABLE_AMOUNT_OF_WINDOWS},
+    ipc::{Connection, IpcServer, Response, TcpServer},
+    log_limit::LogLimiter,
+    opacity::Opacity,
+    output::Output,
+    persist::{PersistedBlacklist, WindowKey},
+    query::{Answer, BlacklistEntry, Query},
+    rate_limit::RateLimiter,
+    rules::Rules,
+    safe_mode::SafeMode,
+    script::Script,
+    sensors::{Reading, Sensors},
+    state::State,
+    status::Status,
+    wm::{Tree, WmConnection, WmEvent},
+    workers::{Cancelled, Workers},
+    x11_opacity::X11Opacity,
+};
+
+fn run(opt: Opt) -> Result<(), Error> {
+    match opt.cmd {
+        None => {
+            if opt.replace {
+                replace_running(opt.socket.as_ref().map(PathBuf::as_path))?;
+            }
+            let mut daemon = Daemon::new(ConfigPath::new(opt.config))?;
+            daemon.socket = opt.socket;
+            daemon.run()?
+        }
+        Some(Cmd::Subscribe { format }) => subscribe::run(format)?,
+        Some(Cmd::Mangen { dir }) => mangen::run(&dir).context(Mangen)?,
+        Some(Cmd::Config { cmd }) => {
+            let socket = opt.socket.as_ref().map(PathBuf::as_path);
+            config_cmd::run(cmd, &ConfigPath::new(opt.config), opt.tcp_port, socket)
+                .context(ConfigCmdErr)?
+        }
+        Some(cmd) => {
+            let json = match cmd {
+                Cmd::Status { json } | Cmd::Get { json, .. } | Cmd::BlacklistList { json } => json,
+                _ => false,
+            };
+            let socket = opt.socket.as_ref().map(PathBuf::as_path);
+            match send_cmd(cmd, opt.tcp_port, socket, opt.spawn)? {
+                Response::Status(status) if json => println!("{}", serde_json::json!(status)),
+                Response::Status(status) => Output::stdout().status(&status),
+                Response::Answer(answer) if json => println!("{}", serde_json::json!(answer)),
+                Response::Answer(answer) => print!("{}", answer),
+                Response::Ok | Response::Err(_) => {}
+            }
+        }
+    }
+    Ok(())
+}
+
+/// How long `--replace` waits for the running daemon to restore opacity and exit
+const REPLACE_TIMEOUT: Duration = Duration::from_secs(5);
+
+fn replace_running(socket: Option<&Path>) -> Result<(), Error> {
+    match ipc::send_cmd(Cmd::Quit, None, socket) {
+        Ok(_) => {}
+        Err(ref e) if e.is_not_running() => return Ok(()),
+        Err(e) => return Err(e).context(Ipc),
+    }
+    log::info!("Waiting for the running daemon to exit");
+    ipc::wait_for_exit(socket, REPLACE_TIMEOUT).context(Ipc)
+}
+
+/// How long `--spawn` waits for the new daemon to accept commands
+const SPAWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+fn send_cmd(
+    cmd: Cmd,
+    tcp_port: Option<u16>,
+    socket: Option<&Path>,
+    spawn: bool,
+) -> Result<Response, Error> {
+    match ipc::send_cmd(cmd.clone(), tcp_port, socket) {
+        Err(ref e) if spawn && e.is_not_running() => {}
+        res => return res.context(Ipc),
+    }
+
+    spawn_daemon(socket)?;
+    let deadline = Instant::now() + SPAWN_TIMEOUT;
+    loop {
+        thread::sleep(Duration::from_millis(50));
+        match ipc::send_cmd(cmd.clone(), tcp_port, socket) {
+            Err(ref e) if e.is_not_running() && Instant::now() < deadline => {}
+            res => return res.context(Ipc),
+        }
+    }
+}
+
+/// Starts the daemon in its own session so it outlives the client and whatever launched it
+fn spawn_daemon(socket: Option<&Path>) -> Result<(), Error> {
+    let exe = std::env::current_exe().context(Spawn)?;
+    let mut daemon = process::Command::new(exe);
+    if let Some(socket) = socket {
+        daemon.arg("--socket").arg(socket);
+    }
+    daemon
+        .stdin(process::Stdio::null())
+        .stdout(process::Stdio::null());
+    unsafe {
+        daemon.pre_exec(|| {
+            libc::setsid();
+            Ok(())
+        });
+    }
+    log::info!("Starting transparentd");
+    daemon.spawn().context(Spawn)?;
+    Ok(())
+}
+
+#[derive(Snafu, Debug)]
+enum Error {
+    #[snafu(display("Can't load config: {}", source))]
+    ConfigErr { source: config::LoadError },
+
+    #[snafu(display("Can't connect to i3: {}", source))]
+    I3Connect { source: i3ipc::EstablishError },
+
+    #[snafu(display("Can't communicate with i3: {}", source))]
+    I3Comm { source: i3ipc::MessageError },
+
+    #[snafu(display("Error in ipc: {}", source))]
+    Ipc { source: ipc::Error },
+
+    #[snafu(display("Can't start http endpoint: {}", source))]
+    Http { source: http::Error },
+
+    #[snafu(display("Can't install signal handler: {}", source))]
+    SignalHandler { source: std::io::Error },
+
+    #[snafu(display("Can't write man page: {}", source))]
+    Mangen { source: std::io::Error },
+
+    #[snafu(display("{}", source))]
+    ConfigCmdErr { source: config_cmd::Error },
+
+    #[snafu(display("Can't start the daemon: {}", source))]
+    Spawn { source: std::io::Error },
+}
+
+impl From<i3ipc::MessageError> for Error {
+    fn from(source: i3ipc::MessageError) -> Self {
+        Error::I3Comm { source }
+    }
+}
+
+#[derive(StructOpt)]
+struct Opt {
+    /// Log more, -v for info and -vv for debug output
+    #[structopt(
+        short = "v",
+        long = "verbose",
+        parse(from_occurrences),
+        raw(global = "true")
+    )]
+    verbose: u8,
+
+    /// Don't log anything, fatal errors are still printed
+    #[structopt(short = "q", long = "quiet", raw(global = "true"))]
+    quiet: bool,
+
+    /// Talk to the daemon through this loopback port instead of the unix socket
+    #[structopt(long = "tcp", env = "TRANSPARENTD_TCP_PORT")]
+    tcp_port: Option<u16>,
+
+    /// Listen on or connect to this unix socket instead of the one in the runtime dir
+    #[structopt(
+        long = "socket",
+        env = "TRANSPARENTD_SOCKET",
+        parse(from_os_str),
+        raw(global = "true")
+    )]
+    socket: Option<PathBuf>,
+
+    /// Load the config from this file instead of the XDG config dir
+    #[structopt(
+        long = "config",
+        env = "TRANSPARENTD_CONFIG",
+        parse(from_os_str),
+        raw(global = "true")
+    )]
+    config: Option<PathBuf>,
+
+    /// Start the daemon if it isn't running yet before sending the command
+    #[structopt(long = "spawn")]
+    spawn: bool,
+
+    /// Stop the running daemon and take its place, e.g. after upgrading
+    #[structopt(long = "replace")]
+    replace: bool,
+
+    #[structopt(subcommand)]
+    cmd: Option<Cmd>,
+}
+
+#[derive(StructOpt, Serialize, Deserialize, Debug, Clone)]
+pub enum Cmd {
+    /// Disable opacity changes of unfocused windows
+    #[structopt(name = "disable")]
+    Disable,
+
+    /// Enable opacity changes of unfocused windows
+    #[structopt(name = "enable")]
+    Enable,
+
+    /// Toggle opacity changes of unfocused windows
+    #[structopt(name = "toggle")]
+    Toggle,
+
+    /// Disable opacity changes and enable them again after a while, e.g. for presentations
+    #[structopt(name = "pause")]
+    Pause {
+        /// How long to stay disabled, e.g. 30m or 1h
+        #[structopt(parse(try_from_str = "humantime::parse_duration"))]
+        duration: Duration,
+    },
+
+    /// Never apply opacity changes to currently focused window
+    #[structopt(name = "focus-blacklist")]
+    FocusBlacklist {
+        /// Only exclude the window for this long, e.g. 10m, and don't remember it across restarts
+        #[structopt(long = "for", parse(try_from_str = "humantime::parse_duration"))]
+        duration: Option<Duration>,
+    },
+
+    /// Remove currently focused window from list of opacity excluded windows
+    #[structopt(name = "focus-blacklist-remove")]
+    FocusBlacklistRemove,
+
+    /// Exclude the currently focused window, or stop excluding it if it already is
+    #[structopt(name = "focus-blacklist-toggle")]
+    FocusBlacklistToggle,
+
+    /// Never apply opacity changes to windows matching i3 style criteria, e.g. 'class="Firefox"'
+    #[structopt(name = "blacklist")]
+    Blacklist { criteria: Criteria },
+
+    /// Stop excluding windows matching the criteria, including ones excluded otherwise
+    #[structopt(name = "unblacklist")]
+    Unblacklist { criteria: Criteria },
+
+    /// Print the excluded windows with their class and title, and the blacklisted criteria
+    #[structopt(name = "blacklist-list")]
+    BlacklistList {
+        /// Print the blacklist as JSON
+        #[structopt(long = "json")]
+        json: bool,
+    },
+
+    /// Stop excluding any window, including ones remembered across restarts
+    #[structopt(name = "blacklist-clear")]
+    BlacklistClear,
+
+    /// Never apply opacity changes to the window with this con_id, e.g. from `i3-msg -t get_tree`
+    #[structopt(name = "blacklist-id")]
+    BlacklistId { id: i64 },
+
+    /// Remove the window with this con_id from the excluded windows
+    #[structopt(name = "unblacklist-id")]
+    UnblacklistId { id: i64 },
+
+    /// Restore opacity and stop the daemon
+    #[structopt(name = "quit")]
+    Quit,
+
+    /// Re-read the config and reapply opacity, same as sending SIGHUP
+    #[structopt(name = "reload")]
+    Reload,
+
+    /// Print a line on every state change, e.g. for polybar's tail modules
+    #[structopt(name = "subscribe")]
+    Subscribe {
+        /// Output format, plain or polybar
+        #[structopt(long = "format", default_value = "plain")]
+        format: subscribe::Format,
+    },
+
+    /// Work with the config file
+    #[structopt(name = "config")]
+    Config {
+        #[structopt(subcommand)]
+        cmd: ConfigCmd,
+    },
+
+    /// Write man pages generated from this help to a directory
+    #[structopt(name = "mangen")]
+    Mangen {
+        #[structopt(parse(from_os_str))]
+        dir: PathBuf,
+    },
+
+    /// Set the opacity of unfocused windows until the config gets reloaded
+    #[structopt(name = "set-opacity")]
+    SetOpacity {
+        /// New opacity, e.g. 0.6 or 60%
+        opacity: Opacity,
+    },
+
+    /// Make unfocused windows more opaque by the configured opacity_step
+    #[structopt(name = "opacity-up")]
+    OpacityUp,
+
+    /// Make unfocused windows more transparent by the configured opacity_step
+    #[structopt(name = "opacity-down")]
+    OpacityDown,
+
+    /// Lower every window to the idle opacity until `active`, e.g. from swayidle's timeout
+    #[structopt(name = "idle")]
+    Idle,
+
+    /// Leave the idle state, e.g. from swayidle's resume
+    #[structopt(name = "active")]
+    Active,
+
+    /// Show every window fully opaque for a moment
+    #[structopt(name = "peek")]
+    Peek {
+        /// How long to reveal the windows, e.g. 2s or 500ms
+        #[structopt(
+            long = "duration",
+            default_value = "2s",
+            parse(try_from_str = "humantime::parse_duration")
+        )]
+        duration: Duration,
+    },
+
+    /// Print the daemon's current state
+    #[structopt(name = "status")]
+    Status {
+        /// Print the state as JSON for scripts
+        #[structopt(long = "json")]
+        json: bool,
+    },
+
+    /// Print a single value: state, opacity, blacklist or rules
+    #[structopt(name = "get")]
+    Get {
+        query: Query,
+        /// Print the value as JSON
+        #[structopt(long = "json")]
+        json: bool,
+    },
+
+    /// Broadcast the whole state as i3 tick events
+    #[structopt(
+        name = "resend-state",
+        raw(setting = "structopt::clap::AppSettings::Hidden")
+    )]
+    ResendState,
+}
+
+/// Pause reason that also stops opacity commands until it goes away
+const SUSPENDED_WHILE_FULLSCREEN: &str = "suspended while fullscreen";
+
+struct Daemon {
+    state: State,
+    transparency: Opacity,
+    focused_opacity: Opacity,
+    other_workspace_opacity: Option<Opacity>,
+    urgent_opacity: Option<Opacity>,
+    floating_opacity: Option<Opacity>,
+    /// Floating windows as of the last traversal, only tracked for `floating_opacity`
+    floating: HashSet<i64>,
+    /// Workspaces shown on other outputs than the focused one as of the last traversal, only
+    /// tracked for `other_workspace_opacity`
+    shown_elsewhere: HashSet<String>,
+    base_opacity: Opacity,
+    min_opacity: Opacity,
+    max_opacity: Opacity,
+    opacity_step: f64,
+    profiles: HashMap<String, Opacity>,
+    rules: Rules,
+    follow_color_scheme: bool,
+    color_scheme: ColorScheme,
+    backlight_config: Option<BacklightConfig>,
+    backlight: Option<f64>,
+    ambient_light_config: Option<AmbientLightConfig>,
+    ambient_light: Option<f64>,
+    solar_config: Option<SolarConfig>,
+    solar_elevation: Option<f64>,
+    battery_config: Option<BatteryConfig>,
+    battery: Option<BatteryState>,
+    pause_for_processes: Vec<String>,
+    process_poll: Duration,
+    config_poll: Duration,
+    running_watched_process: Option<String>,
+    fullscreen: Fullscreen,
+    dim_floating: bool,
+    hover_config: Option<HoverConfig>,
+    /// Where the pointer rests, if it does
+    pointer: Option<(i32, i32)>,
+    /// Window under the resting pointer, shown like the focused one
+    hovered: Option<i64>,
+    min_windows: usize,
+    /// Windows on each workspace as of the last traversal, only tracked for `min_windows`
+    workspace_sizes: HashMap<String, usize>,
+    keep_recent_opaque: usize,
+    recency: Option<RecencyConfig>,
+    /// con_ids of the most recently focused windows, most recent first. Only as long as
+    /// `keep_recent_opaque` needs without `recency`, every live window can be in it otherwise.
+    focus_history: VecDeque<i64>,
+    idle_config: Option<IdleConfig>,
+    /// Tree as of the last traversal, kept up to date from window events so focus, title and
+    /// urgency changes don't need a round trip. `None` when it has to be fetched again.
+    tree: Option<Tree>,
+    /// Names of the workspaces shown on some output, only fetched for hover. `None` when they
+    /// have to be fetched again.
+    visible_workspaces: Option<HashSet<String>>,
+    focus_debounce: Duration,
+    /// Apply once this passes, set by debounced focus changes
+    pending_apply: Option<Instant>,
+    rate_limiter: RateLimiter,
+    /// Last failure to apply opacity, reported back to the client that caused it
+    apply_error: Option<String>,
+    /// Everything stays opaque until then, set by `peek`
+    peek_until: Option<Instant>,
+    /// When windows blacklisted with `focus-blacklist --for` get dimmed again
+    blacklist_expiry: HashMap<i64, Instant>,
+    /// Enable again at this point, set by `pause` and cleared by any other state change
+    resume_at: Option<Instant>,
+    /// Set by the idle watcher or `transparentd idle`, cleared on input or `transparentd active`
+    idle: bool,
+    /// Workspace and output of each window as of the last traversal, only tracked for rules
+    /// matching on them
+    locations: HashMap<i64, Location>,
+    config_path: ConfigPath,
+    tcp_port: Option<u16>,
+    http_port: Option<u16>,
+    /// Set with `--socket`
+    socket: Option<PathBuf>,
+    opacity_backend: OpacityBackend,
+    blacklist: HashSet<i64>,
+    /// Blacklisted windows by class, instance and title, survives restarts
+    persisted_blacklist: PersistedBlacklist,
+    /// Window classes blacklisted in the config
+    class_blacklist: HashSet<String>,
+    /// Added with the blacklist command, forgotten on restart
+    criteria_blacklist: Vec<Criteria>,
+    /// Windows with this mark are kept opaque
+    exempt_mark: Option<String>,
+    /// Windows carrying `exempt_mark` as of the last traversal
+    marked: HashSet<i64>,
+    safe_mode: SafeMode,
+    applier: Applier,
+    fader: Fader,
+    warnings: LogLimiter,
+    broadcast: Option<BroadcastState>,
+    hooks: Hooks,
+    script: Option<Script>,
+}
+
+/// State other i3 IPC consumers get notified about through tick events
+#[derive(Debug, Clone, PartialEq)]
+struct BroadcastState {
+    enabled: bool,
+    paused: Option<String>,
+    opacity: Opacity,
+    degraded: bool,
+    /// Windows skipped because setting their opacity keeps failing
+    skipped: usize,
+}
+
+fn remove_all_transparency(
+    applier: &mut Applier,
+    i3_conn: &mut WmConnection,
+) -> Result<(), ApplyError> {
+    let tree = i3_conn.get_tree()?.root;
+    if applier.uses_x11() {
+        applier.track_x11_windows(i3::x11_windows(&tree));
+    }
+    let all_windows = AllWindows::new(tree).map(|node| (node.id, Opacity::max()));
+
+    applier.set_opacity(i3_conn, all_windows)
+}
+
+impl Daemon {
+    fn new(config_path: ConfigPath) -> Result<Self, Error> {
+        let (written, config) = config_path.load_or_write_default().context(ConfigErr)?;
+        if written {
+            println!("Default config written to {}", config_path.path().display())
+        }
+
+        let mut daemon = Self::with_config(config, config_path);
+        daemon.persisted_blacklist = PersistedBlacklist::load();
+        Ok(daemon)
+    }
+
+    fn with_config(config: Config, config_path: ConfigPath) -> Self {
+        let mut daemon = Self {
+            config_path,
+            state: State::new(config.transparency_at_start),
+            transparency: Opacity::max(),
+            focused_opacity: Opacity::max(),
+            other_workspace_opacity: None,
+            urgent_opacity: None,
+            floating_opacity: None,
+            floating: HashSet::new(),
+            shown_elsewhere: HashSet::new(),
+            base_opacity: Opacity::max(),
+            min_opacity: Opacity::min(),
+            max_opacity: Opacity::max(),
+            opacity_step: config::default_opacity_step(),
+            profiles: HashMap::new(),
+            rules: Rules::default(),
+            follow_color_scheme: false,
+            color_scheme: ColorScheme::NoPreference,
+            backlight_config: None,
+            backlight: None,
+            ambient_light_config: None,
+            ambient_light: None,
+            solar_config: None,
+            solar_elevation: None,
+            battery_config: None,
+            battery: None,
+            pause_for_processes: Vec::new(),
+            process_poll: Duration::from_secs(5),
+            config_poll: Duration::from_secs(5),
+            running_watched_process: None,
+            fullscreen: Fullscreen::Off,
+            dim_floating: true,
+            hover_config: None,
+            pointer: None,
+            hovered: None,
+            min_windows: 0,
+            workspace_sizes: HashMap::new(),
+            keep_recent_opaque: 0,
+            recency: None,
+            focus_history: VecDeque::new(),
+            idle_config: None,
+            tree: None,
+            visible_workspaces: None,
+            focus_debounce: Duration::from_millis(0),
+            pending_apply: None,
+            rate_limiter: RateLimiter::new(0),
+            apply_error: None,
+            peek_until: None,
+            blacklist_expiry: HashMap::new(),
+            resume_at: None,
+            idle: false,
+            locations: HashMap::new(),
+            tcp_port: None,
+            http_port: None,
+            socket: None,
+            opacity_backend: OpacityBackend::Wm,
+            blacklist: HashSet::new(),
+            persisted_blacklist: PersistedBlacklist::default(),
+            class_blacklist: HashSet::new(),
+            criteria_blacklist: Vec::new(),
+            exempt_mark: None,
+            marked: HashSet::new(),
+            safe_mode: SafeMode::default(),
+            applier: Applier::default(),
+            fader: Fader::default(),
+            warnings: LogLimiter::default(),
+            broadcast: None,
+            script: None,
+            hooks: Hooks::default(),
+        };
+        daemon.update_config(config);
+
+        daemon
+    }
+
+    /// Loads the config again, the old one stays active if it's broken
+    fn reload(&mut self, i3_conn: &mut WmConnection) -> Result<(), config::LoadError> {
+        let config = self.config_path.load()?;
+        log::info!("Reloaded config");
+        self.update_config(config);
+        self.apply(i3_conn);
+        Ok(())
+    }
+
+    fn update_config(&mut self, config: Config) {
+        let (min, max) = (config.min_opacity, config.max_opacity);
+        let bounded = move |opacity: Opacity| opacity.clamp(min, max);
+        self.base_opacity = config.bounded_opacity();
+        self.focused_opacity = bounded(config.focused_opacity);
+        self.other_workspace_opacity = config.other_workspace_opacity.map(bounded);
+        self.urgent_opacity = config.urgent_opacity.map(bounded);
+        self.floating_opacity = config.floating_opacity.map(bounded);
+        self.min_opacity = config.min_opacity;
+        self.max_opacity = config.max_opacity;
+        if config.opacity_step_is_valid() {
+            self.opacity_step = config.opacity_step;
+        } else {
+            log::warn!(
+                "opacity_step {} is outside of (0, 1], keeping {}",
+                config.opacity_step,
+                self.opacity_step
+            );
+        }
+        self.profiles = config.profile_opacities();
+        self.rules = Rules::new(&config.rules, config.min_opacity, config.max_opacity);
+        self.follow_color_scheme = config.follow_color_scheme;
+        self.backlight_config = config.backlight;
+        self.ambient_light_config = config
+            .ambient_light
+            .map(|ambient_light| AmbientLightConfig {
+                dark_opacity: bounded(ambient_light.dark_opacity),
+                bright_opacity: bounded(ambient_light.bright_opacity),
+                ..ambient_light
+            });
+        self.solar_config = config.solar;
+        self.battery_config = config.battery;
+        self.fullscreen = config.fullscreen;
+        self.dim_floating = config.dim_floating;
+        self.min_windows = config.min_windows;
+        self.keep_recent_opaque = config.keep_recent_opaque;
+        self.recency = config.recency.map(|recency| RecencyConfig {
+            start: bounded(recency.start),
+            ..recency
+        });
+        self.focus_debounce = Duration::from_millis(config.focus_debounce_ms);
+        self.rate_limiter.set_rate(config.max_commands_per_sec);
+        self.hover_config = config.hover;
+        self.idle_config = config.idle.map(|idle| IdleConfig {
+            opacity: bounded(idle.opacity),
+            ..idle
+        });
+        self.fader.configure(config.fade.as_ref());
+        // only read at startup
+        self.tcp_port = config.tcp_port;
+        self.http_port = config.http_port;
+        self.opacity_backend = config.opacity_backend;
+        self.class_blacklist = config.blacklist.into_iter().collect();
+        self.exempt_mark = config.exempt_mark;
+        self.pause_for_processes = config.pause_for_processes;
+        self.process_poll = Duration::from_secs(config.process_poll_secs.max(1));
+        self.config_poll = Duration::from_secs(config.config_poll_secs.max(1));
+        self.script = script::load_configured(
+            config.script.as_ref().map(String::as_str),
+            &self.config_path.path(),
+        );
+        self.hooks = config.hooks;
+        self.update_transparency();
+    }
+
+    fn battery_tier(&self) -> Option<&BatteryTier> {
+        let state = self.battery?;
+        self.battery_config
+            .as_ref()?
+            .active_tier(state.percent, state.discharging)
+    }
+
+    /// Profile picked by the battery tier or color scheme, battery always wins
+    fn active_profile(&self) -> Option<&str> {
+        self.battery_tier_profile().or_else(|| {
+            if self.follow_color_scheme {
+                self.color_scheme.profile()
+            } else {
+                None
+            }
+        })
+    }
+
+    fn battery_tier_profile(&self) -> Option<&str> {
+        self.battery_tier()
+            .and_then(|tier| tier.profile.as_ref())
+            .map(String::as_str)
+    }
+
+    /// Dimming is suspended by the battery tier
+    fn dimming_disabled_by_battery(&self) -> bool {
+        self.battery_tier()
+            .map_or(false, BatteryTier::disables_dimming)
+    }
+
+    /// Whether window events and config reloads are needed at all
+    fn wants_events(&self) -> bool {
+        self.state.is_enabled()
+            && !self
+                .battery_tier()
+                .map_or(false, |tier| tier.suspend_events)
+    }
+
+    fn profile_opacity(&self, name: &str) -> Opacity {
+        self.profiles
+            .get(name)
+            .cloned()
+            .unwrap_or(self.base_opacity)
+    }
+
+    fn handle_reading(&mut self, reading: Reading) {
+        log::debug!("Sensor reading: {:?}", reading);
+        match reading {
+            Reading::ColorScheme(scheme) => self.color_scheme = scheme,
+            Reading::Backlight(brightness) => self.backlight = Some(brightness),
+            Reading::AmbientLight(lux) => self.ambient_light = Some(lux),
+            Reading::SolarElevation(elevation) => self.solar_elevation = Some(elevation),
+            Reading::Battery(state) => self.battery = Some(state),
+            Reading::WatchedProcess(name) => self.running_watched_process = name,
+            Reading::Pointer(pos) => self.pointer = pos,
+            Reading::Idle(idle) => self.set_idle(idle),
+        }
+        self.update_transparency();
+    }
+
+    fn update_transparency(&mut self) {
+        let profile = self.active_profile().map(str::to_owned);
+        let opacity = match (
+            self.battery_tier_profile(),
+            (&self.ambient_light_config, self.ambient_light),
+            (&self.solar_config, self.solar_elevation),
+        ) {
+            (Some(name), _, _) => self.profile_opacity(name),
+            (_, (Some(config), Some(lux)), _) => config.opacity(lux),
+            (_, _, (Some(config), Some(elevation))) => self
+                .profile_opacity("night")
+                .lerp(self.profile_opacity("day"), config.day_fraction(elevation)),
+            _ => profile
+                .as_ref()
+                .map(|name| self.profile_opacity(name))
+                .unwrap_or(self.base_opacity),
+        };
+        self.transparency = match (&self.backlight_config, self.backlight) {
+            (Some(config), Some(brightness)) => opacity.scale_dim(config.dim_scale(brightness)),
+            _ => opacity,
+        };
+        log::debug!(
+            "Using opacity {} (profile {:?})",
+            self.transparency,
+            profile
+        );
+    }
+
+    /// Why every window should be opaque right now even though transparency is enabled.
+    /// Fullscreen windows are looked up in the cached tree, it has to be fetched before.
+    fn pause_reason(&self) -> Option<String> {
+        if self.dimming_disabled_by_battery() {
+            return Some("battery low".to_owned());
+        }
+        if let Some(ref name) = self.running_watched_process {
+            return Some(format!("{} is running", name));
+        }
+        let fullscreen = self.tree.as_ref().map_or(false, Tree::has_fullscreen);
+        match self.fullscreen {
+            Fullscreen::Off => None,
+            _ if !fullscreen => None,
+            Fullscreen::Pause => Some("fullscreen window exists".to_owned()),
+            Fullscreen::Suspend => Some(SUSPENDED_WHILE_FULLSCREEN.to_owned()),
+        }
+    }
+
+    fn make_unfocused_windows_transparent(
+        &mut self,
+        i3_conn: &mut WmConnection,
+    ) -> Result<(), ApplyError> {
+        if !self.state.is_enabled() {
+            return Ok(());
+        }
+
+        let mut tree = self.tree(i3_conn)?;
+        let reason = self.pause_reason();
+        if reason.as_deref() == Some(SUSPENDED_WHILE_FULLSCREEN) {
+            if self.state.set_paused(reason) {
+                log::info!("Transparency {}", self.state);
+                self.fader.stop();
+                return remove_all_transparency(&mut self.applier, i3_conn);
+            }
+            // nothing gets sent until the fullscreen window is gone
+            return Ok(());
+        }
+        i3::focus_descendants(&mut tree);
+        self.hovered = match self.pointer {
+            Some((x, y)) if self.hover_config.is_some() => {
+                let visible = self.visible_workspaces(i3_conn)?;
+                i3::window_at(&tree, &visible, x, y)
+            }
+            _ => None,
+        };
+        if self.applier.uses_x11() {
+            self.applier.track_x11_windows(i3::x11_windows(&tree));
+        }
+        self.locations = if self.tracks_locations() {
+            i3::locations(&tree)
+        } else {
+            HashMap::new()
+        };
+        self.shown_elsewhere = if self.other_workspace_opacity.is_some() {
+            self.shown_elsewhere(i3_conn, &tree)?
+        } else {
+            HashSet::new()
+        };
+        self.workspace_sizes = if self.min_windows > 1 {
+            i3::workspace_sizes(&self.locations)
+        } else {
+            HashMap::new()
+        };
+        self.floating = if self.floating_opacity.is_some() {
+            i3::floating_windows(&tree)
+        } else {
+            HashSet::new()
+        };
+        self.marked = self.marked_windows();
+        let windows = if self.dim_floating {
+            AllWindows::new(tree)
+        } else {
+            AllWindows::new(tree).without_floating()
+        };
+        let targets = self.targets(windows, reason);
+        let applier = &self.applier;
+        let frame = self.fader.retarget(targets, |id| applier.previous(id));
+
+        self.applier.set_opacity(i3_conn, frame)
+    }
+
+    /// Opacity each window should get while enabled, blacklisted unfocused windows are left
+    /// alone. Also enters or leaves the paused state and prunes the blacklist.
+    fn targets<I>(&mut self, windows: I, pause_reason: Option<String>) -> Vec<(i64, Opacity)>
+    where
+        I: IntoIterator<Item = Node>,
+    {
+        if self.state.set_paused(pause_reason) {
+            log::info!("Transparency {}", self.state);
+        }
+        if self.state.pause_reason().is_some() || self.peek_until.is_some() {
+            return windows
+                .into_iter()
+                .map(|node| (node.id, Opacity::max()))
+                .collect();
+        }
+
+        let idle_opacity = match self.idle_config {
+            Some(ref config) if self.idle => Some(config.opacity),
+            _ => None,
+        };
+
+        let mut targets = Vec::with_capacity(PROBABLE_AMOUNT_OF_WINDOWS);
+        let mut live = HashSet::with_capacity(PROBABLE_AMOUNT_OF_WINDOWS);
+        for node in windows {
+            live.insert(node.id);
+            if !self.blacklist.contains(&node.id) && self.blacklisted_by_key(&node) {
+                self.blacklist.insert(node.id);
+            }
+            if self.marked.contains(&node.id) {
+                targets.push((node.id, Opacity::max()));
+            } else if let Some(opacity) = idle_opacity {
+                if node.focused || !self.blacklist.contains(&node.id) {
+                    targets.push((node.id, opacity));
+                }
+            } else if node.focused {
+                self.note_focus(node.id);
+                targets.push((node.id, self.window_opacity(&node, true)));
+            } else if !self.blacklist.contains(&node.id) {
+                let revealed = self.revealed(node.id);
+                targets.push((node.id, self.window_opacity(&node, revealed)));
+            }
+        }
+        self.prune_blacklist(&live);
+        self.focus_history.retain(|id| live.contains(id));
+
+        targets
+    }
+
+    fn tree(&mut self, i3_conn: &mut WmConnection) -> Result<Node, i3ipc::MessageError> {
+        match self.tree {
+            Some(ref tree) => Ok(tree.root.clone()),
+            None => {
+                let tree = i3_conn.get_tree()?;
+                let root = tree.root.clone();
+                self.tree = Some(tree);
+                Ok(root)
+            }
+        }
+    }
+
+    fn visible_workspaces(
+        &mut self,
+        i3_conn: &mut WmConnection,
+    ) -> Result<HashSet<String>, i3ipc::MessageError> {
+        if let Some(ref visible) = self.visible_workspaces {
+            return Ok(visible.clone());
+        }
+        let visible: HashSet<_> = i3_conn
+            .get_workspaces()?
+            .workspaces
+            .into_iter()
+            .filter(|workspace| workspace.visible)
+            .map(|workspace| workspace.name)
+            .collect();
+        self.visible_workspaces = Some(visible.clone());
+        Ok(visible)
+    }
+
+    /// Visible workspaces other than the focused one, i.e. the ones shown on other outputs
+    fn shown_elsewhere(
+        &mut self,
+        i3_conn: &mut WmConnection,
+        tree: &Node,
+    ) -> Result<HashSet<String>, i3ipc::MessageError> {
+        let mut visible = self.visible_workspaces(i3_conn)?;
+        if let Some(focused) = i3::focused_workspace(tree, &self.locations) {
+            visible.remove(&focused);
+        }
+        Ok(visible)
+    }
+
+    /// Windows carrying `exempt_mark` in the cached tree
+    fn marked_windows(&self) -> HashSet<i64> {
+        match (&self.exempt_mark, &self.tree) {
+            (Some(mark), Some(tree)) => tree.marked(mark),
+            _ => HashSet::new(),
+        }
+    }
+
+    fn focus_changed(&mut self, id: i64) {
+        if let Some(ref mut tree) = self.tree {
+            if !i3::set_focused(&mut tree.root, id) {
+                self.tree = None;
+            }
+        }
+        self.note_focus(id);
+    }
+
+    /// Reapplies what the move changed. A window shown from the scratchpad might not get a focus
+    /// event after the move, it counts as focused if the container sent along says so.
+    fn window_moved(&mut self, i3_conn: &mut WmConnection, id: i64, container: Option<Box<Node>>) {
+        let shown = self
+            .tree
+            .as_ref()
+            .map_or(false, |tree| i3::in_scratchpad(&tree.root, id));
+        self.tree = None;
+        if shown {
+            if container.map_or(false, |container| container.focused) && self.tree(i3_conn).is_ok()
+            {
+                self.focus_changed(id);
+            }
+            self.apply(i3_conn);
+        } else if self.min_windows > 1 {
+            // the window count changes on both workspaces
+            self.apply(i3_conn);
+        } else if self.tracks_locations() {
+            self.reapply_window(i3_conn, id);
+        }
+    }
+
+    /// Takes over title and urgency of a window from the container sent along with its event
+    fn window_changed(&mut self, container: Option<Box<Node>>) {
+        let updated = match (&mut self.tree, container) {
+            (Some(tree), Some(container)) => i3::update_window(&mut tree.root, &container),
+            _ => false,
+        };
+        if !updated {
+            self.tree = None;
+        }
+    }
+
+    /// Moves the focus into the workspace sent along with the focus event, to the workspace
+    /// itself if it's empty
+    fn workspace_focused(&mut self, current: Option<Box<Node>>) {
+        self.visible_workspaces = None;
+        let focused = match (&mut self.tree, current) {
+            (Some(tree), Some(workspace)) => {
+                let id = i3::focused_within(&workspace).unwrap_or(workspace.id);
+                i3::set_focused(&mut tree.root, id)
+            }
+            _ => false,
+        };
+        if !focused {
+            self.tree = None;
+        }
+    }
+
+    /// Moves the window to the front of the focus history
+    fn note_focus(&mut self, id: i64) {
+        if self.focus_history.front() == Some(&id) {
+            return;
+        }
+        self.focus_history.retain(|&other| other != id);
+        self.focus_history.push_front(id);
+        if self.recency.is_none() {
+            self.focus_history.truncate(self.keep_recent_opaque);
+        }
+    }
+
+    /// Whether an unfocused window is shown like the focused one, because the pointer rests on
+    /// it, it was focused recently or its workspace has too few windows for dimming
+    fn revealed(&self, id: i64) -> bool {
+        self.hovered == Some(id)
+            || self
+                .focus_history
+                .iter()
+                .take(self.keep_recent_opaque)
+                .any(|&recent| recent == id)
+            || self.on_sparse_workspace(id)
+    }
+
+    fn on_sparse_workspace(&self, id: i64) -> bool {
+        if self.min_windows <= 1 {
+            return false;
+        }
+        self.locations
+            .get(&id)
+            .and_then(|location| location.workspace.as_ref())
+            .map_or(false, |workspace| {
+                self.workspace_sizes.get(workspace).cloned().unwrap_or(0) < self.min_windows
+            })
+    }
+
+    fn window_closed(&mut self, id: i64) {
+        if let Some(ref mut tree) = self.tree {
+            tree.remove_window(id);
+            // on Hyprland the workspace stays flagged, the closed window might have been the
+            // fullscreen one
+            if tree.has_fullscreen() {
+                self.tree = None;
+            }
+        }
+        log::debug!("Want to remove {} from blacklist", id);
+        log::debug!("Blacklist: {:?}", self.blacklist);
+        self.blacklist.remove(&id);
+        self.blacklist_expiry.remove(&id);
+        self.focus_history.retain(|&other| other != id);
+        self.applier.forget(id);
+        self.fader.forget(id);
+    }
+
+    /// Whether workspace or output of windows can change their opacity
+    fn tracks_locations(&self) -> bool {
+        self.rules.match_locations()
+            || self.other_workspace_opacity.is_some()
+            || self.min_windows > 1
+    }
+
+    /// Opacity of an unfocused window before rules and scripts, graded by focus recency if
+    /// configured
+    fn unfocused_opacity(&self, node: &Node) -> Opacity {
+        let opacity = self.tier_opacity(node);
+        // the focused window and the ones kept opaque come before the graded ones
+        let skipped = self.keep_recent_opaque.max(1);
+        let rank = self
+            .focus_history
+            .iter()
+            .position(|&id| id == node.id)
+            .filter(|&position| position >= skipped)
+            .map(|position| position - skipped);
+        match (&self.recency, rank) {
+            (Some(recency), Some(rank)) => recency
+                .start
+                .saturating_add(-recency.step * rank as f64)
+                .max(opacity),
+            _ => opacity,
+        }
+    }
+
+    /// Opacity of an unfocused window by the first matching tier
+    fn tier_opacity(&self, node: &Node) -> Opacity {
+        if let Some(opacity) = self.urgent_opacity.filter(|_| node.urgent) {
+            return opacity;
+        }
+        if let Some(opacity) = self
+            .floating_opacity
+            .filter(|_| self.floating.contains(&node.id))
+        {
+            return opacity;
+        }
+        let shown_elsewhere = self
+            .locations
+            .get(&node.id)
+            .and_then(|location| location.workspace.as_ref())
+            .map_or(false, |workspace| self.shown_elsewhere.contains(workspace));
+        match self.other_workspace_opacity {
+            Some(opacity) if shown_elsewhere => opacity,
+            _ => self.transparency,
+        }
+    }
+
+    fn window_opacity(&mut self, node: &Node, focused: bool) -> Opacity {
+        let fallback = if focused {
+            self.focused_opacity
+        } else {
+            self.unfocused_opacity(node)
+        };
+        let ctx = script::Context {
+            focused,
+            default: if self.rules.is_empty() {
+                fallback
+            } else {
+                let mut info = WindowInfo::from_node(node);
+                if let Some(location) = self.locations.get(&node.id) {
+                    info.workspace = location.workspace.clone();
+                    info.output = location.output.clone();
+                }
+                self.rules.opacity_for(&info, focused).unwrap_or(fallback)
+            },
+        };
+        script::opacity_for(self.script.as_ref(), node, &ctx).unwrap_or_else(|e| {
+            self.warnings.warn(format_args!("Script error: {}", e));
+            ctx.default
+        })
+    }
+
+    /// Whether the window's class is blacklisted in the config, it was persisted with
+    /// focus-blacklist or matches criteria given to blacklist
+    fn blacklisted_by_key(&self, node: &Node) -> bool {
+        if self.class_blacklist.is_empty()
+            && self.persisted_blacklist.is_empty()
+            && self.criteria_blacklist.is_empty()
+        {
+            return false;
+        }
+        let info = WindowInfo::from_node(node);
+        info.class
+            .as_ref()
+            .map_or(false, |class| self.class_blacklist.contains(class))
+            || self.persisted_blacklist.contains(&WindowKey::from(&info))
+            || self
+                .criteria_blacklist
+                .iter()
+                .any(|criteria| criteria.matches(&info))
+    }
+
+    /// Adds windows to the blacklist, they're left alone from now on so their dimming is undone
+    /// right away
+    fn blacklist_windows(&mut self, i3_conn: &mut WmConnection, ids: Vec<i64>) {
+        for &id in &ids {
+            self.blacklist.insert(id);
+            self.fader.forget(id);
+        }
+        let res = self
+            .applier
+            .set_opacity(i3_conn, ids.into_iter().map(|id| (id, Opacity::max())));
+        self.track_apply_result(i3_conn, res);
+    }
+
+    /// Drops blacklisted con_ids that aren't in the tree anymore.
+    /// Close events can get lost (e.g. across i3 restarts) so this is done on every full traversal.
+    fn prune_blacklist(&mut self, live: &HashSet<i64>) {
+        let before = self.blacklist.len();
+        self.blacklist.retain(|id| live.contains(id));
+        self.blacklist_expiry.retain(|id, _| live.contains(id));
+        if self.blacklist.len() != before {
+            log::debug!(
+                "Pruned {} dead windows from blacklist",
+                before - self.blacklist.len()
+            );
+        }
+    }
+
+    /// Drops temporary blacklist entries that ran out
+    fn expire_blacklist(&mut self, i3_conn: &mut WmConnection) {
+        let now = Instant::now();
+        let blacklist = &mut self.blacklist;
+        self.blacklist_expiry.retain(|id, until| {
+            let expired = *until <= now;
+            if expired {
+                log::debug!("Temporary blacklist entry of {} expired", id);
+                blacklist.remove(id);
+            }
+            !expired
+        });
+        self.apply(i3_conn);
+    }
+
+    fn apply(&mut self, i3_conn: &mut WmConnection) {
+        let now = Instant::now();
+        if let Err(wait) = self.rate_limiter.acquire(now) {
+            // catch up on everything at once when the limit allows it again
+            self.pending_apply = Some(now + wait);
+            self.warnings
+                .warn("Exceeding max_commands_per_sec, delaying opacity changes");
+            return;
+        }
+        self.pending_apply = None;
+        let res = self.make_unfocused_windows_transparent(i3_conn);
+        self.track_apply_result(i3_conn, res);
+    }
+
+    /// Evaluates rules again for a window or every window in a container, e.g. after its title
+    /// changed or it moved to another workspace
+    fn reapply_window(&mut self, i3_conn: &mut WmConnection, id: i64) {
+        let uniform = self.state.pause_reason().is_some() || self.peek_until.is_some() || self.idle;
+        if !self.state.is_enabled() || uniform {
+            // every window gets the same opacity, the change can't matter
+            return;
+        }
+        if self.rate_limiter.acquire(Instant::now()).is_err() {
+            self.apply(i3_conn);
+            return;
+        }
+        let res = self.set_window_target(i3_conn, id);
+        self.track_apply_result(i3_conn, res);
+    }
+
+    fn set_window_target(&mut self, i3_conn: &mut WmConnection, id: i64) -> Result<(), ApplyError> {
+        let mut tree = self.tree(i3_conn)?;
+        i3::focus_descendants(&mut tree);
+        if self.tracks_locations() {
+            self.locations = i3::locations(&tree);
+        }
+        if self.other_workspace_opacity.is_some() {
+            self.shown_elsewhere = self.shown_elsewhere(i3_conn, &tree)?;
+        }
+        if self.min_windows > 1 {
+            self.workspace_sizes = i3::workspace_sizes(&self.locations);
+        }
+        if self.floating_opacity.is_some() {
+            self.floating = i3::floating_windows(&tree);
+        }
+        self.marked = self.marked_windows();
+        let within = i3::ids_within(&tree, id);
+        let windows = if self.dim_floating {
+            AllWindows::new(tree)
+        } else {
+            AllWindows::new(tree).without_floating()
+        };
+
+        let mut targets = Vec::new();
+        for node in windows.filter(|node| within.contains(&node.id)) {
+            if !self.blacklist.contains(&node.id) && self.blacklisted_by_key(&node) {
+                self.blacklist.insert(node.id);
+            }
+            if self.marked.contains(&node.id) {
+                targets.push((node.id, Opacity::max()));
+                continue;
+            }
+            if !node.focused && self.blacklist.contains(&node.id) {
+                continue;
+            }
+            let revealed = node.focused || self.revealed(node.id);
+            targets.push((node.id, self.window_opacity(&node, revealed)));
+        }
+        let applier = &self.applier;
+        let frame = self.fader.retarget(targets, |id| applier.previous(id));
+        self.applier.set_opacity(i3_conn, frame)
+    }
+
+    /// Next step of running fades
+    fn fade(&mut self, i3_conn: &mut WmConnection) {
+        // the frame stays due until the next tick
+        if self.rate_limiter.acquire(Instant::now()).is_err() {
+            return;
+        }
+        let frame = self.fader.frame();
+        let res = self.applier.set_opacity(i3_conn, frame);
+        self.track_apply_result(i3_conn, res);
+    }
+
+    fn restore(&mut self, i3_conn: &mut WmConnection) {
+        self.fader.stop();
+        let res = remove_all_transparency(&mut self.applier, i3_conn);
+        self.track_apply_result(i3_conn, res);
+    }
+
+    fn track_apply_result(&mut self, i3_conn: &mut WmConnection, res: Result<(), ApplyError>) {
+        if let Err(e) = res {
+            self.apply_error = Some(e.to_string());
+            self.warnings
+                .warn(format_args!("Failed to apply opacity: {}", e));
+            self.hooks
+                .run(Hook::ApplyError, &[("TRANSPARENTD_ERROR", e.to_string())]);
+            // failing windows get skipped by the applier, only a broken connection is fatal
+            let window_failure = matches!(e, ApplyError::Partial { .. });
+            if !window_failure && self.safe_mode.record_failure(Instant::now()) {
+                self.enter_safe_mode(i3_conn);
+            }
+        }
+    }
+
+    fn enter_safe_mode(&mut self, i3_conn: &mut WmConnection) {
+        log::error!("Applying opacity failed repeatedly, disabling transparency");
+        self.state.disable();
+        self.resume_at = None;
+        self.fader.stop();
+        if let Err(e) = remove_all_transparency(&mut self.applier, i3_conn) {
+            log::error!("Can't restore opacity: {}", e);
+        }
+        safe_mode::notify_user(
+            "transparentd disabled",
+            "Applying opacity failed repeatedly, run `transparentd enable` to retry",
+        );
+    }
+
+    fn leave_safe_mode(&mut self) {
+        if self.safe_mode.is_degraded() {
+            log::info!("Leaving safe mode");
+        }
+        self.safe_mode.reset();
+    }
+
+    /// Sends a tick like `transparentd:enabled=false` for every changed value so bars and scripts
+    /// can follow the state without speaking transparentd's protocol and runs the matching hooks.
+    fn broadcast_state(&mut self, i3_conn: &mut WmConnection) {
+        let state = BroadcastState {
+            enabled: self.state.is_enabled(),
+            paused: self.state.pause_reason().map(str::to_owned),
+            opacity: self.transparency,
+            degraded: self.safe_mode.is_degraded(),
+            skipped: self.applier.skipped().len(),
+        };
+        let prev = self.broadcast.replace(state.clone());
+        if prev.as_ref() == Some(&state) {
+            return;
+        }
+
+        let mut payloads = Vec::with_capacity(5);
+        if prev.as_ref().map(|prev| prev.enabled) != Some(state.enabled) {
+            payloads.push(format!("{}:enabled={}", TICK_PREFIX, state.enabled));
+        }
+        if prev.as_ref().map(|prev| &prev.paused) != Some(&state.paused) {
+            // empty when running normally
+            payloads.push(format!(
+                "{}:paused={}",
+                TICK_PREFIX,
+                state.paused.as_ref().map(String::as_str).unwrap_or("")
+            ));
+        }
+        if prev.as_ref().map(|prev| prev.opacity) != Some(state.opacity) {
+            payloads.push(format!("{}:opacity={}", TICK_PREFIX, state.opacity));
+        }
+        if prev.as_ref().map(|prev| prev.degraded) != Some(state.degraded) {
+            payloads.push(format!("{}:degraded={}", TICK_PREFIX, state.degraded));
+        }
+        if prev.as_ref().map(|prev| prev.skipped) != Some(state.skipped) {
+            payloads.push(format!("{}:skipped={}", TICK_PREFIX, state.skipped));
+        }
+
+        if let Some(prev) = prev {
+            if prev.enabled != state.enabled {
+                let hook = if state.enabled {
+                    Hook::Enable
+                } else {
+                    Hook::Disable
+                };
+                self.hooks.run(hook, &[]);
+            }
+            if prev.opacity != state.opacity {
+                self.hooks.run(
+                    Hook::ProfileChange,
+                    &[("TRANSPARENTD_OPACITY", state.opacity.to_string())],
+                );
+            }
+        }
+
+        for payload in payloads {
+            if let Err(e) = i3_conn.send_tick(&payload) {
+                self.warnings
+                    .warn(format_args!("Can't send tick {:?}: {}", payload, e));
+            }
+        }
+    }
+
+    fn status(&self) -> Status {
+        let mut blacklisted: Vec<_> = self.blacklist.iter().cloned().collect();
+        blacklisted.sort();
+        Status {
+            state: self.state.name().to_owned(),
+            pause_reason: self.state.pause_reason().map(str::to_owned),
+            opacity: self.transparency,
+            degraded: self.safe_mode.is_degraded(),
+            blacklisted,
+            skipped: self.applier.skipped(),
+            failing: self.applier.failing(),
+        }
+    }
+
+    fn answer(&self, query: Query) -> Answer {
+        match query {
+            Query::State => Answer::State {
+                state: self.state.name().to_owned(),
+                pause_reason: self.state.pause_reason().map(str::to_owned),
+            },
+            Query::Opacity => Answer::Opacity(self.transparency),
+            Query::Blacklist => {
+                let mut blacklisted: Vec<_> = self.blacklist.iter().cloned().collect();
+                blacklisted.sort();
+                Answer::Blacklist(blacklisted)
+            }
+            Query::Rules => Answer::Rules(self.rules.describe()),
+        }
+    }
+
+    fn list_blacklist(
+        &mut self,
+        i3_conn: &mut WmConnection,
+    ) -> Result<Answer, i3ipc::MessageError> {
+        let windows = i3_conn.get_window_infos()?;
+        let live = windows.iter().map(|window| window.id).collect();
+        self.prune_blacklist(&live);
+        let mut windows: Vec<_> = windows
+            .into_iter()
+            .filter(|window| self.blacklist.contains(&window.id))
+            .map(|window| BlacklistEntry {
+                id: window.id,
+                class: window.class,
+                title: window.title,
+            })
+            .collect();
+        windows.sort_by_key(|window| window.id);
+        Ok(Answer::BlacklistEntries {
+            windows,
+            criteria: self
+                .criteria_blacklist
+                .iter()
+                .map(ToString::to_string)
+                .collect(),
+        })
+    }
+
+    fn handle_cmd(&mut self, i3_conn: &mut WmConnection, cmd: Cmd) -> Result<(), Error> {
+        if let Cmd::Enable | Cmd::Disable | Cmd::Toggle = cmd {
+            self.resume_at = None;
+        }
+        match cmd {
+            Cmd::Pause { duration } => {
+                self.state.disable();
+                self.restore(i3_conn);
+                self.resume_at = Some(Instant::now() + duration);
+            }
+            Cmd::Disable => {
+                self.state.disable();
+                self.restore(i3_conn);
+            }
+            Cmd::Enable => {
+                self.state.enable();
+                self.leave_safe_mode();
+                self.apply(i3_conn);
+            }
+            Cmd::Toggle => {
+                self.state.toggle();
+                if self.state.is_enabled() {
+                    self.leave_safe_mode();
+                    self.apply(i3_conn);
+                } else {
+                    self.restore(i3_conn);
+                }
+            }
+            Cmd::FocusBlacklist { duration } => {
+                let windows = i3_conn.get_window_infos()?;
+                let live = windows.iter().map(|window| window.id).collect();
+                self.prune_blacklist(&live);
+                if let Some(focused) = windows.iter().find(|window| window.focused) {
+                    self.blacklist.insert(focused.id);
+                    match duration {
+                        Some(duration) => {
+                            self.blacklist_expiry
+                                .insert(focused.id, Instant::now() + duration);
+                        }
+                        None => {
+                            self.blacklist_expiry.remove(&focused.id);
+                            self.persisted_blacklist.insert(WindowKey::from(focused));
+                        }
+                    }
+                }
+            }
+            Cmd::FocusBlacklistRemove => {
+                let windows = i3_conn.get_window_infos()?;
+                if let Some(focused) = windows.iter().find(|window| window.focused) {
+                    self.blacklist.remove(&focused.id);
+                    self.blacklist_expiry.remove(&focused.id);
+                    self.persisted_blacklist.remove(&WindowKey::from(focused));
+                }
+            }
+            Cmd::FocusBlacklistToggle => {
+                let windows = i3_conn.get_window_infos()?;
+                if let Some(focused) = windows.iter().find(|window| window.focused) {
+                    if self.blacklist.remove(&focused.id) {
+                        self.blacklist_expiry.remove(&focused.id);
+                        self.persisted_blacklist.remove(&WindowKey::from(focused));
+                    } else {
+                        self.blacklist.insert(focused.id);
+                        self.persisted_blacklist.insert(WindowKey::from(focused));
+                    }
+                }
+            }
+            Cmd::Blacklist { criteria } => {
+                let matching = i3_conn
+                    .get_window_infos()?
+                    .iter()
+                    .filter(|window| criteria.matches(window))
+                    .map(|window| window.id)
+                    .collect();
+                if !self.criteria_blacklist.contains(&criteria) {
+                    self.criteria_blacklist.push(criteria);
+                }
+                self.blacklist_windows(i3_conn, matching);
+            }
+            Cmd::Unblacklist { criteria } => {
+                self.criteria_blacklist.retain(|other| *other != criteria);
+                let windows = i3_conn.get_window_infos()?;
+                for window in windows.iter().filter(|window| criteria.matches(window)) {
+                    self.blacklist.remove(&window.id);
+                    self.persisted_blacklist.remove(&WindowKey::from(window));
+                }
+                self.apply(i3_conn);
+            }
+            Cmd::BlacklistClear => {
+                self.blacklist.clear();
+                self.blacklist_expiry.clear();
+                self.persisted_blacklist.clear();
+                self.criteria_blacklist.clear();
+                self.apply(i3_conn);
+            }
+            Cmd::BlacklistId { id } => {
+                if i3_conn.iter_windows()?.any(|node| node.id == id) {
+                    self.blacklist_windows(i3_conn, vec![id]);
+                } else {
+                    self.apply_error = Some(format!("No window with con_id {}", id));
+                }
+            }
+            Cmd::UnblacklistId { id } => {
+                let windows = i3_conn.get_window_infos()?;
+                if let Some(window) = windows.iter().find(|window| window.id == id) {
+                    self.persisted_blacklist.remove(&WindowKey::from(window));
+                }
+                self.blacklist.remove(&id);
+                self.blacklist_expiry.remove(&id);
+                self.apply(i3_conn);
+            }
+            Cmd::SetOpacity { opacity } => {
+                self.base_opacity = opacity.clamp(self.min_opacity, self.max_opacity);
+                self.update_transparency();
+                self.apply(i3_conn);
+            }
+            Cmd::OpacityUp | Cmd::OpacityDown => {
+                let delta = match cmd {
+                    Cmd::OpacityUp => self.opacity_step,
+                    _ => -self.opacity_step,
+                };
+                self.base_opacity =
+                    self.base_opacity
+                        .step(delta, self.min_opacity, self.max_opacity);
+                self.update_transparency();
+                self.apply(i3_conn);
+            }
+            Cmd::Peek { duration } => {
+                self.peek_until = Some(Instant::now() + duration);
+                self.apply(i3_conn);
+            }
+            Cmd::Idle | Cmd::Active => {
+                self.set_idle(matches!(cmd, Cmd::Idle));
+                self.apply(i3_conn);
+            }
+            Cmd::Subscribe { .. } | Cmd::Mangen { .. } | Cmd::Config { .. } => {
+                log::warn!("Received client side command {:?}", cmd);
+            }
+            Cmd::Status { .. } | Cmd::Get { .. } | Cmd::BlacklistList { .. } => {
+                // answered directly to ipc clients, nothing to do for ticks
+            }
+            Cmd::Quit => {
+                // stops the event loop before getting here
+            }
+            Cmd::Reload => {
+                if let Err(e) = self.reload(i3_conn) {
+                    log::warn!("Can't reload config: {}", e);
+                }
+            }
+            Cmd::ResendState => {
+                // everything gets broadcast again next loop iteration
+                self.broadcast = None;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn set_idle(&mut self, idle: bool) {
+        if idle && self.idle_config.is_none() {
+            log::warn!("Got idle without an [idle] section in the config, nothing to dim to");
+        }
+        if self.idle != idle {
+            log::info!("Session {}", if idle { "idle" } else { "active" });
+            self.idle = idle;
+        }
+    }
+
+    /// Returns false if the daemon should exit
+    fn handle_disconnect(
+        &mut self,
+        disconnect: Disconnect,
+        workers: &mut Workers,
+        sources: &mut EventSources,
+        i3_conn: &mut WmConnection,
+    ) -> Result<bool, Error> {
+        match disconnect {
+            Disconnect::Exit => return Ok(false),
+            Disconnect::Restart => log::info!("Window manager restarts, reconnecting"),
+            Disconnect::HungUp => log::warn!("Lost connection to the window manager, reconnecting"),
+        }
+
+        *i3_conn = reconnect()?;
+        sources.reconnect(workers)?;
+        self.reconnected(i3_conn);
+
+        Ok(true)
+    }
+
+    /// Forgets everything learned from the previous connection and applies from scratch
+    fn reconnected(&mut self, i3_conn: &mut WmConnection) {
+        // con_ids don't survive a restart
+        self.applier.reset();
+        self.fader.stop();
+        self.broadcast = None;
+        self.tree = None;
+        self.visible_workspaces = None;
+        self.apply(i3_conn);
+    }
+
+    fn run(&mut self) -> Result<(), Error> {
+        let mut workers = Workers::new();
+        // the receivers get dropped when the loop returns, workers can't block on sending
+        // anymore after that
+        let res = self.event_loop(&mut workers);
+        systemd::notify("STOPPING=1");
+        log::debug!("Stopping worker threads");
+        workers.stop();
+        res
+    }
+
+    fn event_loop(&mut self, workers: &mut Workers) -> Result<(), Error> {
+        let mut i3_conn = wm::connect().context(I3Connect)?;
+        if self.opacity_backend == OpacityBackend::X11 {
+            match X11Opacity::connect() {
+                Ok(x11) => self.applier.use_x11(x11),
+                Err(e) => log::warn!(
+                    "Can't set opacity through X11, using the window manager instead: {}",
+                    e
+                ),
+            }
+        }
+
+        let (mut sources, i3_event, shutdown, config_reload) =
+            EventSources::spawn(workers, self.config_path.clone(), self.config_poll)?;
+        let ipc = spawn_ipc_thread(workers, self.tcp_port, self.http_port, self.socket.clone())?;
+        let (signals, hangup) = spawn_signal_thread(workers)?;
+        systemd::notify("READY=1");
+
+        let (mut sensors, readings) = Sensors::new();
+
+        log::debug!("Starting event loop");
+        loop {
+            if self.follow_color_scheme {
+                sensors.watch_color_scheme();
+            }
+            if let Some(ref config) = self.backlight_config {
+                sensors.watch_backlight(config);
+            }
+            if let Some(ref config) = self.ambient_light_config {
+                sensors.watch_ambient_light(config);
+            }
+            if let Some(ref config) = self.solar_config {
+                sensors.watch_sun(config);
+            }
+            if let Some(ref config) = self.battery_config {
+                sensors.watch_battery(config);
+            }
+            if !self.pause_for_processes.is_empty() {
+                sensors.watch_processes(&self.pause_for_processes, self.process_poll);
+            }
+            if let Some(ref config) = self.hover_config {
+                sensors.watch_pointer(config);
+            }
+            if let Some(ref config) = self.idle_config {
+                sensors.watch_idle(config);
+            }
+            // select! picks randomly between ready channels, check shutdown first so a
+            // flood of other events can't delay it
+            if let Ok(disconnect) = shutdown.try_recv() {
+                if !self.handle_disconnect(disconnect, workers, &mut sources, &mut i3_conn)? {
+                    return Ok(());
+                }
+            }
+            if !self.wants_events() {
+                // window events get missed while the listener is gone
+                self.tree = None;
+                self.visible_workspaces = None;
+            }
+            sources.set_active(workers, self.wants_events())?;
+            self.broadcast_state(&mut i3_conn);
+            let fade_tick = self.fader.ticks();
+            let debounce_end = match self.pending_apply {
+                Some(at) => chan::after(at.saturating_duration_since(Instant::now())),
+                None => chan::never(),
+            };
+            let peek_end = match self.peek_until {
+                Some(until) => chan::after(until.saturating_duration_since(Instant::now())),
+                None => chan::never(),
+            };
+            let resume = match self.resume_at {
+                Some(at) => chan::after(at.saturating_duration_since(Instant::now())),
+                None => chan::never(),
+            };
+            let blacklist_expiry = match self.blacklist_expiry.values().min() {
+                Some(until) => chan::after(until.saturating_duration_since(Instant::now())),
+                None => chan::never(),
+            };
+            select! {
+                recv(shutdown) -> disconnect => {
+                    // never disconnects, `sources` holds a sender
+                    let disconnect = disconnect.unwrap();
+                    if !self.handle_disconnect(disconnect, workers, &mut sources, &mut i3_conn)? {
+                        return Ok(());
+                    }
+                }
+                recv(signals) -> signal => {
+                    log::info!("Received signal {:?}, restoring opacity", signal);
+                    self.restore(&mut i3_conn);
+                    return Ok(());
+                }
+                recv(hangup) -> _ => {
+                    if let Err(e) = self.reload(&mut i3_conn) {
+                        log::warn!("Can't reload config: {}", e);
+                    }
+                }
+                recv(debounce_end) -> _ => {
+                    self.apply(&mut i3_conn);
+                }
+                recv(peek_end) -> _ => {
+                    self.peek_until = None;
+                    self.apply(&mut i3_conn);
+                }
+                recv(resume) -> _ => {
+                    log::info!("Pause is over, enabling transparency");
+                    self.handle_cmd(&mut i3_conn, Cmd::Enable)?;
+                }
+                recv(blacklist_expiry) -> _ => {
+                    self.expire_blacklist(&mut i3_conn);
+                }
+                recv(fade_tick) -> _ => {
+                    self.fade(&mut i3_conn);
+                }
+                recv(config_reload) -> config => {
+                    let config = config.expect("config reload thread died");
+                    self.update_config(config);
+                    self.apply(&mut i3_conn);
+                }
+                recv(readings) -> reading => {
+                    // never disconnects, `sensors` holds a sender
+                    let reading = reading.unwrap();
+                    self.handle_reading(reading);
+                    self.apply(&mut i3_conn);
+                }
+                recv(i3_event) -> event => {
+                    let event = event.expect("i3 event listener thread died");
+                    match event {
+                        I3Event::FocusChanged(id) => {
+                            self.focus_changed(id);
+                            if self.focus_debounce == Duration::from_millis(0) {
+                                self.apply(&mut i3_conn);
+                            } else if self.pending_apply.is_none() {
+                                // not pushed back by later changes so a stream of them can't
+                                // starve applying
+                                self.pending_apply = Some(Instant::now() + self.focus_debounce);
+                            }
+                        }
+                        I3Event::FullscreenChanged => {
+                            self.tree = None;
+                            self.apply(&mut i3_conn);
+                        }
+                        I3Event::NewWindow => {
+                            // the event doesn't say where the window went, its siblings got
+                            // resized too
+                            self.tree = None;
+                            self.apply(&mut i3_conn);
+                        }
+                        I3Event::TreeChanged => {
+                            self.tree = None;
+                            self.visible_workspaces = None;
+                        }
+                        I3Event::TitleChanged(id, container) => {
+                            self.window_changed(container);
+                            if self.rules.match_titles() || self.script.is_some() {
+                                self.reapply_window(&mut i3_conn, id);
+                            }
+                        }
+                        I3Event::WindowMoved(id, container) => {
+                            self.window_moved(&mut i3_conn, id, container);
+                        }
+                        I3Event::OutputsChanged => {
+                            log::debug!("Outputs changed, reapplying opacity to every window");
+                            self.tree = None;
+                            self.visible_workspaces = None;
+                            // windows moved by the window manager might have lost their opacity,
+                            // send everything again instead of only changes
+                            self.applier.reset();
+                            self.apply(&mut i3_conn);
+                        }
+                        I3Event::WorkspaceFocused(current) => {
+                            self.workspace_focused(current);
+                            self.apply(&mut i3_conn);
+                        }
+                        I3Event::WorkspaceChanged => {
+                            self.tree = None;
+                            self.visible_workspaces = None;
+                            if self.tracks_locations() {
+                                self.apply(&mut i3_conn);
+                            }
+                        }
+                        I3Event::FloatingChanged(id) => {
+                            // the window got wrapped in or taken out of a floating container
+                            self.tree = None;
+                            // with dim_floating off the window either just got or lost its
+                            // exemption
+                            if self.floating_opacity.is_some() || !self.dim_floating {
+                                self.reapply_window(&mut i3_conn, id);
+                            }
+                        }
+                        I3Event::MarkChanged(id) => {
+                            // i3ipc drops the marks of the container sent along
+                            self.tree = None;
+                            if self.exempt_mark.is_some() {
+                                self.reapply_window(&mut i3_conn, id);
+                            }
+                        }
+                        I3Event::UrgencyChanged(container) => {
+                            self.window_changed(container);
+                            if self.urgent_opacity.is_some() {
+                                self.apply(&mut i3_conn);
+                            }
+                        }
+                        I3Event::CloseWindow(id) => {
+                            self.window_closed(id);
+                            if self.min_windows > 1 {
+                                self.apply(&mut i3_conn);
+                            }
+                        }
+                        I3Event::Tick(Cmd::Quit) => {
+                            log::info!("Received quit, restoring opacity");
+                            self.restore(&mut i3_conn);
+                            return Ok(());
+                        }
+                        I3Event::Tick(cmd) => {
+                            self.handle_cmd(&mut i3_conn, cmd)?;
+                        }
+                    };
+                }
+                recv(ipc) -> req => {
+                    let (cmd, reply) = req.expect("ipc thread died");
+                    match cmd {
+                        Cmd::Status { .. } => {
+                            let _ = reply.send(Response::Status(self.status()));
+                            continue;
+                        }
+                        Cmd::Get { query, .. } => {
+                            let _ = reply.send(Response::Answer(self.answer(query)));
+                            continue;
+                        }
+                        Cmd::BlacklistList { .. } => {
+                            let _ = reply.send(match self.list_blacklist(&mut i3_conn) {
+                                Ok(answer) => Response::Answer(answer),
+                                Err(e) => Response::Err(format!("Can't list blacklist: {}", e)),
+                            });
+                            continue;
+                        }
+                        Cmd::Quit => {
+                            log::info!("Received quit, restoring opacity");
+                            self.restore(&mut i3_conn);
+                            let _ = reply.send(Response::Ok);
+                            return Ok(());
+                        }
+                        Cmd::Reload => {
+                            let _ = reply.send(match self.reload(&mut i3_conn) {
+                                Ok(()) => Response::Ok,
+                                Err(e) => Response::Err(format!("Can't reload config: {}", e)),
+                            });
+                            continue;
+                        }
+                        _ => {}
+                    }
+                    self.apply_error = None;
+                    let res = self.handle_cmd(&mut i3_conn, cmd);
+                    let _ = reply.send(match (&res, self.apply_error.take()) {
+                        (Ok(()), None) => Response::Ok,
+                        // the daemon keeps running, but the client should know it didn't work
+                        (Ok(()), Some(e)) => Response::Err(format!("Failed to apply opacity: {}", e)),
+                        (Err(e), _) => Response::Err(e.to_string()),
+                    });
+                    res?;
+                }
+            }
+        }
+    }
+}
+
+/// Why the event listener stopped listening
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Disconnect {
+    Exit,
+    Restart,
+    HungUp,
+}
+
+const RECONNECT_ATTEMPTS: u32 = 8;
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(2);
+
+/// Connects again after the window manager went away, backing off while its socket isn't up yet
+fn reconnect() -> Result<WmConnection, Error> {
+    let mut delay = Duration::from_millis(100);
+    let mut attempt = 1;
+    loop {
+        thread::sleep(delay);
+        match wm::connect() {
+            Ok(conn) => return Ok(conn),
+            Err(e) if attempt < RECONNECT_ATTEMPTS => {
+                log::debug!("Reconnect attempt {} failed: {}", attempt, e);
+            }
+            Err(source) => return Err(Error::I3Connect { source }),
+        }
+        attempt += 1;
+        delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+    }
+}
+
+/// Event sources that only need to run while transparency is active.
+/// i3 can't unsubscribe so the window event connection gets dropped instead, a connection
+/// that's only subscribed to shutdown and tick events keeps running so the daemon still exits
+/// with i3 and can be enabled again through ticks.
+struct EventSources {
+    active: bool,
+    i3_tx: chan::Sender<I3Event>,
+    shutdown_tx: chan::Sender<Disconnect>,
+    window_listener: Arc<AtomicUsize>,
+    config_active: ConfigActive,
+}
+
+impl EventSources {
+    #[allow(clippy::type_complexity)]
+    fn spawn(
+        workers: &mut Workers,
+        config_path: ConfigPath,
+        config_poll: Duration,
+    ) -> Result<
+        (
+            Self,
+            chan::Receiver<I3Event>,
+            chan::Receiver<Disconnect>,
+            chan::Receiver<Config>,
+        ),
+        Error,
+    > {
+        let (i3_tx, i3_rx) = chan::bounded(1);
+        // separate so shutdown never queues behind window events
+        let (shutdown_tx, shutdown_rx) = chan::bounded(1);
+        spawn_listener_thread(
+            workers,
+            &[Subscription::Shutdown, Subscription::Tick],
+            i3_tx.clone(),
+            shutdown_tx.clone(),
+            None,
+        )?;
+        // every listener is subscribed to ticks, one wakes them all up to notice the stop
+        workers.on_stop(|| {
+            if let Ok(mut conn) = wm::connect() {
+                let _ = conn.send_tick(&format!("{}:stop", TICK_PREFIX));
+            }
+        });
+
+        let (config_active, config_active_rx, config_active_wake) = ConfigActive::new();
+        let config_reload = spawn_config_reload_thread(
+            workers,
+            config_active_rx,
+            config_active_wake,
+            config_path,
+            config_poll,
+        );
+
+        let sources = Self {
+            active: false,
+            i3_tx,
+            shutdown_tx,
+            window_listener: Arc::new(AtomicUsize::new(0)),
+            config_active,
+        };
+        Ok((sources, i3_rx, shutdown_rx, config_reload))
+    }
+
+    /// Subscribes again after reconnecting, the old listeners died with the old connection
+    fn reconnect(&mut self, workers: &mut Workers) -> Result<(), Error> {
+        spawn_listener_thread(
+            workers,
+            &[Subscription::Shutdown, Subscription::Tick],
+            self.i3_tx.clone(),
+            self.shutdown_tx.clone(),
+            None,
+        )?;
+        if self.active {
+            // forces a new window listener
+            self.active = false;
+            self.set_active(workers, true)?;
+        }
+
+        Ok(())
+    }
+
+    fn set_active(&mut self, workers: &mut Workers, active: bool) -> Result<(), Error> {
+        if self.active == active {
+            return Ok(());
+        }
+        self.active = active;
+        log::debug!("Setting event sources active: {}", active);
+
+        // retires the current window listener, the tick makes it notice right away instead of on
+        // the next window event
+        let generation = self.window_listener.fetch_add(1, Ordering::SeqCst) + 1;
+        if let Ok(mut conn) = wm::connect() {
+            let _ = conn.send_tick(&format!("{}:retire", TICK_PREFIX));
+        }
+        if active {
+            spawn_listener_thread(
+                workers,
+                // ticks only wake it up on stop or retirement
+                &[
+                    Subscription::Window,
+                    Subscription::Workspace,
+                    Subscription::Output,
+                    Subscription::Tick,
+                ],
+                self.i3_tx.clone(),
+                self.shutdown_tx.clone(),
+                Some(Generation {
+                    current: self.window_listener.clone(),
+                    own: generation,
+                }),
+            )?;
+        }
+        self.config_active.send(active);
+
+        Ok(())
+    }
+}
+
+/// Tells the config reload thread whether to watch the config, poking it through a socket so it
+/// drops or adds its watch right away
+struct ConfigActive {
+    tx: chan::Sender<bool>,
+    wake: UnixStream,
+}
+
+impl ConfigActive {
+    fn new() -> (Self, chan::Receiver<bool>, UnixStream) {
+        let (tx, rx) = chan::unbounded();
+        let (wake, woken) = UnixStream::pair().expect("Can't create socket pair");
+        // a full buffer already has the reload thread woken up, and it drains whatever is there
+        for socket in &[&wake, &woken] {
+            socket
+                .set_nonblocking(true)
+                .expect("Can't make socket non-blocking");
+        }
+        (Self { tx, wake }, rx, woken)
+    }
+
+    fn send(&mut self, active: bool) {
+        let _ = self.tx.send(active);
+        let _ = self.wake.write_all(&[0]);
+    }
+}
+
+struct Generation {
+    current: Arc<AtomicUsize>,
+    own: usize,
+}
+
+impl Generation {
+    fn is_current(&self) -> bool {
+        self.current.load(Ordering::SeqCst) == self.own
+    }
+}
+
+/// Quiet period after a config change before reloading, editors save in several steps
+const RELOAD_DEBOUNCE: Duration = Duration::from_millis(200);
+
+fn spawn_config_reload_thread(
+    workers: &mut Workers,
+    active: chan::Receiver<bool>,
+    active_wake: UnixStream,
+    config_path: ConfigPath,
+    poll: Duration,
+) -> chan::Receiver<Config> {
+    use inotify::{EventMask, Inotify, WatchMask};
+
+    let (tx, rx) = chan::bounded(1);
+
+    let mut inotify = match Inotify::init() {
+        Ok(inotify) => inotify,
+        Err(e) => {
+            log::warn!(
+                "Can't watch the config with inotify, checking it every {}s instead: {}",
+                poll.as_secs(),
+                e
+            );
+            spawn_config_poll_thread(workers, active, config_path, poll, tx);
+            return rx;
+        }
+    };
+    // dropping the other end on stop wakes up the poll
+    let (wake, wake_tx) = UnixStream::pair().expect("Can't create socket pair");
+    workers.on_stop(move || drop(wake_tx));
+    let config_file = config_path.path();
+    let config_dir = config_file.parent().unwrap().to_owned();
+    let config_name = config_file.file_name().unwrap().to_owned();
+    // editors like vim save by renaming a new file over the config, a watch on the file
+    // itself would stay on the old inode
+    let watch_config = move |ino: &mut Inotify| {
+        ino.add_watch(
+            &config_dir,
+            WatchMask::CLOSE_WRITE
+                | WatchMask::MOVED_TO
+                | WatchMask::DELETE_SELF
+                | WatchMask::MOVE_SELF,
+        )
+    };
+    let is_config = move |name: Option<&OsStr>| name == Some(config_name.as_os_str());
+    // the watch follows the directory's inode, after a delete or rename the path needs to
+    // be watched again
+    let lost_watch =
+        |mask: EventMask| mask.intersects(EventMask::DELETE_SELF | EventMask::MOVE_SELF);
+
+    // watched before returning so no change after startup goes unnoticed
+    let mut watch = watch_config(&mut inotify).ok();
+
+    workers.spawn("config reload", move |cancelled| {
+        let mut buf = [0u8; 4096];
+        let mut wake_buf = [0u8; 64];
+
+        // Ok(false) once cancelled
+        let mut on_event = || -> Result<bool, Box<dyn std::error::Error>> {
+            let ready = match wait_readable(
+                &[inotify.as_raw_fd(), active_wake.as_raw_fd()],
+                wake.as_raw_fd(),
+            )? {
+                Some(ready) => ready,
+                None => return Ok(false),
+            };
+
+            if ready[1] {
+                while let Ok(n) = (&active_wake).read(&mut wake_buf) {
+                    if n == 0 {
+                        break;
+                    }
+                }
+                match active.try_iter().last() {
+                    // stop watching while transparency is disabled so saves don't wake this up
+                    Some(false) => {
+                        if let Some(wd) = watch.take() {
+                            log::debug!("Transparency disabled, not watching the config");
+                            let _ = inotify.rm_watch(wd);
+                        }
+                    }
+                    // changes from meanwhile went unnoticed
+                    Some(true) if watch.is_none() => {
+                        watch = watch_config(&mut inotify).ok();
+                        return Ok(tx.send(config_path.load()?).is_ok());
+                    }
+                    _ => {}
+                }
+            }
+            if !ready[0] {
+                return Ok(true);
+            }
+
+            let (mut changed, mut rewatch) = (false, false);
+            let mut collect = |event: inotify::Event<&OsStr>| {
+                changed |= is_config(event.name);
+                rewatch |= lost_watch(event.mask);
+            };
+            match inotify.read_events(&mut buf) {
+                Ok(events) => events.for_each(&mut collect),
+                Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => return Ok(true),
+                Err(e) => return Err(e.into()),
+            };
+            if watch.is_none() || !(changed || rewatch) {
+                // leftovers of a removed watch or some other file in the config dir
+                return Ok(true);
+            }
+
+            // collapse the whole burst of events from a single save into one reload
+            loop {
+                thread::sleep(RELOAD_DEBOUNCE);
+                match inotify.read_events(&mut buf) {
+                    Ok(events) => {
+                        let mut events = events.peekable();
+                        if events.peek().is_none() {
+                            break;
+                        }
+                        events.for_each(&mut collect);
+                    }
+                    Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => break,
+                    Err(e) => return Err(e.into()),
+                }
+            }
+
+            if rewatch {
+                log::debug!("Config dir was replaced, watching it again");
+                if let Some(wd) = watch.take() {
+                    // already gone after DELETE_SELF
+                    let _ = inotify.rm_watch(wd);
+                }
+                watch = watch_config(&mut inotify).ok();
+                while watch.is_none() {
+                    if cancelled.is_cancelled() {
+                        return Ok(false);
+                    }
+                    thread::sleep(Duration::new(1, 0));
+                    watch = watch_config(&mut inotify).ok();
+                }
+            } else if !changed {
+                return Ok(true);
+            }
+
+            let cfg = config_path.load()?;
+
+            Ok(tx.send(cfg).is_ok())
+        };
+
+        let mut warnings = LogLimiter::default();
+        while !cancelled.is_cancelled() {
+            match on_event() {
+                Ok(true) => {}
+                Ok(false) => return,
+                Err(e) => warnings.warn(e),
+            }
+        }
+    });
+
+    rx
+}
+
+/// Reloads the config whenever its mtime changes, for systems without inotify
+fn spawn_config_poll_thread(
+    workers: &mut Workers,
+    active: chan::Receiver<bool>,
+    config_path: ConfigPath,
+    poll: Duration,
+    tx: chan::Sender<Config>,
+) {
+    let config_file = config_path.path();
+    let mtime = move || {
+        std::fs::metadata(&config_file)
+            .and_then(|meta| meta.modified())
+            .ok()
+    };
+    // taken before returning so no change after startup goes unnoticed
+    let mut last_mtime = mtime();
+    workers.spawn("config poll", move |cancelled| {
+        let mut warnings = LogLimiter::default();
+        let mut is_active = true;
+        loop {
+            select! {
+                recv(cancelled.channel()) -> _ => return,
+                recv(chan::after(poll)) -> _ => {}
+            }
+            // not checked while transparency is disabled, changes from meanwhile get noticed
+            // on resume
+            is_active = active.try_iter().last().unwrap_or(is_active);
+            if !is_active {
+                continue;
+            }
+            let current = mtime();
+            if current == last_mtime {
+                continue;
+            }
+            last_mtime = current;
+            match config_path.load() {
+                Ok(config) => {
+                    if tx.send(config).is_err() {
+                        return;
+                    }
+                }
+                Err(e) => warnings.warn(e),
+            }
+        }
+    });
+}
+
+/// Blocks until any of `fds` is readable and returns which ones are, `None` if `wake` became
+/// readable first
+fn wait_readable(fds: &[RawFd], wake: RawFd) -> std::io::Result<Option<Vec<bool>>> {
+    let mut pollfds: Vec<_> = fds
+        .iter()
+        .chain(std::iter::once(&wake))
+        .map(|&fd| libc::pollfd {
+            fd,
+            events: libc::POLLIN,
+            revents: 0,
+        })
+        .collect();
+    loop {
+        // pollfds stays valid for the whole call and its length is passed along
+        let res = unsafe { libc::poll(pollfds.as_mut_ptr(), pollfds.len() as libc::nfds_t, -1) };
+        if res >= 0 {
+            let (wake, fds) = pollfds.split_last().unwrap();
+            if wake.revents != 0 {
+                return Ok(None);
+            }
+            return Ok(Some(fds.iter().map(|fd| fd.revents != 0).collect()));
+        }
+        let e = std::io::Error::last_os_error();
+        if e.kind() != std::io::ErrorKind::Interrupted {
+            return Err(e);
+        }
+    }
+}
+
+/// Forwards SIGTERM and SIGINT so the daemon can restore opacity before exiting, SIGHUP
+/// separately to reload the config
+fn spawn_signal_thread(
+    workers: &mut Workers,
+) -> Result<(chan::Receiver<i32>, chan::Receiver<()>), Error> {
+    use signal_hook::{iterator::Signals, SIGHUP, SIGINT, SIGTERM};
+
+    let signals = Signals::new(&[SIGTERM, SIGINT, SIGHUP]).context(SignalHandler)?;
+    let (tx, rx) = chan::bounded(1);
+    // separate so a pending reload can't swallow a SIGTERM
+    let (hangup_tx, hangup_rx) = chan::bounded(1);
+    let closer = signals.clone();
+    workers.spawn("signals", move |cancelled| {
+        for signal in signals.forever() {
+            if cancelled.is_cancelled() {
+                return;
+            }
+            // full means the daemon is already exiting or about to reload anyway
+            if signal == SIGHUP {
+                let _ = hangup_tx.try_send(());
+            } else {
+                let _ = tx.try_send(signal);
+            }
+        }
+    });
+    // ends `forever` on every clone
+    workers.on_stop(move || closer.close());
+
+    Ok((rx, hangup_rx))
+}
+
+/// A command from an ipc client along with where to send the response
+type IpcRequest = (Cmd, chan::Sender<Response>);
+
+fn spawn_ipc_thread(
+    workers: &mut Workers,
+    tcp_port: Option<u16>,
+    http_port: Option<u16>,
+    socket: Option<PathBuf>,
+) -> Result<chan::Receiver<IpcRequest>, Error> {
+    let timeout = Duration::from_millis(100);
+    let srv = IpcServer::new(timeout, socket).context(Ipc)?;
+    let tcp = match tcp_port {
+        Some(port) => Some((port, TcpServer::new(port, timeout).context(Ipc)?)),
+        None => None,
+    };
+
+    let (tx, rx) = chan::bounded(1);
+
+    if let Some(port) = http_port {
+        http::spawn(workers, port, tx.clone()).context(Http)?;
+    }
+
+    bus::spawn(workers, tx.clone());
+
+    if let Some((port, tcp)) = tcp {
+        let tx = tx.clone();
+        workers.spawn("tcp ipc", move |cancelled| {
+            serve_ipc(tcp.incoming(), &tx, &cancelled)
+        });
+        workers.on_stop(move || ipc::wake_tcp_server(port));
+    }
+
+    let socket = srv.socket().to_owned();
+    workers.spawn("ipc", move |cancelled| {
+        serve_ipc(srv.incoming(), &tx, &cancelled)
+    });
+    workers.on_stop(move || ipc::wake_server(&socket));
+
+    Ok(rx)
+}
+
+/// Clients connected at the same time, e.g. status bars polling and keybinds firing commands
+const MAX_IPC_CLIENTS: usize = 32;
+
+/// Serves every client on its own thread so a stuck one doesn't stall the others until it
+/// times out. The client threads are joined before returning.
+fn serve_ipc<I, S>(incoming: I, tx: &chan::Sender<IpcRequest>, cancelled: &Cancelled)
+where
+    I: IntoIterator<Item = Result<Connection<S>, ipc::Error>>,
+    S: Read + Write + Send + 'static,
+{
+    let warnings = Arc::new(Mutex::new(LogLimiter::default()));
+    let mut clients = HashMap::new();
+    let mut next_client = 0u64;
+    // ids of client threads about to exit
+    let (done_tx, done) = chan::unbounded();
+    for conn in incoming {
+        for id in done.try_iter() {
+            join_ipc_client(clients.remove(&id));
+        }
+        // woken up by a connection on stop
+        if cancelled.is_cancelled() {
+            break;
+        }
+        let conn = match conn {
+            Ok(conn) => conn,
+            Err(e) => {
+                warnings
+                    .lock()
+                    .unwrap()
+                    .warn(format_args!("Error while accepting connection: {}", e));
+                continue;
+            }
+        };
+        if clients.len() >= MAX_IPC_CLIENTS {
+            warnings
+                .lock()
+                .unwrap()
+                .warn("Too many ipc clients, closing connection");
+            continue;
+        }
+
+        let id = next_client;
+        next_client += 1;
+        let (tx, warnings, cancelled, done_tx) = (
+            tx.clone(),
+            warnings.clone(),
+            cancelled.clone(),
+            done_tx.clone(),
+        );
+        let spawned = thread::Builder::new()
+            .name("ipc client".to_owned())
+            .spawn(move || {
+                serve_client(conn, &tx, &warnings, &cancelled);
+                let _ = done_tx.send(id);
+            });
+        match spawned {
+            Ok(handle) => {
+                clients.insert(id, handle);
+            }
+            Err(e) => warnings
+                .lock()
+                .unwrap()
+                .warn(format_args!("Can't spawn ipc client thread: {}", e)),
+        }
+    }
+
+    // clients time out between commands and can't reach the stopped daemon anymore
+    for (_, handle) in clients.drain() {
+        join_ipc_client(Some(handle));
+    }
+}
+
+fn join_ipc_client(handle: Option<thread::JoinHandle<()>>) {
+    if let Some(handle) = handle {
+        if handle.join().is_err() {
+            log::error!("ipc client thread panicked");
+        }
+    }
+}
+
+fn serve_client<S>(
+    mut conn: Connection<S>,
+    tx: &chan::Sender<IpcRequest>,
+    warnings: &Mutex<LogLimiter>,
+    cancelled: &Cancelled,
+) where
+    S: Read + Write,
+{
+    let warn = |msg: String| warnings.lock().unwrap().warn(msg);
+    match conn.handshake() {
+        Ok(true) => {}
+        Ok(false) => return,
+        Err(e) => {
+            warn(format!("Rejected ipc client: {}", e));
+            return;
+        }
+    }
+    while !cancelled.is_cancelled() {
+        let response = match conn.next_cmd() {
+            Ok(Some(cmd)) => {
+                let (reply_tx, reply_rx) = chan::bounded(1);
+                if tx.send((cmd, reply_tx)).is_err() {
+                    let _ = conn.respond(&Response::Err("daemon stopped".to_owned()));
+                    return;
+                }
+                reply_rx
+                    .recv()
+                    .unwrap_or_else(|_| Response::Err("daemon stopped".to_owned()))
+            }
+            Ok(None) => break,
+            // a frame that doesn't decode is skipped, anything else leaves the stream
+            // out of sync
+            Err(e @ ipc::Error::Cbor { .. }) => {
+                warn(format!("Error while reading cmd: {}", e));
+                Response::Err(e.to_string())
+            }
+            Err(e) => {
+                warn(format!("Error while reading cmd: {}", e));
+                let _ = conn.respond(&Response::Err(e.to_string()));
+                break;
+            }
+        };
+        if let Err(e) = conn.respond(&response) {
+            warn(format!("Error while responding: {}", e));
+            break;
+        }
+    }
+}
+
+#[derive(Debug)]
+enum I3Event {
+    FocusChanged(i64),
+    /// Dimmed right away, windows opened in the background get no focus event
+    NewWindow,
+    /// The tree changed in ways that don't need reapplying right away
+    TreeChanged,
+    FullscreenChanged,
+    TitleChanged(i64, Option<Box<Node>>),
+    WindowMoved(i64, Option<Box<Node>>),
+    /// A window switched between tiling and floating
+    FloatingChanged(i64),
+    MarkChanged(i64),
+    /// Comes with the newly focused workspace if the window manager sends it
+    WorkspaceFocused(Option<Box<Node>>),
+    WorkspaceChanged,
+    /// Windows might have been reshuffled onto other outputs
+    OutputsChanged,
+    UrgencyChanged(Option<Box<Node>>),
+    CloseWindow(i64),
+    Tick(Cmd),
+}
+
+const TICK_PREFIX: &str = "transparentd";
+
+/// Parses tick payloads like `transparentd toggle` as sent by
+/// `i3-msg -t send_tick 'transparentd toggle'`.
+/// Returns `None` for ticks meant for someone else.
+fn parse_tick(payload: &str) -> Option<Result<Cmd, structopt::clap::Error>> {
+    let mut args = payload.split_whitespace();
+    if args.next() != Some(TICK_PREFIX) {
+        return None;
+    }
+
+    Some(Cmd::from_iter_safe(
+        std::iter::once(TICK_PREFIX).chain(args),
+    ))
+}
+
+fn spawn_listener_thread(
+    workers: &mut Workers,
+    subscriptions: &[Subscription],
+    tx: chan::Sender<I3Event>,
+    shutdown: chan::Sender<Disconnect>,
+    generation: Option<Generation>,
+) -> Result<(), Error> {
+    use i3ipc::event::inner::{WindowChange, WorkspaceChange};
+
+    let events = wm::listen(subscriptions)?;
+
+    workers.spawn("wm listener", move |cancelled| {
+        let mut warnings = LogLimiter::default();
+        for event in events {
+            if cancelled.is_cancelled() {
+                return;
+            }
+            if let Some(ref generation) = generation {
+                if !generation.is_current() {
+                    log::debug!("Retiring window event listener");
+                    return;
+                }
+            }
+            let event = match event {
+                Ok(WmEvent::Window {
+                    change,
+                    id,
+                    container,
+                }) => match change {
+                    WindowChange::Close => Some(I3Event::CloseWindow(id)),
+                    WindowChange::Focus => Some(I3Event::FocusChanged(id)),
+                    WindowChange::New => Some(I3Event::NewWindow),
+                    WindowChange::Floating => Some(I3Event::FloatingChanged(id)),
+                    WindowChange::FullscreenMode => Some(I3Event::FullscreenChanged),
+                    WindowChange::Title => Some(I3Event::TitleChanged(id, container)),
+                    WindowChange::Move => Some(I3Event::WindowMoved(id, container)),
+                    WindowChange::Urgent => Some(I3Event::UrgencyChanged(container)),
+                    WindowChange::Mark => Some(I3Event::MarkChanged(id)),
+                    _ => None,
+                },
+                // switching to an empty workspace comes without a window focus event
+                Ok(WmEvent::Workspace {
+                    change: WorkspaceChange::Focus,
+                    current,
+                }) => Some(I3Event::WorkspaceFocused(current)),
+                // moving a workspace to another output changes the output of its windows
+                Ok(WmEvent::Workspace {
+                    change: WorkspaceChange::Move,
+                    ..
+                }) => Some(I3Event::WorkspaceChanged),
+                // windows report their own urgency
+                Ok(WmEvent::Workspace {
+                    change: WorkspaceChange::Urgent,
+                    ..
+                }) => None,
+                Ok(WmEvent::Workspace { .. }) => Some(I3Event::TreeChanged),
+                Ok(WmEvent::Output) => Some(I3Event::OutputsChanged),
+                // window listeners only get ticks to wake up, the shutdown listener handles
+                // tick commands
+                Ok(WmEvent::Tick(_)) if generation.is_some() => None,
+                Ok(WmEvent::Tick(payload)) => match parse_tick(&payload) {
+                    Some(Ok(cmd)) => Some(I3Event::Tick(cmd)),
+                    Some(Err(e)) => {
+                        warnings.warn(format_args!("Invalid tick command {:?}: {}", payload, e));
+                        None
+                    }
+                    None => None,
+                },
+                Ok(WmEvent::Shutdown { restart }) => {
+                    let disconnect = if restart {
+                        Disconnect::Restart
+                    } else {
+                        Disconnect::Exit
+                    };
+                    // full means a shutdown is already pending
+                    let _ = shutdown.try_send(disconnect);
+                    return;
+                }
+                Ok(WmEvent::Other) => None,
+                // server hung up
+                Err(i3ipc::MessageError::Receive(ref e))
+                    if e.kind() == std::io::ErrorKind::ConnectionReset
+                        || e.kind() == std::io::ErrorKind::UnexpectedEof =>
+                {
+                    // window listeners get replaced on reconnect, only the shutdown listener
+                    // reports a hangup so an exit event can't lose against it
+                    if generation.is_none() {
+                        let _ = shutdown.try_send(Disconnect::HungUp);
+                    }
+                    return;
+                }
+                Err(e) => {
+                    warnings.warn(format_args!("i3listener: {}", e));
+                    None
+                }
+            };
+            if let Some(event) = event {
+                // the daemon stopped listening
+                if tx.send(event).is_err() {
+                    return;
+                }
+            }
+        }
+    });
+
+    Ok(())
+}
+
+/// The flags override RUST_LOG for transparentd's own logs, without them RUST_LOG is used as is
+fn init_logger(opt: &Opt) {
+    let mut builder = env_logger::Builder::from_default_env();
+    if opt.quiet {
+        builder.filter_level(log::LevelFilter::Off);
+    } else if opt.verbose > 0 {
+        let level = match opt.verbose {
+            1 => log::LevelFilter::Info,
+            2 => log::LevelFilter::Debug,
+            _ => log::LevelFilter::Trace,
+        };
+        builder.filter_module(module_path!(), level);
+    }
+    builder.init();
+}
+
+pub fn main() {
+    let opt = Opt::from_args();
+    init_logger(&opt);
+    if let Err(e) = run(opt) {
+        Output::stderr().error(e);
+        std::process::exit(1);
+    }
+}
+
+/// Entry points for the fuzz targets in `fuzz/`
+#[cfg(feature = "fuzzing")]
+pub mod fuzzing {
+    pub use crate::ipc::{decode_cmd, read_frame};
+}
+
+/// Internals measured by the benchmarks in `benches/`
+#[cfg(feature = "bench")]
+pub mod bench {
+    pub use crate::{
+        apply::opacity_cmd,
+        config::RuleConfig,
+        i3::{AllWindows, WindowInfo},
+        opacity::Opacity,
+        rules::Rules,
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        fake_wm::{fake_wm, FakeWm},
+        i3::{test_node, test_root, test_workspace},
+    };
+    use i3ipc::reply::WindowProperty;
+    use proptest::prelude::*;
+
+    #[derive(Debug, Clone)]
+    enum Op {
+        Open,
+        Close(usize),
+        Focus(usize),
+        Enable,
+        Disable,
+        Toggle,
+        Blacklist,
+        BlacklistRemove,
+        Pause(bool),
+    }
+
+    fn op() -> impl Strategy<Value = Op> {
+        prop_oneof![
+            Just(Op::Open),
+            any::<usize>().prop_map(Op::Close),
+            any::<usize>().prop_map(Op::Focus),
+            Just(Op::Enable),
+            Just(Op::Disable),
+            Just(Op::Toggle),
+            Just(Op::Blacklist),
+            Just(Op::BlacklistRemove),
+            any::<bool>().prop_map(Op::Pause),
+        ]
+    }
+
+    /// What the desktop should look like, kept independently from the daemon
+    #[derive(Debug, Default)]
+    struct Model {
+        windows: Vec<i64>,
+        focused: Option<i64>,
+        next_id: i64,
+        enabled: bool,
+        paused: bool,
+        blacklist: HashSet<i64>,
+    }
+
+    impl Model {
+        fn expected(&self, id: i64, transparency: Opacity) -> Option<Opacity> {
+            if self.paused || self.focused == Some(id) {
+                Some(Opacity::max())
+            } else if self.blacklist.contains(&id) {
+                None
+            } else {
+                Some(transparency)
+            }
+        }
+    }
+
+    fn daemon() -> Daemon {
+        daemon_with("")
+    }
+
+    /// Daemon with `config` added to the test defaults
+    fn daemon_with(config: &str) -> Daemon {
+        let config = format!("transparency_at_start = true\nopacity = 0.8\n{}", config);
+        Daemon::with_config(toml::from_str(&config).unwrap(), ConfigPath::default())
+    }
+
+    #[test]
+    fn keeps_opacity_step_when_invalid() {
+        let mut daemon = daemon();
+        let step = daemon.opacity_step;
+        for &invalid in &[std::f64::NAN, -0.1, 0.0] {
+            let mut config: Config =
+                toml::from_str("transparency_at_start = true\nopacity = 0.8\n").unwrap();
+            config.opacity_step = invalid;
+            daemon.update_config(config);
+            assert_eq!(daemon.opacity_step.to_bits(), step.to_bits());
+        }
+
+        let (mut conn, _) = fake_wm(test_root(vec![]));
+        let base = daemon.base_opacity;
+        daemon.handle_cmd(&mut conn, Cmd::OpacityUp).unwrap();
+        assert!(daemon.base_opacity.value() > base.value());
+    }
+
+    #[test]
+    fn bounds_every_tier() {
+        let daemon = daemon_with(
+            "focused_opacity = 1.0\nurgent_opacity = 1.0\nother_workspace_opacity = 0.1\n\
+             min_opacity = 0.3\nmax_opacity = 0.9\n\
+             [idle]\nopacity = 0.0\n\
+             [ambient_light]\ndark_opacity = 1.0\nbright_opacity = 0.2\n",
+        );
+        let (min, max) = (Opacity::new(0.3), Opacity::new(0.9));
+        assert_eq!(Some(daemon.focused_opacity), max);
+        assert_eq!(daemon.urgent_opacity, max);
+        assert_eq!(daemon.other_workspace_opacity, min);
+        assert_eq!(daemon.idle_config.as_ref().map(|idle| idle.opacity), min);
+        let ambient_light = daemon.ambient_light_config.as_ref().unwrap();
+        assert_eq!(Some(ambient_light.dark_opacity), max);
+        assert_eq!(Some(ambient_light.bright_opacity), min);
+    }
+
+    #[test]
+    fn blacklists_by_criteria() {
+        let mut root = test_root(vec![
+            test_node(1, vec![], vec![]),
+            test_node(2, vec![], vec![]),
+        ]);
+        root.nodes[1].name = Some("YouTube".to_owned());
+        let (mut conn, applied) = fake_wm(root);
+
+        let mut daemon = daemon();
+        daemon.apply(&mut conn);
+        let criteria: Criteria = "title=Tube".parse().unwrap();
+        daemon
+            .handle_cmd(
+                &mut conn,
+                Cmd::Blacklist {
+                    criteria: criteria.clone(),
+                },
+            )
+            .unwrap();
+        assert_eq!(applied.borrow().get(&2), Some(&Opacity::max()));
+        daemon.apply(&mut conn);
+        assert_eq!(applied.borrow().get(&2), Some(&Opacity::max()));
+        assert_eq!(
+            daemon.list_blacklist(&mut conn).unwrap().to_string(),
+            "2\t-\tYouTube\n[title=\"Tube\"]\n"
+        );
+
+        daemon
+            .handle_cmd(&mut conn, Cmd::Unblacklist { criteria })
+            .unwrap();
+        assert!(daemon.blacklist.is_empty());
+        assert_eq!(applied.borrow().get(&2), Some(&daemon.transparency));
+    }
+
+    #[test]
+    fn blacklist_clear_forgets_everything() {
+        let mut root = test_root(vec![
+            test_node(1, vec![], vec![]),
+            test_node(2, vec![], vec![]),
+            test_node(3, vec![], vec![]),
+        ]);
+        root.nodes[0].name = Some("YouTube".to_owned());
+        root.nodes[2].focused = true;
+        let (mut conn, applied) = fake_wm(root);
+
+        let mut daemon = daemon();
+        let criteria = "title=Tube".parse().unwrap();
+        daemon
+            .handle_cmd(&mut conn, Cmd::Blacklist { criteria })
+            .unwrap();
+        daemon
+            .handle_cmd(&mut conn, Cmd::BlacklistId { id: 2 })
+            .unwrap();
+        daemon
+            .handle_cmd(&mut conn, Cmd::FocusBlacklist { duration: None })
+            .unwrap();
+        assert_eq!(applied.borrow().get(&1), Some(&Opacity::max()));
+        assert_eq!(applied.borrow().get(&2), Some(&Opacity::max()));
+        assert!(!daemon.persisted_blacklist.is_empty());
+
+        daemon.handle_cmd(&mut conn, Cmd::BlacklistClear).unwrap();
+        assert!(daemon.persisted_blacklist.is_empty());
+        assert_eq!(daemon.list_blacklist(&mut conn).unwrap().to_string(), "");
+        assert_eq!(applied.borrow().get(&1), Some(&daemon.transparency));
+        assert_eq!(applied.borrow().get(&2), Some(&daemon.transparency));
+    }
+
+    #[test]
+    fn temporary_blacklist_expires() {
+        let mut root = test_root(vec![
+            test_node(1, vec![], vec![]),
+            test_node(2, vec![], vec![]),
+        ]);
+        root.nodes[0].focused = true;
+        let (mut conn, _) = fake_wm(root);
+
+        let mut daemon = daemon();
+        let duration = Some(Duration::from_secs(600));
+        daemon
+            .handle_cmd(&mut conn, Cmd::FocusBlacklist { duration })
+            .unwrap();
+        daemon.expire_blacklist(&mut conn);
+        assert!(daemon.blacklist.contains(&1));
+
+        daemon.blacklist_expiry.insert(1, Instant::now());
+        daemon.expire_blacklist(&mut conn);
+        assert!(daemon.blacklist.is_empty());
+        assert!(daemon.blacklist_expiry.is_empty());
+    }
+
+    #[test]
+    fn pause_disables_until_resumed() {
+        let root = test_root(vec![test_node(1, vec![], vec![])]);
+        let (mut conn, _) = fake_wm(root);
+
+        let mut daemon = daemon();
+        let duration = Duration::from_secs(1800);
+        daemon
+            .handle_cmd(&mut conn, Cmd::Pause { duration })
+            .unwrap();
+        assert!(!daemon.state.is_enabled());
+        assert!(daemon.resume_at.is_some());
+
+        daemon.handle_cmd(&mut conn, Cmd::Enable).unwrap();
+        assert!(daemon.state.is_enabled());
+        assert!(daemon.resume_at.is_none());
+    }
+
+    #[test]
+    fn keeps_recently_focused_windows_opaque() {
+        let mut root = test_root(vec![
+            test_node(1, vec![], vec![]),
+            test_node(2, vec![], vec![]),
+            test_node(3, vec![], vec![]),
+        ]);
+        root.nodes[0].focused = true;
+        let (mut conn, applied) = fake_wm(root);
+
+        let mut daemon = daemon();
+        daemon.keep_recent_opaque = 2;
+        daemon.apply(&mut conn);
+        daemon.focus_changed(2);
+        daemon.apply(&mut conn);
+        let applied = applied.borrow();
+        assert_eq!(applied.get(&1), Some(&Opacity::max()));
+        assert_eq!(applied.get(&2), Some(&Opacity::max()));
+        assert_eq!(applied.get(&3), Some(&daemon.transparency));
+    }
+
+    #[test]
+    fn grades_opacity_by_focus_recency() {
+        let windows = (1..=4).map(|id| test_node(id, vec![], vec![])).collect();
+        let mut root = test_root(windows);
+        root.nodes[0].focused = true;
+        let (mut conn, applied) = fake_wm(root);
+
+        let mut daemon = daemon();
+        daemon.recency = Some(RecencyConfig {
+            start: Opacity::new(0.95).unwrap(),
+            step: 0.1,
+        });
+        daemon.apply(&mut conn);
+        for id in 2..=4 {
+            daemon.focus_changed(id);
+        }
+        daemon.apply(&mut conn);
+        let applied = applied.borrow();
+        assert_eq!(applied.get(&4), Some(&Opacity::max()));
+        assert_eq!(applied.get(&3), Opacity::new(0.95).as_ref());
+        assert_eq!(applied.get(&2), Opacity::new(0.85).as_ref());
+        // graded below the usual opacity
+        assert_eq!(applied.get(&1), Some(&daemon.transparency));
+    }
+
+    #[test]
+    fn skips_dimming_on_sparse_workspaces() {
+        let mut root = test_root(vec![
+            test_workspace(10, "1", vec![test_node(1, vec![], vec![])]),
+            test_workspace(
+                20,
+                "2",
+                vec![test_node(2, vec![], vec![]), test_node(3, vec![], vec![])],
+            ),
+        ]);
+        root.nodes[1].nodes[0].focused = true;
+        let (mut conn, applied) = fake_wm(root);
+
+        let mut daemon = daemon();
+        daemon.min_windows = 2;
+        daemon.apply(&mut conn);
+        let applied = applied.borrow();
+        assert_eq!(applied.get(&1), Some(&Opacity::max()));
+        assert_eq!(applied.get(&3), Some(&daemon.transparency));
+    }
+
+    #[test]
+    fn suspends_while_fullscreen() {
+        let mut root = test_root(vec![
+            test_node(1, vec![], vec![]),
+            test_node(2, vec![], vec![]),
+        ]);
+        root.nodes[0].focused = true;
+        let wm = FakeWm {
+            fullscreen: vec![1].into_iter().collect(),
+            ..FakeWm::new(root.clone())
+        };
+        let applied = wm.applied.clone();
+        let mut conn: WmConnection = Box::new(wm);
+
+        let mut daemon = daemon();
+        daemon.fullscreen = Fullscreen::Suspend;
+        daemon.apply(&mut conn);
+        assert!(daemon.state.pause_reason().is_some());
+        assert_eq!(applied.borrow().get(&2), Some(&Opacity::max()));
+
+        applied.borrow_mut().clear();
+        daemon.focus_changed(2);
+        daemon.apply(&mut conn);
+        assert!(applied.borrow().is_empty());
+
+        // the window left fullscreen
+        let (mut conn, applied) = fake_wm(root);
+        daemon.tree = None;
+        daemon.apply(&mut conn);
+        assert_eq!(daemon.state, State::Enabled);
+        assert_eq!(applied.borrow().get(&2), Some(&daemon.transparency));
+    }
+
+    #[test]
+    fn toggles_focused_window_blacklist() {
+        let mut root = test_root(vec![test_node(1, vec![], vec![])]);
+        root.nodes[0].focused = true;
+        let (mut conn, _) = fake_wm(root);
+
+        let mut daemon = daemon();
+        daemon
+            .handle_cmd(&mut conn, Cmd::FocusBlacklistToggle)
+            .unwrap();
+        assert!(daemon.blacklist.contains(&1));
+        daemon
+            .handle_cmd(&mut conn, Cmd::FocusBlacklistToggle)
+            .unwrap();
+        assert!(daemon.blacklist.is_empty());
+    }
+
+    #[test]
+    fn exempts_marked_windows() {
+        let root = test_root(vec![
+            test_node(1, vec![], vec![]),
+            test_node(2, vec![], vec![]),
+        ]);
+        let wm = FakeWm {
+            marks: vec![(2, vec!["opaque".to_owned()])].into_iter().collect(),
+            ..FakeWm::new(root)
+        };
+        let applied = wm.applied.clone();
+        let mut conn: WmConnection = Box::new(wm);
+
+        let mut daemon = daemon();
+        daemon.apply(&mut conn);
+        assert_eq!(applied.borrow().get(&2), Some(&daemon.transparency));
+
+        daemon.exempt_mark = Some("opaque".to_owned());
+        daemon.apply(&mut conn);
+        assert_eq!(applied.borrow().get(&1), Some(&daemon.transparency));
+        assert_eq!(applied.borrow().get(&2), Some(&Opacity::max()));
+    }
+
+    #[test]
+    fn updates_cached_tree_from_events() {
+        let mut root = test_root(vec![
+            test_workspace(
+                10,
+                "1",
+                vec![test_node(1, vec![], vec![]), test_node(2, vec![], vec![])],
+            ),
+            test_workspace(20, "2", vec![]),
+        ]);
+        root.nodes[0].nodes[0].focused = true;
+        let (mut conn, _) = fake_wm(root);
+        let mut daemon = daemon();
+        daemon.apply(&mut conn);
+
+        let mut renamed = test_node(2, vec![], vec![]);
+        renamed.name = Some("YouTube".to_owned());
+        renamed.urgent = true;
+        daemon.window_changed(Some(Box::new(renamed)));
+        let tree = daemon.tree.clone().unwrap().root;
+        let window = AllWindows::new(tree).find(|node| node.id == 2).unwrap();
+        assert_eq!(window.name.as_deref(), Some("YouTube"));
+        assert!(window.urgent);
+
+        daemon.workspace_focused(Some(Box::new(test_workspace(20, "2", vec![]))));
+        let tree = &daemon.tree.as_ref().unwrap().root;
+        let focused = i3::focused_workspace(tree, &i3::locations(tree));
+        assert_eq!(focused.as_deref(), Some("2"));
+
+        // a window the cache doesn't know about means it's outdated
+        daemon.window_changed(Some(Box::new(test_node(3, vec![], vec![]))));
+        assert!(daemon.tree.is_none());
+    }
+
+    #[test]
+    fn shown_scratchpad_window_counts_as_focused() {
+        let output = |id, name: &str, children| {
+            let mut output = test_node(id, children, vec![]);
+            output.nodetype = i3ipc::reply::NodeType::Output;
+            output.name = Some(name.to_owned());
+            output.window = None;
+            output
+        };
+        let mut focused = test_node(2, vec![], vec![]);
+        focused.focused = true;
+        let hidden = test_root(vec![
+            output(10, "__i3", vec![test_node(1, vec![], vec![])]),
+            output(20, "eDP-1", vec![test_workspace(30, "1", vec![focused])]),
+        ]);
+        // the focus event hasn't arrived yet, the window manager still reports the old focus
+        let mut shown = hidden.clone();
+        let window = shown.nodes[0].nodes.remove(0);
+        shown.nodes[1].nodes[0].floating_nodes.push(window);
+
+        let mut daemon = daemon();
+        let (mut conn, _) = fake_wm(hidden);
+        daemon.apply(&mut conn);
+        let (mut conn, applied) = fake_wm(shown);
+        let mut container = test_node(1, vec![], vec![]);
+        container.focused = true;
+        daemon.window_moved(&mut conn, 1, Some(Box::new(container)));
+        assert_eq!(applied.borrow().get(&1), Some(&Opacity::max()));
+        assert_eq!(applied.borrow().get(&2), Some(&daemon.transparency));
+    }
+
+    #[test]
+    fn applies_through_backend() {
+        let mut root = test_root(vec![
+            test_node(1, vec![], vec![]),
+            test_node(2, vec![], vec![]),
+        ]);
+        root.nodes[0].focused = true;
+        let (mut conn, applied) = fake_wm(root);
+
+        let mut daemon = daemon();
+        daemon.apply(&mut conn);
+        assert_eq!(applied.borrow().get(&1), Some(&Opacity::max()));
+        assert_eq!(applied.borrow().get(&2), Some(&daemon.transparency));
+
+        daemon.handle_cmd(&mut conn, Cmd::Disable).unwrap();
+        assert!(applied
+            .borrow()
+            .values()
+            .all(|&opacity| opacity == Opacity::max()));
+    }
+
+    #[test]
+    fn reapplies_rules_on_title_change() {
+        let mut root = test_root(vec![
+            test_node(1, vec![], vec![]),
+            test_node(2, vec![], vec![]),
+        ]);
+        root.nodes[0].focused = true;
+        root.nodes[1].name = Some("vim".to_owned());
+        let (mut conn, applied) = fake_wm(root.clone());
+
+        let mut daemon = daemon();
+        let rule = config::RuleConfig {
+            class: None,
+            instance: None,
+            match_title: Some("YouTube".to_owned()),
+            workspace: None,
+            output: None,
+            unfocused: Some(Opacity::max()),
+            focused: None,
+        };
+        daemon.rules = Rules::new(&[rule], Opacity::min(), Opacity::max());
+        daemon.apply(&mut conn);
+        assert_eq!(applied.borrow().get(&2), Some(&daemon.transparency));
+
+        root.nodes[1].name = Some("YouTube - Firefox".to_owned());
+        let (mut conn, applied) = fake_wm(root);
+        daemon.tree = None;
+        daemon.reapply_window(&mut conn, 2);
+        assert_eq!(applied.borrow().len(), 1);
+        assert_eq!(applied.borrow().get(&2), Some(&Opacity::max()));
+    }
+
+    #[test]
+    fn only_connection_failures_trip_safe_mode() {
+        let (mut conn, _) = fake_wm(test_root(vec![]));
+        let mut daemon = daemon();
+        for _ in 0..10 {
+            let res = Err(ApplyError::Partial {
+                failed: vec![1],
+                reason: "con_id 1: No matching node".to_owned(),
+            });
+            daemon.track_apply_result(&mut conn, res);
+        }
+        assert!(!daemon.safe_mode.is_degraded());
+
+        for _ in 0..10 {
+            let res = Err(ApplyError::X11 {
+                reason: "connection lost".to_owned(),
+            });
+            daemon.track_apply_result(&mut conn, res);
+        }
+        assert!(daemon.safe_mode.is_degraded());
+    }
+
+    #[test]
+    fn applies_opacity_tiers() {
+        let mut urgent = test_node(4, vec![], vec![]);
+        urgent.urgent = true;
+        let dialog = test_node(5, vec![], vec![]);
+        let mut root = test_root(vec![
+            test_workspace(
+                10,
+                "1",
+                vec![test_node(1, vec![], vec![]), test_node(2, vec![], vec![])],
+            ),
+            test_workspace(20, "2", vec![test_node(3, vec![], vec![]), urgent]),
+            test_workspace(30, "3", vec![test_node(6, vec![], vec![])]),
+        ]);
+        root.nodes[0].nodes[0].focused = true;
+        root.nodes[0].floating_nodes.push(dialog);
+        // "1" and "2" are shown on two outputs, "3" on none
+        let wm = FakeWm {
+            visible: vec!["1".to_owned(), "2".to_owned()],
+            ..FakeWm::new(root)
+        };
+        let applied = wm.applied.clone();
+        let mut conn: WmConnection = Box::new(wm);
+
+        let mut daemon = daemon();
+        daemon.other_workspace_opacity = Opacity::new(0.5);
+        daemon.urgent_opacity = Some(Opacity::max());
+        daemon.floating_opacity = Opacity::new(0.9);
+        daemon.apply(&mut conn);
+        let applied = applied.borrow();
+        assert_eq!(applied.get(&1), Some(&Opacity::max()));
+        assert_eq!(applied.get(&2), Some(&daemon.transparency));
+        assert_eq!(applied.get(&3), Opacity::new(0.5).as_ref());
+        assert_eq!(applied.get(&4), Some(&Opacity::max()));
+        assert_eq!(applied.get(&5), Opacity::new(0.9).as_ref());
+        assert_eq!(applied.get(&6), Some(&daemon.transparency));
+    }
+
+    #[test]
+    fn parses_tick_commands() {
+        assert!(matches!(
+            parse_tick("transparentd toggle"),
+            Some(Ok(Cmd::Toggle))
+        ));
+        assert!(matches!(
+            parse_tick("  transparentd   disable "),
+            Some(Ok(Cmd::Disable))
+        ));
+        assert!(matches!(
+            parse_tick("transparentd frobnicate"),
+            Some(Err(_))
+        ));
+        // ticks for other consumers and the daemon's own broadcasts
+        assert!(parse_tick("polybar toggle").is_none());
+        assert!(parse_tick("transparentd:enabled=false").is_none());
+        assert!(parse_tick("").is_none());
+    }
+
+    #[test]
+    fn broadcasts_changed_state_as_ticks() {
+        let wm = FakeWm::new(test_root(vec![]));
+        let ticks = wm.ticks.clone();
+        let mut conn: WmConnection = Box::new(wm);
+
+        let mut daemon = daemon();
+        daemon.broadcast_state(&mut conn);
+        assert_eq!(
+            *ticks.borrow(),
+            vec![
+                "transparentd:enabled=true".to_owned(),
+                "transparentd:paused=".to_owned(),
+                format!("transparentd:opacity={}", daemon.transparency),
+                "transparentd:degraded=false".to_owned(),
+                "transparentd:skipped=0".to_owned(),
+            ]
+        );
+
+        ticks.borrow_mut().clear();
+        daemon.broadcast_state(&mut conn);
+        assert!(ticks.borrow().is_empty());
+
+        daemon.handle_cmd(&mut conn, Cmd::Disable).unwrap();
+        daemon.broadcast_state(&mut conn);
+        assert_eq!(*ticks.borrow(), vec!["transparentd:enabled=false"]);
+    }
+
+    #[test]
+    fn follows_color_scheme_profiles() {
+        let mut daemon = daemon_with(
+            "follow_color_scheme = true\n\
+             [profiles.dark]\nopacity = 0.9\n[profiles.light]\nopacity = 0.6\n",
+        );
+        assert_eq!(daemon.transparency, Opacity::new(0.8).unwrap());
+
+        daemon.handle_reading(Reading::ColorScheme(ColorScheme::Dark));
+        assert_eq!(daemon.transparency, Opacity::new(0.9).unwrap());
+        daemon.handle_reading(Reading::ColorScheme(ColorScheme::Light));
+        assert_eq!(daemon.transparency, Opacity::new(0.6).unwrap());
+        daemon.handle_reading(Reading::ColorScheme(ColorScheme::NoPreference));
+        assert_eq!(daemon.transparency, Opacity::new(0.8).unwrap());
+
+        daemon.follow_color_scheme = false;
+        daemon.handle_reading(Reading::ColorScheme(ColorScheme::Dark));
+        assert_eq!(daemon.transparency, Opacity::new(0.8).unwrap());
+    }
+
+    #[test]
+    fn backlight_scales_dimming() {
+        let mut daemon = daemon_with("[backlight]\nmin_scale = 0.5\n");
+        daemon.handle_reading(Reading::Backlight(1.0));
+        assert_eq!(daemon.transparency, Opacity::new(0.8).unwrap());
+        daemon.handle_reading(Reading::Backlight(0.5));
+        assert_eq!(daemon.transparency, Opacity::new(0.85).unwrap());
+        daemon.handle_reading(Reading::Backlight(0.0));
+        assert_eq!(daemon.transparency, Opacity::new(0.9).unwrap());
+    }
+
+    #[test]
+    fn ambient_light_picks_opacity() {
+        let mut daemon = daemon_with("[ambient_light]\ndark_opacity = 0.9\nbright_opacity = 0.7\n");
+        // no reading yet
+        assert_eq!(daemon.transparency, Opacity::new(0.8).unwrap());
+        daemon.handle_reading(Reading::AmbientLight(1.0));
+        assert_eq!(daemon.transparency, Opacity::new(0.9).unwrap());
+        daemon.handle_reading(Reading::AmbientLight(100.0));
+        assert_eq!(daemon.transparency, Opacity::new(0.8).unwrap());
+        daemon.handle_reading(Reading::AmbientLight(50_000.0));
+        assert_eq!(daemon.transparency, Opacity::new(0.7).unwrap());
+    }
+
+    #[test]
+    fn eases_between_night_and_day() {
+        let mut daemon = daemon_with(
+            "[solar]\nlatitude = 52.5\nlongitude = 13.4\n\
+             [profiles.day]\nopacity = 0.7\n[profiles.night]\nopacity = 0.9\n",
+        );
+        daemon.handle_reading(Reading::SolarElevation(30.0));
+        assert_eq!(daemon.transparency, Opacity::new(0.7).unwrap());
+        // halfway between the default night and day elevations
+        daemon.handle_reading(Reading::SolarElevation(-1.5));
+        assert_eq!(daemon.transparency, Opacity::new(0.8).unwrap());
+        daemon.handle_reading(Reading::SolarElevation(-20.0));
+        assert_eq!(daemon.transparency, Opacity::new(0.9).unwrap());
+    }
+
+    #[test]
+    fn applies_battery_tiers() {
+        let mut daemon = daemon_with(
+            "[profiles.saver]\nopacity = 0.9\n\
+             [[battery.tiers]]\nbelow = 50\nprofile = 'saver'\n\
+             [[battery.tiers]]\nbelow = 30\ndisable = true\n\
+             [[battery.tiers]]\nbelow = 15\nsuspend_events = true\n",
+        );
+        let battery = |percent, discharging| {
+            Reading::Battery(BatteryState {
+                percent,
+                discharging,
+            })
+        };
+
+        daemon.handle_reading(battery(80, true));
+        assert_eq!(daemon.transparency, Opacity::new(0.8).unwrap());
+        daemon.handle_reading(battery(40, true));
+        assert_eq!(daemon.transparency, Opacity::new(0.9).unwrap());
+        assert_eq!(daemon.pause_reason(), None);
+
+        daemon.handle_reading(battery(20, true));
+        assert_eq!(daemon.pause_reason().as_deref(), Some("battery low"));
+        assert!(daemon.wants_events());
+        daemon.handle_reading(battery(10, true));
+        assert!(!daemon.wants_events());
+
+        // tiers only apply while discharging
+        daemon.handle_reading(battery(10, false));
+        assert!(daemon.wants_events());
+        assert_eq!(daemon.pause_reason(), None);
+        assert_eq!(daemon.transparency, Opacity::new(0.8).unwrap());
+    }
+
+    #[test]
+    fn pauses_while_watched_process_runs() {
+        let mut daemon = daemon();
+        daemon.handle_reading(Reading::WatchedProcess(Some("wf-recorder".to_owned())));
+        assert_eq!(
+            daemon.pause_reason().as_deref(),
+            Some("wf-recorder is running")
+        );
+        daemon.handle_reading(Reading::WatchedProcess(None));
+        assert_eq!(daemon.pause_reason(), None);
+    }
+
+    /// Config file in an empty scratch directory
+    fn scratch_config(name: &str) -> (PathBuf, ConfigPath) {
+        let dir = std::env::temp_dir().join(format!(
+            "transparentd-reload-{}-{}",
+            std::process::id(),
+            name
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("transparentd.toml");
+        std::fs::write(&path, "transparency_at_start = true\nopacity = 0.8\n").unwrap();
+        (dir, ConfigPath::new(Some(path)))
+    }
+
+    fn write_opacity(path: &Path, opacity: f64) {
+        let config = format!("transparency_at_start = true\nopacity = {}\n", opacity);
+        std::fs::write(path, config).unwrap();
+    }
+
+    #[test]
+    fn reloads_once_per_save() {
+        let (dir, config_path) = scratch_config("debounce");
+        let path = config_path.path();
+        let mut workers = Workers::new();
+        let (_active, active_rx, active_wake) = ConfigActive::new();
+        let reloads = spawn_config_reload_thread(
+            &mut workers,
+            active_rx,
+            active_wake,
+            config_path,
+            Duration::from_secs(1),
+        );
+
+        // vim moves the old file to a backup, writes the new one and removes the backup
+        let backup = dir.join("transparentd.toml~");
+        std::fs::rename(&path, &backup).unwrap();
+        write_opacity(&path, 0.6);
+        std::fs::remove_file(&backup).unwrap();
+        write_opacity(&path, 0.5);
+
+        let config = reloads.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(config.opacity, Opacity::new(0.5).unwrap());
+        assert!(reloads.recv_timeout(RELOAD_DEBOUNCE * 3).is_err());
+
+        drop(reloads);
+        workers.stop();
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn keeps_reloading_after_rename_saves() {
+        let (dir, config_path) = scratch_config("rename");
+        let path = config_path.path();
+        let mut workers = Workers::new();
+        let (_active, active_rx, active_wake) = ConfigActive::new();
+        let reloads = spawn_config_reload_thread(
+            &mut workers,
+            active_rx,
+            active_wake,
+            config_path,
+            Duration::from_secs(1),
+        );
+
+        // editors writing a temporary file and renaming it over the config replace the inode
+        let tmp = dir.join(".transparentd.toml.tmp");
+        for &opacity in &[0.6, 0.5] {
+            write_opacity(&tmp, opacity);
+            std::fs::rename(&tmp, &path).unwrap();
+            let config = reloads.recv_timeout(Duration::from_secs(5)).unwrap();
+            assert_eq!(config.opacity, Opacity::new(opacity).unwrap());
+        }
+
+        drop(reloads);
+        workers.stop();
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn polls_config_for_changes() {
+        let (dir, config_path) = scratch_config("poll");
+        let path = config_path.path();
+        let mut workers = Workers::new();
+        let (active, active_rx) = chan::unbounded();
+        let (tx, reloads) = chan::unbounded();
+        spawn_config_poll_thread(
+            &mut workers,
+            active_rx,
+            config_path,
+            Duration::from_millis(20),
+            tx,
+        );
+
+        // mtimes are only as fine grained as the kernel's clock tick
+        thread::sleep(Duration::from_millis(50));
+        write_opacity(&path, 0.6);
+        let config = reloads.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(config.opacity, Opacity::new(0.6).unwrap());
+
+        active.send(false).unwrap();
+        thread::sleep(Duration::from_millis(50));
+        write_opacity(&path, 0.5);
+        assert!(reloads.recv_timeout(Duration::from_millis(100)).is_err());
+        active.send(true).unwrap();
+        let config = reloads.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(config.opacity, Opacity::new(0.5).unwrap());
+
+        drop(reloads);
+        workers.stop();
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn reload_command_keeps_working_config() {
+        let mut root = test_root(vec![
+            test_node(1, vec![], vec![]),
+            test_node(2, vec![], vec![]),
+        ]);
+        root.nodes[0].focused = true;
+        let (mut conn, applied) = fake_wm(root);
+        let (dir, config_path) = scratch_config("command");
+        let path = config_path.path();
+        let mut daemon = daemon();
+        daemon.config_path = config_path;
+
+        write_opacity(&path, 0.6);
+        daemon.handle_cmd(&mut conn, Cmd::Reload).unwrap();
+        assert_eq!(applied.borrow().get(&2), Opacity::new(0.6).as_ref());
+
+        std::fs::write(&path, "opacity = 'half'\n").unwrap();
+        assert!(daemon.reload(&mut conn).is_err());
+        assert_eq!(daemon.base_opacity, Opacity::new(0.6).unwrap());
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn applies_rule_opacities() {
+        let mut root = test_root(vec![
+            test_node(1, vec![], vec![]),
+            test_node(2, vec![], vec![]),
+            test_node(3, vec![], vec![]),
+        ]);
+        root.nodes[0].focused = true;
+        for (node, class) in root.nodes.iter_mut().zip(&["mpv", "mpv", "Alacritty"]) {
+            let mut properties = HashMap::new();
+            properties.insert(WindowProperty::Class, class.to_string());
+            node.window_properties = Some(properties);
+        }
+        let (mut conn, applied) = fake_wm(root);
+
+        let mut daemon = daemon_with(
+            "[[rule]]\nclass = 'mpv'\nfocused = 0.95\nunfocused = 1.0\n\
+             [[rule]]\nclass = 'Alacritty'\nunfocused = 0.7\n",
+        );
+        daemon.apply(&mut conn);
+        let applied = applied.borrow();
+        assert_eq!(applied.get(&1), Opacity::new(0.95).as_ref());
+        assert_eq!(applied.get(&2), Some(&Opacity::max()));
+        assert_eq!(applied.get(&3), Opacity::new(0.7).as_ref());
+    }
+
+    #[test]
+    fn reapplies_everything_after_reconnecting() {
+        let mut root = test_root(vec![
+            test_node(1, vec![], vec![]),
+            test_node(2, vec![], vec![]),
+        ]);
+        root.nodes[0].focused = true;
+        let wm = FakeWm {
+            failing: vec![2].into_iter().collect(),
+            ..FakeWm::new(root)
+        };
+        let batches = wm.batches.clone();
+        let mut conn: WmConnection = Box::new(wm);
+
+        let mut daemon = daemon();
+        for _ in 0..3 {
+            daemon.apply(&mut conn);
+        }
+        assert_eq!(daemon.applier.skipped(), vec![2]);
+        daemon.apply(&mut conn);
+        assert!(batches
+            .borrow()
+            .last()
+            .unwrap()
+            .iter()
+            .all(|&(id, _)| id != 2));
+
+        // con_ids get reused after a restart, the skipped window is someone else now
+        daemon.reconnected(&mut conn);
+        assert!(daemon.applier.skipped().is_empty());
+        assert!(daemon.broadcast.is_none());
+        assert_eq!(batches.borrow().last().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn restores_every_window_on_exit() {
+        let mut root = test_root(vec![
+            test_node(1, vec![], vec![]),
+            test_node(2, vec![], vec![]),
+            test_node(3, vec![], vec![]),
+        ]);
+        root.nodes[0].focused = true;
+        let (mut conn, applied) = fake_wm(root);
+
+        let mut daemon = daemon();
+        daemon.apply(&mut conn);
+        daemon.blacklist.insert(3);
+        remove_all_transparency(&mut daemon.applier, &mut conn).unwrap();
+        let applied = applied.borrow();
+        assert_eq!(applied.len(), 3);
+        assert!(applied.values().all(|&opacity| opacity == Opacity::max()));
+    }
+
+    #[test]
+    fn dims_every_window_while_idle() {
+        let mut root = test_root(vec![
+            test_node(1, vec![], vec![]),
+            test_node(2, vec![], vec![]),
+        ]);
+        root.nodes[0].focused = true;
+        let (mut conn, applied) = fake_wm(root);
+
+        let mut daemon = daemon_with("[idle]\nopacity = 0.5\n");
+        daemon.handle_reading(Reading::Idle(true));
+        daemon.apply(&mut conn);
+        assert_eq!(applied.borrow().get(&1), Opacity::new(0.5).as_ref());
+        assert_eq!(applied.borrow().get(&2), Opacity::new(0.5).as_ref());
+
+        daemon.handle_reading(Reading::Idle(false));
+        daemon.apply(&mut conn);
+        assert_eq!(applied.borrow().get(&1), Some(&Opacity::max()));
+        assert_eq!(applied.borrow().get(&2), Opacity::new(0.8).as_ref());
+    }
+
+    #[test]
+    fn stuck_ipc_client_doesnt_stall_others() {
+        let socket = std::env::temp_dir().join(format!(
+            "transparentd-concurrent-{}.sock",
+            std::process::id()
+        ));
+        let srv = IpcServer::new(Duration::from_secs(5), Some(socket.clone())).unwrap();
+        let (tx, rx) = chan::bounded(1);
+        let mut workers = Workers::new();
+        workers.spawn("ipc", move |cancelled| {
+            serve_ipc(srv.incoming(), &tx, &cancelled)
+        });
+        let waker = socket.clone();
+        workers.on_stop(move || ipc::wake_server(&waker));
+
+        // connected but never says hello
+        let stuck = UnixStream::connect(&socket).unwrap();
+        let client = {
+            let socket = socket.clone();
+            thread::spawn(move || {
+                ipc::Client::connect(Some(&socket))
+                    .unwrap()
+                    .send(Cmd::Toggle)
+                    .unwrap()
+            })
+        };
+        let (cmd, reply) = rx.recv_timeout(Duration::from_secs(1)).unwrap();
+        assert!(matches!(cmd, Cmd::Toggle));
+        reply.send(Response::Ok).unwrap();
+        assert!(matches!(client.join().unwrap(), Response::Ok));
+
+        drop(stuck);
+        drop(rx);
+        workers.stop();
+    }
+
+    #[test]
+    fn replaces_running_daemon() {
+        let socket =
+            std::env::temp_dir().join(format!("transparentd-replace-{}.sock", std::process::id()));
+        let srv = IpcServer::new(Duration::from_secs(5), Some(socket.clone())).unwrap();
+        let running = thread::spawn(move || {
+            let mut conn = srv.incoming().next().unwrap().unwrap();
+            assert!(conn.handshake().unwrap());
+            let cmd = conn.next_cmd().unwrap().unwrap();
+            conn.respond(&Response::Ok).unwrap();
+            // takes a moment to restore opacity before releasing the lock
+            thread::sleep(Duration::from_millis(100));
+            cmd
+        });
+
+        replace_running(Some(&socket)).unwrap();
+        assert!(matches!(running.join().unwrap(), Cmd::Quit));
+        assert!(!socket.exists());
+        // nothing to replace
+        replace_running(Some(&socket)).unwrap();
+        let _ = std::fs::remove_file(socket.with_extension("sock.lock"));
+    }
+
+    proptest! {
+        #[test]
+        fn targets_follow_model(ops in proptest::collection::vec(op(), 1..64)) {
+            let mut daemon = daemon();
+            let mut model = Model {
+                enabled: true,
+                ..Model::default()
+            };
+
+            for op in ops {
+                match op {
+                    Op::Open => {
+                        model.next_id += 1;
+                        model.windows.push(model.next_id);
+                        model.focused = Some(model.next_id);
+                    }
+                    Op::Close(i) if !model.windows.is_empty() => {
+                        let id = model.windows.remove(i % model.windows.len());
+                        if model.focused == Some(id) {
+                            model.focused = None;
+                        }
+                        model.blacklist.remove(&id);
+                        daemon.window_closed(id);
+                    }
+                    Op::Focus(i) if !model.windows.is_empty() => {
+                        model.focused = Some(model.windows[i % model.windows.len()]);
+                    }
+                    Op::Close(_) | Op::Focus(_) => {}
+                    Op::Enable => {
+                        model.enabled = true;
+                        daemon.state.enable();
+                    }
+                    Op::Disable => {
+                        model.enabled = false;
+                        daemon.state.disable();
+                    }
+                    Op::Toggle => {
+                        model.enabled = !model.enabled;
+                        daemon.state.toggle();
+                    }
+                    Op::Blacklist => {
+                        if let Some(id) = model.focused {
+                            model.blacklist.insert(id);
+                            daemon.blacklist.insert(id);
+                        }
+                    }
+                    Op::BlacklistRemove => {
+                        if let Some(id) = model.focused {
+                            model.blacklist.remove(&id);
+                            daemon.blacklist.remove(&id);
+                        }
+                    }
+                    Op::Pause(paused) => model.paused = paused,
+                }
+
+                prop_assert_eq!(daemon.state.is_enabled(), model.enabled);
+                if !model.enabled {
+                    continue;
+                }
+
+                let windows = model.windows.iter().map(|&id| {
+                    let mut node = test_node(id, vec![], vec![]);
+                    node.focused = model.focused == Some(id);
+                    node
+                });
+                let reason = if model.paused {
+                    Some("test".to_owned())
+                } else {
+                    None
+                };
+                let targets: HashMap<_, _> = daemon.targets(windows, reason).into_iter().collect();
+
+                prop_assert_eq!(daemon.state.pause_reason().is_some(), model.paused);
+                prop_assert!(targets.keys().all(|id| model.windows.contains(id)));
+                for &id in &model.windows {
+                    prop_assert_eq!(
+                        targets.get(&id).cloned(),
+                        model.expected(id, daemon.transparency),
+                        "window {} in {:?}",
+                        id,
+                        model
+                    );
+                }
+                prop_assert!(daemon.blacklist.iter().all(|id| model.windows.contains(id)));
+            }
+        }
+    }
+}
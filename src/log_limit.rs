@@ -0,0 +1,118 @@
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    time::{Duration, Instant},
+};
+
+/// How long identical warnings get suppressed after being logged once
+pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(60);
+
+struct Entry {
+    logged_at: Instant,
+    suppressed: usize,
+}
+
+/// Deduplicates recurring warnings, e.g. a broken config that fails to load on every inotify
+/// event. Identical messages are only logged once per interval, the amount of swallowed repeats
+/// gets summarized when the interval is over.
+pub struct LogLimiter {
+    interval: Duration,
+    entries: HashMap<String, Entry>,
+}
+
+impl Default for LogLimiter {
+    fn default() -> Self {
+        Self::new(DEFAULT_INTERVAL)
+    }
+}
+
+impl LogLimiter {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            entries: HashMap::new(),
+        }
+    }
+
+    pub fn warn<D>(&mut self, msg: D)
+    where
+        D: Display,
+    {
+        for line in self.limit(msg.to_string(), Instant::now()) {
+            log::warn!("{}", line);
+        }
+    }
+
+    /// What to log for `msg` arriving at `now`, summaries of expired repeats come first
+    fn limit(&mut self, msg: String, now: Instant) -> Vec<String> {
+        let mut lines = self.flush_expired(now);
+        match self.entries.get_mut(&msg) {
+            Some(entry) => entry.suppressed += 1,
+            None => {
+                lines.push(msg.clone());
+                self.entries.insert(
+                    msg,
+                    Entry {
+                        logged_at: now,
+                        suppressed: 0,
+                    },
+                );
+            }
+        }
+        lines
+    }
+
+    fn flush_expired(&mut self, now: Instant) -> Vec<String> {
+        let interval = self.interval;
+        let mut lines = Vec::new();
+        self.entries.retain(|msg, entry| {
+            let expired = now.saturating_duration_since(entry.logged_at) >= interval;
+            if expired && entry.suppressed > 0 {
+                lines.push(format!(
+                    "{} (repeated {} more times in the last {}s)",
+                    msg,
+                    entry.suppressed,
+                    interval.as_secs()
+                ));
+            }
+            !expired
+        });
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn suppresses_repeats_within_interval() {
+        let start = Instant::now();
+        let mut limiter = LogLimiter::new(Duration::from_secs(10));
+        assert_eq!(limiter.limit("broken".to_owned(), start), vec!["broken"]);
+        for secs in 1..4 {
+            let now = start + Duration::from_secs(secs);
+            assert!(limiter.limit("broken".to_owned(), now).is_empty());
+        }
+        let other = limiter.limit("other".to_owned(), start + Duration::from_secs(5));
+        assert_eq!(other, vec!["other"]);
+    }
+
+    #[test]
+    fn summarizes_suppressed_repeats() {
+        let start = Instant::now();
+        let mut limiter = LogLimiter::new(Duration::from_secs(10));
+        limiter.limit("broken".to_owned(), start);
+        limiter.limit("broken".to_owned(), start + Duration::from_secs(1));
+        limiter.limit("broken".to_owned(), start + Duration::from_secs(2));
+        assert_eq!(
+            limiter.limit("broken".to_owned(), start + Duration::from_secs(10)),
+            vec!["broken (repeated 2 more times in the last 10s)", "broken"]
+        );
+        // nothing was swallowed this time around
+        assert_eq!(
+            limiter.limit("broken".to_owned(), start + Duration::from_secs(20)),
+            vec!["broken"]
+        );
+    }
+}
@@ -0,0 +1,72 @@
+use std::{fs, io, path::Path};
+
+use structopt::{clap::AppSettings, StructOpt};
+
+use crate::Opt;
+
+/// Help text clap would print for the given arguments
+fn help(args: &[&str]) -> String {
+    let app = Opt::clap().global_setting(AppSettings::ColorNever);
+    match app.get_matches_from_safe(args) {
+        Err(e) => e.message,
+        Ok(_) => String::new(),
+    }
+}
+
+/// Visible subcommands as listed in the top level help
+fn subcommands(help: &str) -> Vec<String> {
+    help.lines()
+        .skip_while(|line| line.trim() != "SUBCOMMANDS:")
+        .skip(1)
+        .take_while(|line| line.starts_with(' '))
+        .filter_map(|line| line.split_whitespace().next())
+        .filter(|name| *name != "help")
+        .map(str::to_owned)
+        .collect()
+}
+
+fn escape(text: &str) -> String {
+    text.lines()
+        .map(|line| {
+            let line = line.replace('\\', "\\e");
+            if line.starts_with('.') || line.starts_with('\'') {
+                format!("\\&{}", line)
+            } else {
+                line
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn preformatted(page: &mut String, text: &str) {
+    page.push_str(".nf\n");
+    page.push_str(&escape(text.trim_end()));
+    page.push_str("\n.fi\n");
+}
+
+/// Renders transparentd(1) from the structopt definitions
+pub fn render() -> String {
+    let top = help(&["transparentd", "--help"]);
+
+    let mut page = String::new();
+    page.push_str(".TH TRANSPARENTD 1\n");
+    page.push_str(".SH NAME\n");
+    page.push_str("transparentd \\- dim unfocused windows in i3 and sway\n");
+    page.push_str(".SH DESCRIPTION\n");
+    preformatted(&mut page, &top);
+    page.push_str(".SH COMMANDS\n");
+    for name in subcommands(&top) {
+        page.push_str(&format!(".SS {}\n", name));
+        preformatted(&mut page, &help(&["transparentd", &name, "--help"]));
+    }
+    page
+}
+
+pub fn run(dir: &Path) -> Result<(), io::Error> {
+    fs::create_dir_all(dir)?;
+    let path = dir.join("transparentd.1");
+    fs::write(&path, render())?;
+    println!("{}", path.display());
+    Ok(())
+}
@@ -0,0 +1,226 @@
+use std::{cmp::Ordering, fmt, str::FromStr};
+
+use serde::{
+    de::{self, Deserialize, Deserializer, Visitor},
+    Serialize, Serializer,
+};
+use snafu::{ResultExt, Snafu};
+
+/// Opacity between 0.0 (invisible) and 1.0 (opaque), never NaN.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Opacity(f64);
+
+/// Results of arithmetic get rounded to this many decimal places so repeated steps don't
+/// accumulate float noise like 0.30000000000000004
+const PRECISION: f64 = 10_000.0;
+
+fn round(value: f64) -> f64 {
+    (value * PRECISION).round() / PRECISION
+}
+
+impl Opacity {
+    pub fn new(opacity: f64) -> Option<Self> {
+        if opacity >= 0.0 && opacity <= 1.0 {
+            Some(Self(opacity))
+        } else {
+            None
+        }
+    }
+
+    pub fn max() -> Self {
+        Self(1.0)
+    }
+
+    pub fn min() -> Self {
+        Self(0.0)
+    }
+
+    pub fn value(self) -> f64 {
+        self.0
+    }
+
+    /// Adds `delta`, `None` if the result would leave `[0.0, 1.0]`.
+    pub fn checked_add(self, delta: f64) -> Option<Self> {
+        Self::new(round(self.0 + delta))
+    }
+
+    /// Adds `delta`, clamping the result to `[0.0, 1.0]`.
+    pub fn saturating_add(self, delta: f64) -> Self {
+        Self(round(self.0 + delta).max(0.0).min(1.0))
+    }
+
+    /// Restricts the opacity to `[floor, ceiling]`, the floor wins if the bounds are swapped.
+    pub fn clamp(self, floor: Opacity, ceiling: Opacity) -> Self {
+        Self(self.0.min(ceiling.0).max(floor.0))
+    }
+
+    /// Linear interpolation towards `other`, `t` gets clamped to `[0.0, 1.0]`.
+    pub fn lerp(self, other: Opacity, t: f64) -> Self {
+        let t = t.max(0.0).min(1.0);
+        Self(round(self.0 + (other.0 - self.0) * t))
+    }
+
+    /// Scales how far the opacity is away from fully opaque, a factor of 0.0 makes it opaque.
+    pub fn scale_dim(self, factor: f64) -> Self {
+        let factor = factor.max(0.0).min(1.0);
+        Self(round(1.0 - (1.0 - self.0) * factor))
+    }
+
+    /// Changes the opacity by `delta` without leaving `[floor, ceiling]`.
+    pub fn step(self, delta: f64, floor: Opacity, ceiling: Opacity) -> Self {
+        self.saturating_add(delta).clamp(floor, ceiling)
+    }
+}
+
+// opacities are never NaN so they are totally ordered
+impl Eq for Opacity {}
+
+impl Ord for Opacity {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.partial_cmp(&other.0).unwrap_or(Ordering::Equal)
+    }
+}
+
+impl PartialOrd for Opacity {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for Opacity {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        // shortest representation that round trips, e.g. 0.85 instead of 0.850
+        write!(fmt, "{}", self.0)
+    }
+}
+
+#[derive(Snafu, Debug)]
+pub enum ParseOpacityError {
+    #[snafu(display("Invalid opacity {}: {}", input, source))]
+    Number {
+        input: String,
+        source: std::num::ParseFloatError,
+    },
+
+    #[snafu(display(
+        "Opacity out of range: {}, must be between 0.0 and 1.0 or 0% and 100%",
+        input
+    ))]
+    OutOfRange { input: String },
+}
+
+/// Accepts floats like `0.85` and percentages like `85%`
+impl FromStr for Opacity {
+    type Err = ParseOpacityError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let trimmed = s.trim();
+        let value = if let Some(percent) = trimmed.strip_suffix('%') {
+            let percent: f64 = percent.trim_end().parse().context(Number { input: s })?;
+            percent / 100.0
+        } else {
+            trimmed.parse().context(Number { input: s })?
+        };
+
+        Opacity::new(value).ok_or_else(|| ParseOpacityError::OutOfRange {
+            input: s.to_owned(),
+        })
+    }
+}
+
+struct OpacityVisitor;
+
+impl<'de> Visitor<'de> for OpacityVisitor {
+    type Value = Opacity;
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("an opacity value between 0.0 and 1.0 or a percentage")
+    }
+
+    fn visit_f64<E>(self, value: f64) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        Opacity::new(value).ok_or_else(|| {
+            E::custom(format!(
+                "float out of range: {}, must be between 0.0 and 1.0",
+                value
+            ))
+        })
+    }
+
+    fn visit_i64<E>(self, value: i64) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        self.visit_f64(value as f64)
+    }
+
+    fn visit_u64<E>(self, value: u64) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        self.visit_f64(value as f64)
+    }
+
+    fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        value.parse().map_err(E::custom)
+    }
+}
+
+impl<'de> Deserialize<'de> for Opacity {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_any(OpacityVisitor)
+    }
+}
+
+impl Serialize for Opacity {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_f64(self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_floats_and_percentages() {
+        assert_eq!("0.85".parse::<Opacity>().unwrap(), Opacity(0.85));
+        assert_eq!("85%".parse::<Opacity>().unwrap(), Opacity(0.85));
+        assert_eq!(" 100 % ".parse::<Opacity>().unwrap(), Opacity::max());
+        assert!("1.5".parse::<Opacity>().is_err());
+        assert!("-1%".parse::<Opacity>().is_err());
+        assert!("NaN".parse::<Opacity>().is_err());
+        assert!("opaque".parse::<Opacity>().is_err());
+    }
+
+    #[test]
+    fn display_round_trips() {
+        for s in &["0", "0.85", "0.333", "1"] {
+            assert_eq!(s.parse::<Opacity>().unwrap().to_string(), *s);
+        }
+    }
+
+    #[test]
+    fn arithmetic_clamps() {
+        let opacity = Opacity(0.1);
+        assert_eq!(opacity.saturating_add(0.2), Opacity(0.3));
+        assert_eq!(opacity.saturating_add(-0.5), Opacity::min());
+        assert_eq!(opacity.checked_add(-0.5), None);
+        assert_eq!(Opacity(0.95).checked_add(0.05), Some(Opacity::max()));
+        assert_eq!(
+            Opacity(0.5).step(0.4, Opacity(0.2), Opacity(0.8)),
+            Opacity(0.8)
+        );
+        assert!(Opacity(0.2) < Opacity(0.8));
+    }
+}
@@ -0,0 +1,154 @@
+use std::{env, fmt};
+
+use crate::status::Status;
+
+/// Client side printing, colored and aligned for humans when writing to a terminal and plain
+/// `key: value` lines for scripts otherwise.
+#[derive(Debug, Copy, Clone)]
+pub struct Output {
+    color: bool,
+}
+
+#[derive(Debug, Copy, Clone)]
+enum Color {
+    Red,
+    Green,
+    Yellow,
+    Dim,
+}
+
+impl Color {
+    fn code(self) -> &'static str {
+        match self {
+            Color::Red => "31",
+            Color::Green => "32",
+            Color::Yellow => "33",
+            Color::Dim => "2",
+        }
+    }
+}
+
+/// Honors https://no-color.org
+fn color_allowed() -> bool {
+    env::var_os("NO_COLOR").is_none()
+}
+
+impl Output {
+    pub fn stdout() -> Self {
+        Self {
+            color: atty::is(atty::Stream::Stdout) && color_allowed(),
+        }
+    }
+
+    pub fn stderr() -> Self {
+        Self {
+            color: atty::is(atty::Stream::Stderr) && color_allowed(),
+        }
+    }
+
+    fn paint(self, color: Color, text: impl fmt::Display) -> String {
+        if self.color {
+            format!("\x1b[{}m{}\x1b[0m", color.code(), text)
+        } else {
+            text.to_string()
+        }
+    }
+
+    fn bold(self, text: impl fmt::Display) -> String {
+        if self.color {
+            format!("\x1b[1m{}\x1b[0m", text)
+        } else {
+            text.to_string()
+        }
+    }
+
+    fn ids(self, ids: &[i64]) -> String {
+        if ids.is_empty() {
+            self.paint(Color::Dim, "none")
+        } else {
+            ids.iter()
+                .map(i64::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        }
+    }
+
+    pub fn status(self, status: &Status) {
+        print!("{}", self.format_status(status));
+    }
+
+    fn format_status(self, status: &Status) -> String {
+        if !self.color {
+            return status.to_string();
+        }
+
+        let state = match status.state.as_str() {
+            "enabled" => self.paint(Color::Green, &status.state),
+            "paused" => self.paint(Color::Yellow, &status.state),
+            _ => self.paint(Color::Dim, &status.state),
+        };
+        let degraded = if status.degraded {
+            self.paint(Color::Red, "yes, run `transparentd enable` to retry")
+        } else {
+            "no".to_owned()
+        };
+
+        let mut rows = vec![("state", state)];
+        if let Some(ref reason) = status.pause_reason {
+            rows.push(("paused by", reason.clone()));
+        }
+        rows.push(("opacity", status.opacity.to_string()));
+        rows.push(("degraded", degraded));
+        rows.push(("blacklisted", self.ids(&status.blacklisted)));
+        rows.push(("skipped", self.ids(&status.skipped)));
+        for window in &status.failing {
+            rows.push(("failing", self.paint(Color::Red, &window.to_string())));
+        }
+
+        let width = rows.iter().map(|(key, _)| key.len()).max().unwrap_or(0);
+        rows.into_iter()
+            .map(|(key, value)| {
+                format!("{}  {}\n", self.bold(format!("{:<1$}", key, width)), value)
+            })
+            .collect()
+    }
+
+    pub fn error(self, e: impl fmt::Display) {
+        eprintln!("{} {}", self.paint(Color::Red, "error:"), e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::opacity::Opacity;
+
+    fn status() -> Status {
+        Status {
+            state: "paused".to_owned(),
+            pause_reason: Some("battery low".to_owned()),
+            opacity: Opacity::new(0.8).unwrap(),
+            degraded: false,
+            blacklisted: vec![3, 7],
+            skipped: vec![],
+            failing: vec![],
+        }
+    }
+
+    #[test]
+    fn aligns_colored_status() {
+        let output = Output { color: true }.format_status(&status());
+        let lines: Vec<_> = output.lines().collect();
+        assert_eq!(lines[0], "\x1b[1mstate      \x1b[0m  \x1b[33mpaused\x1b[0m");
+        assert_eq!(lines[1], "\x1b[1mpaused by  \x1b[0m  battery low");
+        assert_eq!(lines[4], "\x1b[1mblacklisted\x1b[0m  3, 7");
+        assert_eq!(lines[5], "\x1b[1mskipped    \x1b[0m  \x1b[2mnone\x1b[0m");
+    }
+
+    #[test]
+    fn plain_status_for_scripts() {
+        let output = Output { color: false }.format_status(&status());
+        assert_eq!(output, status().to_string());
+        assert!(!output.contains('\x1b'));
+    }
+}
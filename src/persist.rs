@@ -0,0 +1,169 @@
+//! Blacklisted windows saved to `$XDG_STATE_HOME/transparentd/blacklist.json`.
+//! con_ids change with every i3 restart so windows are remembered by class, instance and title.
+
+use std::{
+    collections::HashSet,
+    env, fs, io,
+    path::{Path, PathBuf},
+};
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::i3::WindowInfo;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct WindowKey {
+    pub class: Option<String>,
+    pub instance: Option<String>,
+    pub title: Option<String>,
+}
+
+impl From<&WindowInfo> for WindowKey {
+    fn from(info: &WindowInfo) -> Self {
+        Self {
+            class: info.class.clone(),
+            instance: info.instance.clone(),
+            title: info.title.clone(),
+        }
+    }
+}
+
+fn state_path() -> Option<PathBuf> {
+    let state_home = env::var_os("XDG_STATE_HOME")
+        .map(PathBuf::from)
+        .filter(|path| path.is_absolute())
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".local/state")))?;
+    Some(state_home.join("transparentd").join("blacklist.json"))
+}
+
+#[derive(Debug, Default)]
+pub struct PersistedBlacklist {
+    /// Nothing gets saved without a path
+    path: Option<PathBuf>,
+    keys: HashSet<WindowKey>,
+}
+
+impl PersistedBlacklist {
+    /// Loads the saved blacklist, a missing or broken file just means nothing was saved
+    pub fn load() -> Self {
+        Self::load_from(state_path())
+    }
+
+    fn load_from(path: Option<PathBuf>) -> Self {
+        let keys = path
+            .as_ref()
+            .and_then(|path| match fs::read(path) {
+                Ok(content) => serde_json::from_slice(&content)
+                    .map_err(|e| log::warn!("Ignoring broken {}: {}", path.display(), e))
+                    .ok(),
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => None,
+                Err(e) => {
+                    log::warn!("Can't read {}: {}", path.display(), e);
+                    None
+                }
+            })
+            .unwrap_or_default();
+        Self { path, keys }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    pub fn contains(&self, key: &WindowKey) -> bool {
+        self.keys.contains(key)
+    }
+
+    pub fn insert(&mut self, key: WindowKey) {
+        if self.keys.insert(key) {
+            self.save();
+        }
+    }
+
+    pub fn remove(&mut self, key: &WindowKey) {
+        if self.keys.remove(key) {
+            self.save();
+        }
+    }
+
+    pub fn clear(&mut self) {
+        if !self.keys.is_empty() {
+            self.keys.clear();
+            self.save();
+        }
+    }
+
+    fn save(&self) {
+        if let Some(ref path) = self.path {
+            if let Err(e) = self.write(path) {
+                log::warn!("Can't save blacklist to {}: {}", path.display(), e);
+            }
+        }
+    }
+
+    /// Written to a temporary file first so a crash can't leave a truncated file behind
+    fn write(&self, path: &Path) -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut keys: Vec<_> = self.keys.iter().collect();
+        keys.sort_by(|a, b| {
+            (&a.class, &a.instance, &a.title).cmp(&(&b.class, &b.instance, &b.title))
+        });
+        let content = serde_json::to_vec_pretty(&keys)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, content)?;
+        fs::rename(tmp, path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(class: &str, title: &str) -> WindowKey {
+        WindowKey {
+            class: Some(class.to_owned()),
+            instance: None,
+            title: Some(title.to_owned()),
+        }
+    }
+
+    #[test]
+    fn survives_reloading() {
+        let path = env::temp_dir()
+            .join(format!("transparentd-state-{}", std::process::id()))
+            .join("blacklist.json");
+        let mut blacklist = PersistedBlacklist::load_from(Some(path.clone()));
+        assert!(blacklist.is_empty());
+        blacklist.insert(key("mpv", "video.mkv"));
+        blacklist.insert(key("Firefox", "YouTube"));
+        blacklist.remove(&key("Firefox", "YouTube"));
+
+        let reloaded = PersistedBlacklist::load_from(Some(path.clone()));
+        assert!(reloaded.contains(&key("mpv", "video.mkv")));
+        assert!(!reloaded.contains(&key("Firefox", "YouTube")));
+        // a window with the same class but another title isn't the same window
+        assert!(!reloaded.contains(&key("mpv", "other.mkv")));
+
+        fs::write(&path, "[{").unwrap();
+        let broken = PersistedBlacklist::load_from(Some(path.clone()));
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+        assert!(broken.is_empty());
+    }
+
+    #[test]
+    fn clearing_is_saved() {
+        let path = env::temp_dir()
+            .join(format!("transparentd-clear-{}", std::process::id()))
+            .join("blacklist.json");
+        let mut blacklist = PersistedBlacklist::load_from(Some(path.clone()));
+        blacklist.insert(key("mpv", "video.mkv"));
+        blacklist.clear();
+
+        let reloaded = PersistedBlacklist::load_from(Some(path.clone()));
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+        assert!(reloaded.is_empty());
+    }
+}
@@ -0,0 +1,71 @@
+//! Polls the X11 pointer position and reports it once it rested for the hover delay.
+//! Sway doesn't expose the cursor through IPC so this only works on X11.
+
+use std::time::Duration;
+
+use crossbeam_channel as chan;
+
+use crate::{config::HoverConfig, sensors::Reading};
+
+#[cfg(feature = "x11")]
+pub fn spawn_watcher(config: &HoverConfig, tx: chan::Sender<Reading>) {
+    let poll = Duration::from_millis(config.poll_ms.max(10));
+    let delay = Duration::from_millis(config.delay_ms);
+
+    // FIXME: unjoined thread
+    std::thread::spawn(move || {
+        if let Err(e) = x11::watch(poll, delay, tx) {
+            log::warn!("Can't track the pointer: {}", e);
+        }
+    });
+}
+
+#[cfg(not(feature = "x11"))]
+pub fn spawn_watcher(_: &HoverConfig, _: chan::Sender<Reading>) {
+    log::warn!("transparentd was built without the x11 feature, can't reveal hovered windows");
+}
+
+#[cfg(feature = "x11")]
+mod x11 {
+    use std::{
+        error::Error,
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use crossbeam_channel as chan;
+    use x11rb::protocol::xproto::ConnectionExt;
+
+    use crate::sensors::Reading;
+
+    pub fn watch(
+        poll: Duration,
+        delay: Duration,
+        tx: chan::Sender<Reading>,
+    ) -> Result<(), Box<dyn Error>> {
+        let (conn, screen) = x11rb::connect(None)?;
+        let root = conn.setup().roots[screen].root;
+
+        let mut last = None;
+        let mut still_since = Instant::now();
+        let mut reported = None;
+        loop {
+            let reply = conn.query_pointer(root)?.reply()?;
+            let pos = (i32::from(reply.root_x), i32::from(reply.root_y));
+            if last != Some(pos) {
+                last = Some(pos);
+                still_since = Instant::now();
+                // moving, hide again until it rests
+                if reported.take().is_some() && tx.send(Reading::Pointer(None)).is_err() {
+                    return Ok(());
+                }
+            } else if reported != Some(pos) && still_since.elapsed() >= delay {
+                reported = Some(pos);
+                if tx.send(Reading::Pointer(Some(pos))).is_err() {
+                    return Ok(());
+                }
+            }
+            thread::sleep(poll);
+        }
+    }
+}
@@ -0,0 +1,84 @@
+//! Polls /proc for processes that should pause dimming, e.g. screen recorders.
+
+use std::{fs, io, thread, time::Duration};
+
+use crossbeam_channel as chan;
+
+use crate::sensors::Reading;
+
+/// The kernel truncates `comm` to this many bytes
+const COMM_LEN: usize = 15;
+
+fn comm_matches(name: &str, comm: &str) -> bool {
+    comm == name || (comm.len() == COMM_LEN && name.starts_with(comm))
+}
+
+/// Returns the first watched process that's currently running
+fn find_running(names: &[String]) -> io::Result<Option<String>> {
+    for entry in fs::read_dir("/proc")? {
+        let entry = entry?;
+        if !entry
+            .file_name()
+            .to_string_lossy()
+            .bytes()
+            .all(|b| b.is_ascii_digit())
+        {
+            continue;
+        }
+
+        // processes can exit while iterating
+        let comm = match fs::read_to_string(entry.path().join("comm")) {
+            Ok(comm) => comm,
+            Err(_) => continue,
+        };
+        let comm = comm.trim_end();
+        if let Some(name) = names.iter().find(|name| comm_matches(name, comm)) {
+            return Ok(Some(name.clone()));
+        }
+    }
+
+    Ok(None)
+}
+
+pub fn spawn_watcher(names: Vec<String>, interval: Duration, tx: chan::Sender<Reading>) {
+    // FIXME: unjoined thread
+    thread::spawn(move || {
+        let mut last = None;
+        loop {
+            match find_running(&names) {
+                Ok(running) => {
+                    if running != last {
+                        last = running.clone();
+                        if tx.send(Reading::WatchedProcess(running)).is_err() {
+                            return;
+                        }
+                    }
+                }
+                Err(e) => log::debug!("Can't list processes: {}", e),
+            }
+            thread::sleep(interval);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_truncated_comm() {
+        assert!(comm_matches("ffmpeg", "ffmpeg"));
+        assert!(!comm_matches("ffmpeg", "ffmpeg2"));
+        assert!(comm_matches("wf-recorder-wrapped", "wf-recorder-wra"));
+        // shorter names aren't truncated
+        assert!(!comm_matches("wf-recorder", "wf-rec"));
+    }
+
+    #[test]
+    fn finds_running_process() {
+        let own = fs::read_to_string("/proc/self/comm").unwrap();
+        let names = vec!["no-such-process".to_owned(), own.trim_end().to_owned()];
+        assert_eq!(find_running(&names).unwrap(), Some(names[1].clone()));
+        assert_eq!(find_running(&names[..1]).unwrap(), None);
+    }
+}
@@ -0,0 +1,88 @@
+//! Single values clients can ask the daemon for, e.g. for status bars and scripts.
+
+use std::{fmt, str::FromStr};
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::opacity::Opacity;
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+pub enum Query {
+    State,
+    Opacity,
+    Blacklist,
+    Rules,
+}
+
+impl FromStr for Query {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "state" => Ok(Query::State),
+            "opacity" => Ok(Query::Opacity),
+            "blacklist" => Ok(Query::Blacklist),
+            "rules" => Ok(Query::Rules),
+            _ => Err(format!(
+                "unknown query {}, expected state, opacity, blacklist or rules",
+                s
+            )),
+        }
+    }
+}
+
+/// The daemon's answer to a `Query`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Answer {
+    State {
+        /// `enabled`, `disabled` or `paused`
+        state: String,
+        pause_reason: Option<String>,
+    },
+    Opacity(Opacity),
+    /// con_ids of windows excluded from dimming
+    Blacklist(Vec<i64>),
+    /// Active rules in config order
+    Rules(Vec<String>),
+    /// Windows excluded from dimming and the criteria given to `blacklist`
+    BlacklistEntries {
+        windows: Vec<BlacklistEntry>,
+        criteria: Vec<String>,
+    },
+}
+
+/// A blacklisted window, with class and title so people can tell which one it is
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BlacklistEntry {
+    pub id: i64,
+    pub class: Option<String>,
+    pub title: Option<String>,
+}
+
+/// One value per line for shell scripts
+impl fmt::Display for Answer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Answer::State { state, .. } => writeln!(f, "{}", state),
+            Answer::Opacity(opacity) => writeln!(f, "{}", opacity),
+            Answer::Blacklist(ids) => ids.iter().try_for_each(|id| writeln!(f, "{}", id)),
+            Answer::Rules(rules) => rules.iter().try_for_each(|rule| writeln!(f, "{}", rule)),
+            Answer::BlacklistEntries { windows, criteria } => {
+                let or_dash = |value: &Option<String>| value.as_ref().map_or("-", String::as_str);
+                for window in windows {
+                    writeln!(
+                        f,
+                        "{}\t{}\t{}",
+                        window.id,
+                        or_dash(&window.class),
+                        or_dash(&window.title)
+                    )?;
+                }
+                criteria
+                    .iter()
+                    .try_for_each(|criteria| writeln!(f, "[{}]", criteria))
+            }
+        }
+    }
+}
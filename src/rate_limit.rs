@@ -0,0 +1,70 @@
+use std::time::{Duration, Instant};
+
+/// Token bucket capping how many opacity commands get sent to the window manager per second,
+/// so event storms can't flood it. Up to a second worth of commands can be sent in a burst.
+pub struct RateLimiter {
+    /// Commands per second, 0 doesn't limit at all
+    rate: u32,
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(rate: u32) -> Self {
+        Self {
+            rate,
+            tokens: f64::from(rate),
+            refilled_at: Instant::now(),
+        }
+    }
+
+    pub fn set_rate(&mut self, rate: u32) {
+        if rate != self.rate {
+            *self = Self::new(rate);
+        }
+    }
+
+    /// Takes a token, or returns how long it takes until the next one is available
+    pub fn acquire(&mut self, now: Instant) -> Result<(), Duration> {
+        if self.rate == 0 {
+            return Ok(());
+        }
+
+        let rate = f64::from(self.rate);
+        let elapsed = now.saturating_duration_since(self.refilled_at);
+        self.tokens = (self.tokens + elapsed.as_secs_f64() * rate).min(rate);
+        self.refilled_at = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - self.tokens) / rate))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn refills_over_time() {
+        let start = Instant::now();
+        let mut limiter = RateLimiter::new(2);
+        limiter.refilled_at = start;
+
+        assert!(limiter.acquire(start).is_ok());
+        assert!(limiter.acquire(start).is_ok());
+        let wait = limiter.acquire(start).unwrap_err();
+        assert_eq!(wait, Duration::from_millis(500));
+        assert!(limiter.acquire(start + wait).is_ok());
+    }
+
+    #[test]
+    fn zero_is_unlimited() {
+        let now = Instant::now();
+        let mut limiter = RateLimiter::new(0);
+        assert!((0..1000).all(|_| limiter.acquire(now).is_ok()));
+    }
+}
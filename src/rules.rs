@@ -0,0 +1,336 @@
+use std::fmt;
+
+use regex::Regex;
+
+use crate::{config::RuleConfig, i3::WindowInfo, opacity::Opacity};
+
+/// Per application opacities, the first matching rule decides a window's opacity
+#[derive(Debug, Default)]
+pub struct Rules {
+    rules: Vec<Rule>,
+}
+
+#[derive(Debug)]
+struct Rule {
+    class: Option<String>,
+    instance: Option<String>,
+    title: Option<Regex>,
+    workspace: Option<String>,
+    output: Option<String>,
+    focused: Option<Opacity>,
+    unfocused: Option<Opacity>,
+}
+
+impl Rule {
+    /// Every given criterion has to match, a rule without any matches nothing
+    fn matches(&self, window: &WindowInfo) -> bool {
+        let criteria = [
+            (&self.class, &window.class),
+            (&self.instance, &window.instance),
+            (&self.output, &window.output),
+        ];
+        let title_matches = match (&self.title, &window.title) {
+            (Some(re), Some(title)) => re.is_match(title),
+            (Some(_), None) => false,
+            (None, _) => true,
+        };
+        let workspace_matches = match (&self.workspace, &window.workspace) {
+            (Some(wanted), Some(name)) => {
+                wanted == name || workspace_number(name) == Some(wanted.as_str())
+            }
+            (Some(_), None) => false,
+            (None, _) => true,
+        };
+        (self.title.is_some()
+            || self.workspace.is_some()
+            || criteria.iter().any(|(wanted, _)| wanted.is_some()))
+            && title_matches
+            && workspace_matches
+            && criteria
+                .iter()
+                .all(|(wanted, actual)| wanted.is_none() || wanted == actual)
+    }
+}
+
+/// Criteria like in the config followed by the opacities, e.g.
+/// `class = 'mpv' => focused default, unfocused 1`
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let criteria = [
+            ("class", self.class.as_ref().map(String::as_str)),
+            ("instance", self.instance.as_ref().map(String::as_str)),
+            ("match_title", self.title.as_ref().map(Regex::as_str)),
+            ("workspace", self.workspace.as_ref().map(String::as_str)),
+            ("output", self.output.as_ref().map(String::as_str)),
+        ];
+        for (key, value) in criteria.iter() {
+            if let Some(value) = value {
+                write!(f, "{} = {:?} ", key, value)?;
+            }
+        }
+        let opacity = |opacity: Option<Opacity>| {
+            opacity.map_or_else(|| "default".to_owned(), |opacity| opacity.to_string())
+        };
+        write!(
+            f,
+            "=> focused {}, unfocused {}",
+            opacity(self.focused),
+            opacity(self.unfocused)
+        )
+    }
+}
+
+/// i3 takes the leading digits of a workspace name like `1: web` as its number
+fn workspace_number(name: &str) -> Option<&str> {
+    let end = name
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(name.len());
+    if end > 0 {
+        Some(&name[..end])
+    } else {
+        None
+    }
+}
+
+impl Rules {
+    /// Unfocused opacities get restricted to `min` and `max` like everything else from the config.
+    /// Rules with an invalid title regex get skipped with a warning.
+    pub fn new(rules: &[RuleConfig], min: Opacity, max: Opacity) -> Self {
+        Self {
+            rules: rules
+                .iter()
+                .filter_map(|rule| {
+                    let title = match rule.match_title {
+                        Some(ref title) => match Regex::new(title) {
+                            Ok(re) => Some(re),
+                            Err(e) => {
+                                log::warn!("Skipping rule with invalid match_title: {}", e);
+                                return None;
+                            }
+                        },
+                        None => None,
+                    };
+                    Some(Rule {
+                        class: rule.class.clone(),
+                        instance: rule.instance.clone(),
+                        title,
+                        workspace: rule.workspace.clone(),
+                        output: rule.output.clone(),
+                        focused: rule.focused,
+                        unfocused: rule.unfocused.map(|opacity| opacity.clamp(min, max)),
+                    })
+                })
+                .collect(),
+        }
+    }
+
+    /// Whether title changes can change the outcome
+    pub fn match_titles(&self) -> bool {
+        self.rules.iter().any(|rule| rule.title.is_some())
+    }
+
+    /// Whether windows need to be looked up by workspace or output
+    pub fn match_locations(&self) -> bool {
+        self.rules
+            .iter()
+            .any(|rule| rule.workspace.is_some() || rule.output.is_some())
+    }
+
+    /// Human readable form of each rule
+    pub fn describe(&self) -> Vec<String> {
+        self.rules.iter().map(Rule::to_string).collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Opacity set by the first matching rule, `None` if no rule matches or the matching one
+    /// leaves this case to the defaults
+    pub fn opacity_for(&self, window: &WindowInfo, focused: bool) -> Option<Opacity> {
+        let rule = self.rules.iter().find(|rule| rule.matches(window))?;
+        if focused {
+            rule.focused
+        } else {
+            rule.unfocused
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn window(class: &str, title: &str) -> WindowInfo {
+        WindowInfo {
+            id: 1,
+            focused: false,
+            class: Some(class.to_owned()),
+            instance: None,
+            title: Some(title.to_owned()),
+            role: None,
+            app_id: None,
+            workspace: Some("1: web".to_owned()),
+            output: Some("eDP-1".to_owned()),
+        }
+    }
+
+    fn rule(
+        class: Option<&str>,
+        title: Option<&str>,
+        focused: Option<f64>,
+        unfocused: Option<f64>,
+    ) -> RuleConfig {
+        RuleConfig {
+            class: class.map(str::to_owned),
+            instance: None,
+            match_title: title.map(str::to_owned),
+            workspace: None,
+            output: None,
+            focused: focused.and_then(Opacity::new),
+            unfocused: unfocused.and_then(Opacity::new),
+        }
+    }
+
+    #[test]
+    fn first_match_wins() {
+        let rules = Rules::new(
+            &[
+                rule(Some("mpv"), Some("^Picture-in-Picture$"), None, None),
+                rule(Some("mpv"), None, Some(0.95), Some(0.7)),
+                rule(None, Some("mpv"), Some(0.5), Some(0.5)),
+            ],
+            Opacity::min(),
+            Opacity::max(),
+        );
+
+        let video = window("mpv", "video.mkv - mpv");
+        assert_eq!(rules.opacity_for(&video, true), Opacity::new(0.95));
+        assert_eq!(rules.opacity_for(&video, false), Opacity::new(0.7));
+
+        // matches the first rule, which leaves everything to the defaults
+        let pip = window("mpv", "Picture-in-Picture");
+        assert_eq!(rules.opacity_for(&pip, true), None);
+        assert_eq!(rules.opacity_for(&pip, false), None);
+
+        assert_eq!(
+            rules.opacity_for(&window("Alacritty", "mpv"), false),
+            Opacity::new(0.5)
+        );
+        assert_eq!(rules.opacity_for(&window("Alacritty", "vim"), false), None);
+    }
+
+    #[test]
+    fn matches_class_and_instance() {
+        let mut both = rule(Some("Alacritty"), None, None, Some(0.7));
+        both.instance = Some("scratchpad".to_owned());
+        let rules = Rules::new(
+            &[both, rule(Some("Alacritty"), None, None, Some(0.6))],
+            Opacity::min(),
+            Opacity::max(),
+        );
+
+        let mut scratchpad = window("Alacritty", "zsh");
+        scratchpad.instance = Some("scratchpad".to_owned());
+        assert_eq!(rules.opacity_for(&scratchpad, false), Opacity::new(0.7));
+        // classes are compared exactly
+        assert_eq!(
+            rules.opacity_for(&window("Alacritty", "zsh"), false),
+            Opacity::new(0.6)
+        );
+        assert_eq!(rules.opacity_for(&window("alacritty", "zsh"), false), None);
+
+        let empty = Rules::new(
+            &[rule(None, None, None, Some(1.0))],
+            Opacity::min(),
+            Opacity::max(),
+        );
+        assert_eq!(empty.opacity_for(&scratchpad, false), None);
+    }
+
+    #[test]
+    fn matches_title_regexes() {
+        let rules = Rules::new(
+            &[
+                rule(None, Some("(unclosed"), None, Some(0.9)),
+                rule(None, Some(" - YouTube"), None, Some(1.0)),
+            ],
+            Opacity::min(),
+            Opacity::max(),
+        );
+        // the invalid rule is skipped with a warning
+        assert_eq!(rules.describe().len(), 1);
+        assert!(rules.match_titles());
+
+        let video = window("firefox", "Some video - YouTube - Mozilla Firefox");
+        assert_eq!(rules.opacity_for(&video, false), Some(Opacity::max()));
+        let mut untitled = window("firefox", "");
+        untitled.title = None;
+        assert_eq!(rules.opacity_for(&untitled, false), None);
+
+        let without_titles = Rules::new(
+            &[rule(Some("mpv"), None, None, Some(1.0))],
+            Opacity::min(),
+            Opacity::max(),
+        );
+        assert!(!without_titles.match_titles());
+    }
+
+    #[test]
+    fn matches_locations() {
+        let mut by_name = rule(None, None, None, Some(1.0));
+        by_name.workspace = Some("1: web".to_owned());
+        let mut by_number = rule(None, None, None, Some(1.0));
+        by_number.workspace = Some("1".to_owned());
+        let mut other = rule(None, None, None, Some(1.0));
+        other.workspace = Some("10".to_owned());
+        let mut by_output = rule(None, None, None, Some(1.0));
+        by_output.output = Some("eDP-1".to_owned());
+        let mut other_output = rule(None, None, None, Some(1.0));
+        other_output.output = Some("HDMI-1".to_owned());
+
+        let window = window("Firefox", "Mozilla Firefox");
+        for (config, matches) in vec![
+            (by_name, true),
+            (by_number, true),
+            (other, false),
+            (by_output, true),
+            (other_output, false),
+        ] {
+            let rules = Rules::new(&[config], Opacity::min(), Opacity::max());
+            assert_eq!(rules.opacity_for(&window, false).is_some(), matches);
+        }
+    }
+
+    #[test]
+    fn clamps_unfocused_opacity() {
+        let rules = Rules::new(
+            &[rule(Some("mpv"), None, Some(1.0), Some(0.1))],
+            Opacity::new(0.5).unwrap(),
+            Opacity::new(0.9).unwrap(),
+        );
+        let video = window("mpv", "video.mkv");
+        assert_eq!(rules.opacity_for(&video, true), Some(Opacity::max()));
+        assert_eq!(rules.opacity_for(&video, false), Opacity::new(0.5));
+    }
+
+    #[test]
+    fn describes_rules() {
+        let rules = Rules::new(
+            &[rule(
+                Some("mpv"),
+                Some("^Picture-in-Picture$"),
+                None,
+                Some(0.7),
+            )],
+            Opacity::min(),
+            Opacity::max(),
+        );
+        assert_eq!(
+            rules.describe(),
+            vec![
+                r#"class = "mpv" match_title = "^Picture-in-Picture$" => focused default, unfocused 0.7"#
+            ]
+        );
+    }
+}
@@ -0,0 +1,84 @@
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+/// Amount of failed applies within `FAILURE_WINDOW` after which dimming gets disabled
+const MAX_FAILURES: usize = 5;
+const FAILURE_WINDOW: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Default)]
+pub struct SafeMode {
+    failures: VecDeque<Instant>,
+    degraded: bool,
+}
+
+impl SafeMode {
+    /// Records a failed apply. Returns true when this failure tipped the daemon into safe mode.
+    pub fn record_failure(&mut self, now: Instant) -> bool {
+        while self.failures.front().map_or(false, |&failed_at| {
+            now.saturating_duration_since(failed_at) > FAILURE_WINDOW
+        }) {
+            self.failures.pop_front();
+        }
+        self.failures.push_back(now);
+
+        if !self.degraded && self.failures.len() >= MAX_FAILURES {
+            self.degraded = true;
+            self.failures.clear();
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn is_degraded(&self) -> bool {
+        self.degraded
+    }
+
+    pub fn reset(&mut self) {
+        self.degraded = false;
+        self.failures.clear();
+    }
+}
+
+/// Best effort desktop notification, there may be no notification daemon running at all.
+pub fn notify_user(summary: &str, body: &str) {
+    match std::process::Command::new("notify-send")
+        .args(&["--app-name=transparentd", summary, body])
+        .status()
+    {
+        Ok(status) if !status.success() => log::debug!("notify-send exited with {}", status),
+        Ok(_) => {}
+        Err(e) => log::debug!("Can't run notify-send: {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trips_after_failures_in_window() {
+        let start = Instant::now();
+        let mut safe_mode = SafeMode::default();
+        for i in 1..MAX_FAILURES {
+            assert!(!safe_mode.record_failure(start + Duration::from_secs(i as u64)));
+        }
+        assert!(safe_mode.record_failure(start + FAILURE_WINDOW));
+        assert!(safe_mode.is_degraded());
+        // already degraded
+        assert!(!safe_mode.record_failure(start + FAILURE_WINDOW));
+    }
+
+    #[test]
+    fn spread_out_failures_dont_trip() {
+        let start = Instant::now();
+        let mut safe_mode = SafeMode::default();
+        let spacing = FAILURE_WINDOW / (MAX_FAILURES as u32 - 1) + Duration::from_secs(1);
+        for i in 0..MAX_FAILURES * 3 {
+            assert!(!safe_mode.record_failure(start + spacing * i as u32));
+        }
+        assert!(!safe_mode.is_degraded());
+    }
+}
@@ -0,0 +1,235 @@
+//! User scripts deciding the opacity of single windows.
+//!
+//! The script has to define `fn opacity(window, ctx)` which gets called for every window with
+//! maps of window properties and context and returns either a number between 0.0 and 1.0 or `()`
+//! to keep transparentd's default. Calls running away get stopped by rhai's operation and call
+//! depth limits and count as failed.
+
+use std::path::{Path, PathBuf};
+
+use i3ipc::reply::Node;
+use snafu::Snafu;
+
+use crate::opacity::Opacity;
+
+#[derive(Snafu, Debug)]
+pub enum Error {
+    #[snafu(display("Can't load script {}: {}", path.display(), msg))]
+    Load { path: PathBuf, msg: String },
+}
+
+/// What the script gets to know besides the window itself
+#[derive(Debug, Copy, Clone)]
+pub struct Context {
+    pub focused: bool,
+    pub default: Opacity,
+}
+
+#[cfg(feature = "scripting")]
+pub use self::rhai_script::Script;
+
+#[cfg(not(feature = "scripting"))]
+pub use self::disabled::Script;
+
+#[cfg(feature = "scripting")]
+mod rhai_script {
+    use std::path::PathBuf;
+
+    use i3ipc::reply::{Node, WindowProperty};
+    use rhai::{Dynamic, Engine, EvalAltResult, Map, Scope, AST};
+
+    use super::{Context, Error};
+    use crate::opacity::Opacity;
+
+    /// Operations one call may take, the script runs on the event loop for every window
+    const MAX_OPERATIONS: u64 = 100_000;
+    const MAX_CALL_LEVELS: usize = 32;
+
+    pub struct Script {
+        engine: Engine,
+        ast: AST,
+    }
+
+    fn window_map(node: &Node) -> Map {
+        let prop = |prop| {
+            node.window_properties
+                .as_ref()
+                .and_then(|props| props.get(&prop))
+                .cloned()
+                .unwrap_or_default()
+        };
+
+        let mut map = Map::new();
+        map.insert("id".into(), Dynamic::from(node.id));
+        map.insert(
+            "name".into(),
+            Dynamic::from(node.name.clone().unwrap_or_default()),
+        );
+        map.insert("class".into(), Dynamic::from(prop(WindowProperty::Class)));
+        map.insert(
+            "instance".into(),
+            Dynamic::from(prop(WindowProperty::Instance)),
+        );
+        map.insert("title".into(), Dynamic::from(prop(WindowProperty::Title)));
+        map.insert(
+            "role".into(),
+            Dynamic::from(prop(WindowProperty::WindowRole)),
+        );
+        map
+    }
+
+    /// The limit a failed call ran into, looking through the calls the error happened in
+    fn limit_hit(e: &EvalAltResult) -> Option<String> {
+        match e {
+            EvalAltResult::ErrorTooManyOperations(_) => Some(format!(
+                "script stopped after {} operations",
+                MAX_OPERATIONS
+            )),
+            EvalAltResult::ErrorStackOverflow(_) => {
+                Some(format!("script exceeded {} nested calls", MAX_CALL_LEVELS))
+            }
+            EvalAltResult::ErrorInFunctionCall(_, _, e, _) => limit_hit(e),
+            _ => None,
+        }
+    }
+
+    fn context_map(ctx: &Context) -> Map {
+        let mut map = Map::new();
+        map.insert("focused".into(), Dynamic::from(ctx.focused));
+        map.insert("default".into(), Dynamic::from(ctx.default.value()));
+        map
+    }
+
+    impl Script {
+        pub fn load(path: PathBuf) -> Result<Self, Error> {
+            let mut engine = Engine::new();
+            engine.set_max_operations(MAX_OPERATIONS);
+            engine.set_max_call_levels(MAX_CALL_LEVELS);
+            match engine.compile_file(path.clone()) {
+                Ok(ast) => Ok(Self { engine, ast }),
+                Err(e) => Err(Error::Load {
+                    path,
+                    msg: e.to_string(),
+                }),
+            }
+        }
+
+        pub fn opacity(&self, node: &Node, ctx: &Context) -> Result<Option<Opacity>, String> {
+            let ret: Dynamic = self
+                .engine
+                .call_fn(
+                    &mut Scope::new(),
+                    &self.ast,
+                    "opacity",
+                    (window_map(node), context_map(ctx)),
+                )
+                .map_err(|e| limit_hit(&e).unwrap_or_else(|| e.to_string()))?;
+
+            if ret.is::<()>() {
+                return Ok(None);
+            }
+
+            // `1` and `0` are as good as `1.0` and `0.0`
+            ret.as_float()
+                .or_else(|_| ret.as_int().map(|int| int as f64))
+                .ok()
+                .and_then(Opacity::new)
+                .map(Some)
+                .ok_or_else(|| {
+                    format!(
+                        "script returned {}, expected a number between 0.0 and 1.0 or ()",
+                        ret
+                    )
+                })
+        }
+    }
+}
+
+#[cfg(not(feature = "scripting"))]
+mod disabled {
+    use std::path::PathBuf;
+
+    use i3ipc::reply::Node;
+
+    use super::{Context, Error};
+    use crate::opacity::Opacity;
+
+    pub enum Script {}
+
+    impl Script {
+        pub fn load(path: PathBuf) -> Result<Self, Error> {
+            Err(Error::Load {
+                path,
+                msg: "transparentd was built without the scripting feature".to_owned(),
+            })
+        }
+
+        pub fn opacity(&self, _: &Node, _: &Context) -> Result<Option<Opacity>, String> {
+            match *self {}
+        }
+    }
+}
+
+/// Loads the script configured in `script`, relative paths are resolved from the config dir.
+pub fn load_configured(script: Option<&str>, config_path: &Path) -> Option<Script> {
+    let path = config_path.parent().unwrap_or(config_path).join(script?);
+    match Script::load(path) {
+        Ok(script) => Some(script),
+        Err(e) => {
+            log::warn!("{}", e);
+            None
+        }
+    }
+}
+
+/// Asks the script for the opacity of `node`, falling back to `ctx.default` on errors
+pub fn opacity_for(script: Option<&Script>, node: &Node, ctx: &Context) -> Result<Opacity, String> {
+    match script {
+        Some(script) => Ok(script.opacity(node, ctx)?.unwrap_or(ctx.default)),
+        None => Ok(ctx.default),
+    }
+}
+
+#[cfg(all(test, feature = "scripting"))]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::i3::test_node;
+
+    fn run(name: &str, source: &str, focused: bool) -> Result<Option<Opacity>, String> {
+        let path = std::env::temp_dir().join(format!(
+            "transparentd-script-{}-{}.rhai",
+            std::process::id(),
+            name
+        ));
+        fs::write(&path, source).unwrap();
+        let script = Script::load(path.clone()).unwrap();
+        let _ = fs::remove_file(path);
+        let ctx = Context {
+            focused,
+            default: Opacity::new(0.8).unwrap(),
+        };
+        script.opacity(&test_node(1, vec![], vec![]), &ctx)
+    }
+
+    #[test]
+    fn accepts_ints_and_floats() {
+        let source = "fn opacity(window, ctx) { if ctx.focused { 1 } else { 0.5 } }";
+        assert_eq!(run("numbers", source, true), Ok(Some(Opacity::max())));
+        assert_eq!(run("numbers", source, false), Ok(Opacity::new(0.5)));
+        let source = "fn opacity(window, ctx) { 2 }";
+        assert!(run("out-of-range", source, true).is_err());
+    }
+
+    #[test]
+    fn stops_runaway_scripts() {
+        let source = "fn opacity(window, ctx) { loop {} }";
+        let e = run("loop", source, false).unwrap_err();
+        assert!(e.contains("operations"), "{}", e);
+
+        let source = "fn deeper(n) { deeper(n + 1) } fn opacity(window, ctx) { deeper(0) }";
+        let e = run("recursion", source, false).unwrap_err();
+        assert!(e.contains("nested calls"), "{}", e);
+    }
+}
@@ -0,0 +1,122 @@
+//! Threads watching the environment of the desktop, they all report through one channel.
+
+use std::time::Duration;
+
+use crossbeam_channel as chan;
+
+use crate::{
+    ambient_light, backlight,
+    battery::{self, BatteryState},
+    color_scheme,
+    color_scheme::ColorScheme,
+    config::{
+        AmbientLightConfig, BacklightConfig, BatteryConfig, HoverConfig, IdleConfig, SolarConfig,
+    },
+    idle, pointer, process_watch, solar,
+};
+
+#[derive(Debug, Clone)]
+pub enum Reading {
+    ColorScheme(ColorScheme),
+    /// Backlight brightness between 0.0 and 1.0
+    Backlight(f64),
+    /// Ambient illuminance in lux
+    AmbientLight(f64),
+    /// Elevation of the sun in degrees
+    SolarElevation(f64),
+    Battery(BatteryState),
+    /// First of the watched processes that's running
+    WatchedProcess(Option<String>),
+    /// Position the pointer rests at, `None` while it moves
+    Pointer(Option<(i32, i32)>),
+    /// Whether the session went without input for the idle timeout
+    Idle(bool),
+}
+
+/// Spawns watchers the first time the config asks for them.
+/// Watchers keep running with the settings they were started with.
+pub struct Sensors {
+    tx: chan::Sender<Reading>,
+    color_scheme: bool,
+    backlight: bool,
+    ambient_light: bool,
+    solar: bool,
+    battery: bool,
+    processes: bool,
+    pointer: bool,
+    idle: bool,
+}
+
+impl Sensors {
+    pub fn new() -> (Self, chan::Receiver<Reading>) {
+        let (tx, rx) = chan::unbounded();
+        let sensors = Self {
+            tx,
+            color_scheme: false,
+            backlight: false,
+            ambient_light: false,
+            solar: false,
+            battery: false,
+            processes: false,
+            pointer: false,
+            idle: false,
+        };
+        (sensors, rx)
+    }
+
+    pub fn watch_color_scheme(&mut self) {
+        if !self.color_scheme {
+            color_scheme::spawn_watcher(self.tx.clone());
+            self.color_scheme = true;
+        }
+    }
+
+    pub fn watch_backlight(&mut self, config: &BacklightConfig) {
+        if !self.backlight {
+            backlight::spawn_watcher(config, self.tx.clone());
+            self.backlight = true;
+        }
+    }
+
+    pub fn watch_ambient_light(&mut self, config: &AmbientLightConfig) {
+        if !self.ambient_light {
+            ambient_light::spawn_watcher(config, self.tx.clone());
+            self.ambient_light = true;
+        }
+    }
+
+    pub fn watch_sun(&mut self, config: &SolarConfig) {
+        if !self.solar {
+            solar::spawn_watcher(config, self.tx.clone());
+            self.solar = true;
+        }
+    }
+
+    pub fn watch_battery(&mut self, config: &BatteryConfig) {
+        if !self.battery {
+            battery::spawn_watcher(config, self.tx.clone());
+            self.battery = true;
+        }
+    }
+
+    pub fn watch_processes(&mut self, names: &[String], interval: Duration) {
+        if !self.processes {
+            process_watch::spawn_watcher(names.to_vec(), interval, self.tx.clone());
+            self.processes = true;
+        }
+    }
+
+    pub fn watch_pointer(&mut self, config: &HoverConfig) {
+        if !self.pointer {
+            pointer::spawn_watcher(config, self.tx.clone());
+            self.pointer = true;
+        }
+    }
+
+    pub fn watch_idle(&mut self, config: &IdleConfig) {
+        if !self.idle {
+            idle::spawn_watcher(config, self.tx.clone());
+            self.idle = true;
+        }
+    }
+}
@@ -0,0 +1,81 @@
+//! Tracks the elevation of the sun to ease between day and night opacity like redshift does.
+
+use std::{
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crossbeam_channel as chan;
+
+use crate::{config::SolarConfig, sensors::Reading};
+
+const INTERVAL: Duration = Duration::from_secs(60);
+
+/// Changes smaller than this many degrees don't get reported
+const MIN_CHANGE: f64 = 0.1;
+
+/// Solar elevation in degrees, low precision algorithm from the Astronomical Almanac which is
+/// more than good enough to tell day from night.
+pub fn elevation(unix_secs: f64, latitude: f64, longitude: f64) -> f64 {
+    // days since J2000.0
+    let n = unix_secs / 86400.0 + 2_440_587.5 - 2_451_545.0;
+
+    let mean_longitude = (280.460 + 0.985_647_4 * n).rem_euclid(360.0);
+    let mean_anomaly = (357.528 + 0.985_600_3 * n).rem_euclid(360.0).to_radians();
+    let ecliptic_longitude =
+        (mean_longitude + 1.915 * mean_anomaly.sin() + 0.020 * (2.0 * mean_anomaly).sin())
+            .to_radians();
+    let obliquity = (23.439 - 0.000_000_4 * n).to_radians();
+
+    let right_ascension =
+        (obliquity.cos() * ecliptic_longitude.sin()).atan2(ecliptic_longitude.cos());
+    let declination = (obliquity.sin() * ecliptic_longitude.sin()).asin();
+
+    let sidereal_hours = (18.697_374_558 + 24.065_709_824_419_08 * n).rem_euclid(24.0);
+    let hour_angle = (sidereal_hours * 15.0 + longitude).to_radians() - right_ascension;
+
+    let latitude = latitude.to_radians();
+    (latitude.sin() * declination.sin() + latitude.cos() * declination.cos() * hour_angle.cos())
+        .asin()
+        .to_degrees()
+}
+
+fn now_secs() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.as_secs() as f64)
+        .unwrap_or(0.0)
+}
+
+pub fn spawn_watcher(config: &SolarConfig, tx: chan::Sender<Reading>) {
+    let (latitude, longitude) = (config.latitude, config.longitude);
+
+    // FIXME: unjoined thread
+    thread::spawn(move || {
+        let mut last: Option<f64> = None;
+        loop {
+            let elevation = elevation(now_secs(), latitude, longitude);
+            if last.map_or(true, |last| (last - elevation).abs() >= MIN_CHANGE) {
+                last = Some(elevation);
+                if tx.send(Reading::SolarElevation(elevation)).is_err() {
+                    return;
+                }
+            }
+            thread::sleep(INTERVAL);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn berlin_summer_solstice() {
+        // 2021-06-21 around solar noon and solar midnight
+        let noon = elevation(1_624_273_620.0, 52.5, 13.4);
+        let midnight = elevation(1_624_316_820.0, 52.5, 13.4);
+        assert!((noon - 60.9).abs() < 0.5, "{}", noon);
+        assert!((midnight + 14.1).abs() < 0.5, "{}", midnight);
+    }
+}
@@ -0,0 +1,165 @@
+use std::fmt;
+
+/// What the daemon does with unfocused windows.
+/// Disabling is a user decision that sticks, pausing happens automatically while some condition
+/// holds (fullscreen, battery, watched process) and ends by itself.
+#[derive(Debug, Clone, PartialEq)]
+pub enum State {
+    Enabled,
+    Disabled,
+    /// Enabled but keeping every window opaque for the given reason
+    Paused(String),
+}
+
+impl State {
+    pub fn new(enabled: bool) -> Self {
+        if enabled {
+            State::Enabled
+        } else {
+            State::Disabled
+        }
+    }
+
+    /// Whether the user wants transparency, true while paused
+    pub fn is_enabled(&self) -> bool {
+        *self != State::Disabled
+    }
+
+    /// Name of the state without the pause reason
+    pub fn name(&self) -> &'static str {
+        match self {
+            State::Enabled => "enabled",
+            State::Disabled => "disabled",
+            State::Paused(_) => "paused",
+        }
+    }
+
+    pub fn pause_reason(&self) -> Option<&str> {
+        match self {
+            State::Paused(reason) => Some(reason),
+            _ => None,
+        }
+    }
+
+    pub fn enable(&mut self) {
+        if *self == State::Disabled {
+            *self = State::Enabled;
+        }
+    }
+
+    pub fn disable(&mut self) {
+        *self = State::Disabled;
+    }
+
+    pub fn toggle(&mut self) {
+        if self.is_enabled() {
+            self.disable();
+        } else {
+            self.enable();
+        }
+    }
+
+    /// Pauses for `reason` or resumes when there's none, does nothing while disabled.
+    /// Returns whether the state changed.
+    pub fn set_paused(&mut self, reason: Option<String>) -> bool {
+        let next = match (&*self, reason) {
+            (State::Disabled, _) => return false,
+            (_, Some(reason)) => State::Paused(reason),
+            (_, None) => State::Enabled,
+        };
+        if *self == next {
+            return false;
+        }
+        *self = next;
+        true
+    }
+}
+
+impl fmt::Display for State {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            State::Enabled => f.write_str("enabled"),
+            State::Disabled => f.write_str("disabled"),
+            State::Paused(reason) => write!(f, "paused ({})", reason),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn paused(reason: &str) -> State {
+        State::Paused(reason.to_owned())
+    }
+
+    #[test]
+    fn pauses_and_resumes_while_enabled() {
+        let mut state = State::new(true);
+        assert!(state.set_paused(Some("fullscreen".to_owned())));
+        assert_eq!(state, paused("fullscreen"));
+        assert!(state.is_enabled());
+        assert_eq!(state.pause_reason(), Some("fullscreen"));
+        assert_eq!(state.to_string(), "paused (fullscreen)");
+
+        assert!(!state.set_paused(Some("fullscreen".to_owned())));
+        assert!(state.set_paused(Some("battery".to_owned())));
+        assert_eq!(state, paused("battery"));
+
+        assert!(state.set_paused(None));
+        assert_eq!(state, State::Enabled);
+        assert!(!state.set_paused(None));
+    }
+
+    #[test]
+    fn conditions_dont_pause_while_disabled() {
+        let mut state = State::new(false);
+        assert!(!state.set_paused(Some("fullscreen".to_owned())));
+        assert!(!state.set_paused(None));
+        assert_eq!(state, State::Disabled);
+        assert_eq!(state.pause_reason(), None);
+    }
+
+    #[test]
+    fn disable_overrides_pause() {
+        let mut state = paused("fullscreen");
+        state.disable();
+        assert_eq!(state, State::Disabled);
+        // the next condition check must not bring transparency back
+        assert!(!state.set_paused(None));
+        assert_eq!(state, State::Disabled);
+    }
+
+    // `Cmd::Pause` disables and enables again once the duration is over
+    #[test]
+    fn timed_pause_enables_again() {
+        let mut state = State::new(true);
+        state.disable();
+        assert!(!state.is_enabled());
+        state.enable();
+        assert_eq!(state, State::Enabled);
+
+        let mut state = paused("battery");
+        state.disable();
+        state.enable();
+        assert_eq!(state, State::Enabled);
+    }
+
+    #[test]
+    fn enable_keeps_pause() {
+        let mut state = paused("fullscreen");
+        state.enable();
+        assert_eq!(state, paused("fullscreen"));
+    }
+
+    #[test]
+    fn toggles_between_disabled_and_enabled() {
+        let mut state = paused("fullscreen");
+        state.toggle();
+        assert_eq!(state, State::Disabled);
+        assert_eq!(state.name(), "disabled");
+        state.toggle();
+        assert_eq!(state, State::Enabled);
+        assert_eq!(state.name(), "enabled");
+    }
+}
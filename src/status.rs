@@ -0,0 +1,58 @@
+use std::fmt;
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::opacity::Opacity;
+
+/// Snapshot of the daemon's state as reported to clients
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Status {
+    /// `enabled`, `disabled` or `paused`
+    pub state: String,
+    pub pause_reason: Option<String>,
+    pub opacity: Opacity,
+    /// Safe mode kicked in after repeated failures
+    pub degraded: bool,
+    pub blacklisted: Vec<i64>,
+    /// Windows skipped because setting their opacity keeps failing
+    pub skipped: Vec<i64>,
+    #[serde(default)]
+    pub failing: Vec<FailingWindow>,
+}
+
+/// Window whose last opacity command failed
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FailingWindow {
+    pub id: i64,
+    /// Failures in a row
+    pub failures: u32,
+    /// What the window manager replied the last time
+    pub error: String,
+}
+
+impl fmt::Display for FailingWindow {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "con_id {}: {} ({} failures)",
+            self.id, self.error, self.failures
+        )
+    }
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "state: {}", self.state)?;
+        if let Some(ref reason) = self.pause_reason {
+            writeln!(f, "pause reason: {}", reason)?;
+        }
+        writeln!(f, "opacity: {}", self.opacity)?;
+        writeln!(f, "degraded: {}", self.degraded)?;
+        writeln!(f, "blacklisted: {:?}", self.blacklisted)?;
+        writeln!(f, "skipped: {:?}", self.skipped)?;
+        for window in &self.failing {
+            writeln!(f, "failing: {}", window)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,169 @@
+use std::str::FromStr;
+
+use i3ipc::Subscription;
+use serde_derive::{Deserialize, Serialize};
+use snafu::ResultExt;
+
+use crate::{
+    wm::{self, WmEvent},
+    Error, I3Comm, I3Connect, TICK_PREFIX,
+};
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone)]
+pub enum Format {
+    Plain,
+    Polybar,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "plain" => Ok(Format::Plain),
+            "polybar" => Ok(Format::Polybar),
+            _ => Err(format!("unknown format {}, expected plain or polybar", s)),
+        }
+    }
+}
+
+/// Daemon state as learned from its tick broadcasts
+#[derive(Debug, Default)]
+struct State {
+    enabled: Option<bool>,
+    paused: Option<String>,
+    opacity: Option<String>,
+    degraded: bool,
+    skipped: usize,
+}
+
+impl State {
+    /// Returns true if the payload was a state broadcast
+    fn update(&mut self, payload: &str) -> bool {
+        let mut parts = payload.splitn(2, ':');
+        if parts.next() != Some(TICK_PREFIX) {
+            return false;
+        }
+        let mut kv = match parts.next() {
+            Some(kv) => kv.splitn(2, '='),
+            None => return false,
+        };
+        match (kv.next(), kv.next()) {
+            (Some("enabled"), Some(v)) => self.enabled = v.parse().ok(),
+            (Some("paused"), Some("")) => self.paused = None,
+            (Some("paused"), Some(v)) => self.paused = Some(v.to_owned()),
+            (Some("opacity"), Some(v)) => self.opacity = Some(v.trim().to_owned()),
+            (Some("degraded"), Some(v)) => self.degraded = v == "true",
+            (Some("skipped"), Some(v)) => self.skipped = v.parse().unwrap_or(0),
+            _ => return false,
+        }
+        true
+    }
+
+    fn render(&self, format: Format) -> Option<String> {
+        let enabled = self.enabled?;
+        let opacity = self.opacity.as_ref().map(String::as_str).unwrap_or("?");
+        Some(match format {
+            Format::Plain => format!(
+                "enabled={} opacity={} degraded={} paused={} skipped={}",
+                enabled,
+                opacity,
+                self.degraded,
+                self.paused.as_ref().map(String::as_str).unwrap_or("false"),
+                self.skipped
+            ),
+            Format::Polybar => {
+                let label = if self.degraded {
+                    "%{F#f00}transparentd failed%{F-}".to_owned()
+                } else if let (true, Some(reason)) = (enabled, &self.paused) {
+                    format!("%{{F#777}}paused: {}%{{F-}}", reason)
+                } else if enabled {
+                    format!("transparent {}", opacity)
+                } else {
+                    "%{F#777}opaque%{F-}".to_owned()
+                };
+                format!("%{{A1:transparentd toggle:}}{}%{{A}}", label)
+            }
+        })
+    }
+}
+
+/// Prints a line each time the daemon broadcasts a state change, meant for status bars like
+/// polybar's `tail = true` modules.
+pub fn run(format: Format) -> Result<(), Error> {
+    let events = wm::listen(&[Subscription::Tick])?;
+
+    // ask the daemon to broadcast its whole state so there's something to print right away
+    wm::connect()
+        .context(I3Connect)?
+        .send_tick(&format!("{} resend-state", TICK_PREFIX))
+        .context(I3Comm)?;
+
+    let mut state = State::default();
+    for event in events {
+        if let WmEvent::Tick(payload) = event.context(I3Comm)? {
+            if state.update(&payload) {
+                if let Some(line) = state.render(format) {
+                    println!("{}", line);
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(payloads: &[&str]) -> State {
+        let mut state = State::default();
+        for payload in payloads {
+            assert!(state.update(payload), "{}", payload);
+        }
+        state
+    }
+
+    #[test]
+    fn renders_polybar_labels() {
+        let mut state = state(&["transparentd:enabled=true", "transparentd:opacity=0.8"]);
+        assert_eq!(
+            state.render(Format::Polybar).unwrap(),
+            "%{A1:transparentd toggle:}transparent 0.8%{A}"
+        );
+
+        state.update("transparentd:paused=fullscreen window exists");
+        assert_eq!(
+            state.render(Format::Polybar).unwrap(),
+            "%{A1:transparentd toggle:}%{F#777}paused: fullscreen window exists%{F-}%{A}"
+        );
+
+        state.update("transparentd:enabled=false");
+        assert_eq!(
+            state.render(Format::Polybar).unwrap(),
+            "%{A1:transparentd toggle:}%{F#777}opaque%{F-}%{A}"
+        );
+
+        state.update("transparentd:degraded=true");
+        assert_eq!(
+            state.render(Format::Polybar).unwrap(),
+            "%{A1:transparentd toggle:}%{F#f00}transparentd failed%{F-}%{A}"
+        );
+    }
+
+    #[test]
+    fn waits_for_enabled_state() {
+        let state = state(&["transparentd:opacity=0.8", "transparentd:skipped=2"]);
+        assert_eq!(state.render(Format::Plain), None);
+    }
+
+    #[test]
+    fn ignores_foreign_ticks() {
+        let mut state = State::default();
+        assert!(!state.update("transparentd toggle"));
+        assert!(!state.update("polybar:enabled=true"));
+        assert!(!state.update("transparentd:unknown=1"));
+        assert!(state.enabled.is_none());
+    }
+}
@@ -0,0 +1,86 @@
+//! Service manager integration, readiness notifications and socket activation.
+//! Everything here is a no-op when transparentd isn't started by systemd.
+
+use std::{
+    env,
+    os::unix::{
+        io::{FromRawFd, RawFd},
+        net::{UnixDatagram, UnixListener},
+    },
+    process,
+};
+
+/// First fd passed by socket activation, see sd_listen_fds(3)
+const LISTEN_FDS_START: RawFd = 3;
+
+/// Sends a state like `READY=1` to `$NOTIFY_SOCKET`, see sd_notify(3)
+pub fn notify(state: &str) {
+    let path = match env::var_os("NOTIFY_SOCKET") {
+        Some(path) => path,
+        None => return,
+    };
+    if path.to_string_lossy().starts_with('@') {
+        log::warn!("Abstract notify sockets aren't supported, can't notify systemd");
+        return;
+    }
+
+    let res = UnixDatagram::unbound().and_then(|socket| socket.send_to(state.as_bytes(), &path));
+    if let Err(e) = res {
+        log::warn!("Can't notify systemd: {}", e);
+    }
+}
+
+/// The ipc socket if systemd passed one, the environment variables get removed so hooks don't
+/// inherit them.
+pub fn activated_listener() -> Option<UnixListener> {
+    let pid = env::var("LISTEN_PID").ok()?;
+    let fds = env::var("LISTEN_FDS").ok()?;
+    for var in &["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+        env::remove_var(var);
+    }
+
+    if pid.parse::<u32>().ok()? != process::id() {
+        return None;
+    }
+    match fds.parse::<RawFd>() {
+        Ok(1) => {}
+        Ok(n) if n > 1 => log::warn!("Got {} sockets from systemd, only using the first", n),
+        _ => return None,
+    }
+
+    // systemd doesn't set CLOEXEC on passed fds
+    // the fd is open and owned by nothing else in this process
+    unsafe {
+        libc::fcntl(LISTEN_FDS_START, libc::F_SETFD, libc::FD_CLOEXEC);
+        Some(UnixListener::from_raw_fd(LISTEN_FDS_START))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn notifies_ready() {
+        let path = env::temp_dir().join(format!("transparentd-notify-{}", process::id()));
+        let _ = std::fs::remove_file(&path);
+        let socket = UnixDatagram::bind(&path).unwrap();
+        env::set_var("NOTIFY_SOCKET", &path);
+        notify("READY=1");
+        env::remove_var("NOTIFY_SOCKET");
+
+        let mut buf = [0; 64];
+        let n = socket.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"READY=1");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn ignores_sockets_for_other_processes() {
+        env::set_var("LISTEN_PID", (process::id() + 1).to_string());
+        env::set_var("LISTEN_FDS", "1");
+        assert!(activated_listener().is_none());
+        assert!(env::var_os("LISTEN_PID").is_none());
+        assert!(env::var_os("LISTEN_FDS").is_none());
+    }
+}
@@ -0,0 +1,912 @@
+//! Connections to the window manager, each supported one implements `WmBackend`.
+//! i3 is talked to through i3ipc, sway through swayipc when the sway feature is enabled and
+//! Hyprland through its own sockets. Their replies get converted into i3ipc's types so the rest of
+//! transparentd doesn't need to care.
+
+use std::{
+    collections::{HashMap, HashSet},
+    env,
+    io::{self, Read, Write},
+    os::unix::net::UnixStream,
+    path::PathBuf,
+    process::Command,
+};
+
+use i3ipc::{
+    event::{
+        inner::{ShutdownChange, WindowChange, WorkspaceChange},
+        Event,
+    },
+    reply::{CommandReply, Node, NodeBorder, NodeLayout, NodeType, WindowProperty, Workspaces},
+    EstablishError, I3Connection, I3EventListener, MessageError, Subscription,
+};
+use serde_derive::Deserialize;
+use snafu::ResultExt;
+
+use crate::{
+    apply::opacity_cmd,
+    hyprland,
+    i3::{self, AllWindows, WindowInfo},
+    opacity::Opacity,
+    Error, I3Comm, I3Connect,
+};
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Wm {
+    I3,
+    Sway,
+    Hyprland,
+}
+
+impl Wm {
+    /// Hyprland sets `HYPRLAND_INSTANCE_SIGNATURE`, sway `SWAYSOCK`, i3 only `I3SOCK`
+    pub fn detect() -> Self {
+        if hyprland::detect() {
+            return Wm::Hyprland;
+        }
+        if env::var_os("SWAYSOCK").is_some() {
+            if cfg!(feature = "sway") {
+                return Wm::Sway;
+            }
+            log::debug!("Running on sway without the sway feature, using its i3 compatible ipc");
+        }
+        Wm::I3
+    }
+}
+
+/// The layout tree along with the container state i3ipc's `Node` has no fields for
+#[derive(Debug, Clone)]
+pub struct Tree {
+    pub root: Node,
+    /// Marks of each container carrying any, always empty for window managers without marks
+    pub marks: HashMap<i64, Vec<String>>,
+    /// con_ids of the fullscreen containers. Hyprland doesn't say which window is fullscreen,
+    /// there they're the workspaces showing one.
+    pub fullscreen: HashSet<i64>,
+}
+
+impl Tree {
+    /// Tree without marks or fullscreen containers
+    pub fn new(root: Node) -> Self {
+        Self {
+            root,
+            marks: HashMap::new(),
+            fullscreen: HashSet::new(),
+        }
+    }
+
+    /// con_ids of the containers carrying the given mark
+    pub fn marked(&self, mark: &str) -> HashSet<i64> {
+        self.marks
+            .iter()
+            .filter(|(_, marks)| marks.iter().any(|m| m == mark))
+            .map(|(&id, _)| id)
+            .collect()
+    }
+
+    /// Whether any container is fullscreen, on its output or globally
+    pub fn has_fullscreen(&self) -> bool {
+        !self.fullscreen.is_empty()
+    }
+
+    /// Forgets a closed container
+    pub fn remove_window(&mut self, id: i64) {
+        i3::remove_window(&mut self.root, id);
+        self.marks.remove(&id);
+        self.fullscreen.remove(&id);
+    }
+}
+
+/// What transparentd needs from a window manager, each supported one implements it.
+/// Replies use i3ipc's types no matter which window manager sent them.
+pub trait WmBackend {
+    /// The whole tree, fetched with a single request where the window manager allows it
+    fn get_tree(&mut self) -> Result<Tree, MessageError>;
+
+    fn get_workspaces(&mut self) -> Result<Workspaces, MessageError>;
+
+    /// Outcome for each window
+    fn set_opacity(&mut self, windows: &[(i64, Opacity)]) -> Result<CommandReply, MessageError>;
+
+    fn send_tick(&mut self, payload: &str) -> Result<(), MessageError>;
+
+    /// Subscribes a new connection to the given events
+    fn listen(&self, subscriptions: &[Subscription]) -> Result<Events, Error>;
+
+    fn iter_windows(&mut self) -> Result<AllWindows, MessageError> {
+        Ok(AllWindows::new(self.get_tree()?.root))
+    }
+
+    fn get_focused_window(&mut self) -> Result<Option<i64>, MessageError> {
+        Ok(self
+            .iter_windows()?
+            .find(|node| node.focused)
+            .map(|node| node.id))
+    }
+
+    fn get_window_infos(&mut self) -> Result<Vec<WindowInfo>, MessageError> {
+        Ok(self
+            .iter_windows()?
+            .map(|node| WindowInfo::from_node(&node))
+            .collect())
+    }
+}
+
+pub type WmConnection = Box<dyn WmBackend>;
+
+/// Connects to the window manager transparentd runs under
+pub fn connect() -> Result<WmConnection, EstablishError> {
+    let conn: WmConnection = match Wm::detect() {
+        #[cfg(feature = "sway")]
+        Wm::Sway => Box::new(
+            swayipc::Connection::new()
+                .map_err(|e| EstablishError::SocketError(sway::io_error(e)))?,
+        ),
+        Wm::Hyprland => Box::new(hyprland::Connection::connect()?),
+        _ => Box::new(I3Backend::connect()?),
+    };
+    Ok(conn)
+}
+
+/// i3ipc's connection along with a socket of its own for the trees, i3ipc drops their marks and
+/// fullscreen modes
+struct I3Backend {
+    conn: I3Connection,
+    socket_path: PathBuf,
+    /// Opened on the first raw request
+    raw: Option<UnixStream>,
+}
+
+impl I3Backend {
+    fn connect() -> Result<Self, EstablishError> {
+        let socket_path = i3_socket_path().map_err(EstablishError::SocketError)?;
+        Ok(Self {
+            conn: I3Connection::connect()?,
+            socket_path,
+            raw: None,
+        })
+    }
+
+    /// GET_TREE returning the unparsed JSON reply
+    fn raw_tree(&mut self) -> Result<Vec<u8>, MessageError> {
+        if self.raw.is_none() {
+            let stream = UnixStream::connect(&self.socket_path).map_err(MessageError::Send)?;
+            self.raw = Some(stream);
+        }
+        let reply = i3_raw_tree(self.raw.as_mut().unwrap());
+        if reply.is_err() {
+            // the stream is out of sync or i3 restarted, the next request connects again
+            self.raw = None;
+        }
+        reply
+    }
+}
+
+impl WmBackend for I3Backend {
+    fn get_tree(&mut self) -> Result<Tree, MessageError> {
+        Ok(RawNode::parse(&self.raw_tree()?)?.into_tree())
+    }
+
+    fn get_workspaces(&mut self) -> Result<Workspaces, MessageError> {
+        self.conn.get_workspaces()
+    }
+
+    fn set_opacity(&mut self, windows: &[(i64, Opacity)]) -> Result<CommandReply, MessageError> {
+        self.conn.run_command(&opacity_cmd(windows))
+    }
+
+    fn send_tick(&mut self, payload: &str) -> Result<(), MessageError> {
+        self.conn.send_tick(payload).map(|_| ())
+    }
+
+    fn listen(&self, subscriptions: &[Subscription]) -> Result<Events, Error> {
+        let mut listener = I3EventListener::connect().context(I3Connect)?;
+        listener.subscribe(subscriptions).context(I3Comm)?;
+        // the iterator returned by `listen` only borrows the socket, a fresh one per event
+        // reads the same stream
+        Ok(Box::new(std::iter::repeat(()).map(move |()| {
+            listener
+                .listen()
+                .next()
+                .unwrap_or_else(|| Err(MessageError::Receive(io::ErrorKind::UnexpectedEof.into())))
+                .map(i3_event)
+        })))
+    }
+}
+
+/// Tree node as i3 sends it, unlike i3ipc's `Node` with marks and fullscreen mode
+#[derive(Deserialize, Debug)]
+struct RawNode {
+    id: i64,
+    #[serde(rename = "type", default)]
+    nodetype: String,
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    border: String,
+    #[serde(default)]
+    current_border_width: i32,
+    #[serde(default)]
+    layout: String,
+    #[serde(default)]
+    percent: Option<f64>,
+    #[serde(default)]
+    rect: RawRect,
+    #[serde(default)]
+    window_rect: RawRect,
+    #[serde(default)]
+    deco_rect: RawRect,
+    #[serde(default)]
+    geometry: RawRect,
+    #[serde(default)]
+    window: Option<i32>,
+    /// Strings except for `transient_for`, which is a window id or null
+    #[serde(default)]
+    window_properties: Option<HashMap<String, serde_json::Value>>,
+    #[serde(default)]
+    urgent: bool,
+    #[serde(default)]
+    focused: bool,
+    #[serde(default)]
+    focus: Vec<i64>,
+    #[serde(default)]
+    marks: Vec<String>,
+    /// 0 when not fullscreen, 1 fullscreen on its output and 2 globally. i3 sets 1 on every
+    /// workspace too.
+    #[serde(default)]
+    fullscreen_mode: u8,
+    #[serde(default)]
+    nodes: Vec<RawNode>,
+    #[serde(default)]
+    floating_nodes: Vec<RawNode>,
+}
+
+#[derive(Deserialize, Debug, Default, Clone, Copy)]
+struct RawRect {
+    x: i32,
+    y: i32,
+    width: i32,
+    height: i32,
+}
+
+impl From<RawRect> for (i32, i32, i32, i32) {
+    fn from(rect: RawRect) -> Self {
+        (rect.x, rect.y, rect.width, rect.height)
+    }
+}
+
+impl RawNode {
+    fn parse(json: &[u8]) -> Result<Self, MessageError> {
+        serde_json::from_slice(json)
+            .map_err(|e| MessageError::Receive(io::Error::new(io::ErrorKind::InvalidData, e)))
+    }
+
+    fn into_tree(self) -> Tree {
+        let mut marks = HashMap::new();
+        let mut fullscreen = HashSet::new();
+        let root = self.into_node(&mut marks, &mut fullscreen);
+        Tree {
+            root,
+            marks,
+            fullscreen,
+        }
+    }
+
+    /// Converts the node and everything in it, collecting what `Node` can't hold on the way
+    fn into_node(
+        self,
+        marks: &mut HashMap<i64, Vec<String>>,
+        fullscreen: &mut HashSet<i64>,
+    ) -> Node {
+        let nodetype = match self.nodetype.as_str() {
+            "root" => NodeType::Root,
+            "output" => NodeType::Output,
+            "con" => NodeType::Con,
+            "floating_con" => NodeType::FloatingCon,
+            "workspace" => NodeType::Workspace,
+            "dockarea" => NodeType::DockArea,
+            _ => NodeType::Unknown,
+        };
+        let container = matches!(nodetype, NodeType::Con | NodeType::FloatingCon);
+        if container && self.fullscreen_mode != 0 {
+            fullscreen.insert(self.id);
+        }
+        if !self.marks.is_empty() {
+            marks.insert(self.id, self.marks);
+        }
+
+        let window_properties = self.window_properties.map(|props| {
+            props
+                .into_iter()
+                .filter_map(|(key, value)| {
+                    let prop = match key.as_str() {
+                        "title" => WindowProperty::Title,
+                        "instance" => WindowProperty::Instance,
+                        "class" => WindowProperty::Class,
+                        "window_role" => WindowProperty::WindowRole,
+                        "transient_for" => WindowProperty::TransientFor,
+                        _ => return None,
+                    };
+                    match value {
+                        serde_json::Value::String(value) => Some((prop, value)),
+                        serde_json::Value::Number(value) => Some((prop, value.to_string())),
+                        _ => None,
+                    }
+                })
+                .collect()
+        });
+
+        Node {
+            focus: self.focus,
+            nodes: self
+                .nodes
+                .into_iter()
+                .map(|node| node.into_node(marks, fullscreen))
+                .collect(),
+            floating_nodes: self
+                .floating_nodes
+                .into_iter()
+                .map(|node| node.into_node(marks, fullscreen))
+                .collect(),
+            id: self.id,
+            name: self.name,
+            nodetype,
+            border: match self.border.as_str() {
+                "normal" => NodeBorder::Normal,
+                "pixel" => NodeBorder::Pixel,
+                _ => NodeBorder::None,
+            },
+            current_border_width: self.current_border_width,
+            layout: match self.layout.as_str() {
+                "splith" => NodeLayout::SplitH,
+                "splitv" => NodeLayout::SplitV,
+                "stacked" => NodeLayout::Stacked,
+                "tabbed" => NodeLayout::Tabbed,
+                "dockarea" => NodeLayout::DockArea,
+                "output" => NodeLayout::Output,
+                _ => NodeLayout::Unknown,
+            },
+            percent: self.percent,
+            rect: self.rect.into(),
+            window_rect: self.window_rect.into(),
+            deco_rect: self.deco_rect.into(),
+            geometry: self.geometry.into(),
+            window: self.window,
+            window_properties,
+            urgent: self.urgent,
+            focused: self.focused,
+        }
+    }
+}
+
+fn i3_socket_path() -> io::Result<PathBuf> {
+    if let Some(path) = env::var_os("I3SOCK") {
+        return Ok(path.into());
+    }
+    let output = Command::new("i3").arg("--get-socketpath").output()?;
+    let path = String::from_utf8(output.stdout)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    Ok(path.trim_end().into())
+}
+
+fn i3_raw_tree(stream: &mut UnixStream) -> Result<Vec<u8>, MessageError> {
+    const MAGIC: &[u8] = b"i3-ipc";
+    const GET_TREE: u32 = 4;
+
+    let mut request = MAGIC.to_vec();
+    request.extend_from_slice(&0u32.to_ne_bytes());
+    request.extend_from_slice(&GET_TREE.to_ne_bytes());
+    stream.write_all(&request).map_err(MessageError::Send)?;
+
+    let mut header = [0; 14];
+    stream
+        .read_exact(&mut header)
+        .map_err(MessageError::Receive)?;
+    if &header[..MAGIC.len()] != MAGIC {
+        return Err(MessageError::Receive(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "reply without i3-ipc magic",
+        )));
+    }
+    let mut len = [0; 4];
+    len.copy_from_slice(&header[6..10]);
+    let mut payload = vec![0; u32::from_ne_bytes(len) as usize];
+    stream
+        .read_exact(&mut payload)
+        .map_err(MessageError::Receive)?;
+    Ok(payload)
+}
+
+/// The parts of window manager events transparentd cares about
+#[derive(Debug)]
+pub enum WmEvent {
+    Window {
+        change: WindowChange,
+        id: i64,
+        /// The changed container as of the event, Hyprland's events don't carry it
+        container: Option<Box<Node>>,
+    },
+    Workspace {
+        change: WorkspaceChange,
+        /// The affected workspace with everything in it, if the event carries it
+        current: Option<Box<Node>>,
+    },
+    /// Outputs were connected, disconnected or reconfigured
+    Output,
+    Tick(String),
+    /// The window manager exits, or restarts in place and can be connected to again
+    Shutdown {
+        restart: bool,
+    },
+    Other,
+}
+
+pub type Events = Box<dyn Iterator<Item = Result<WmEvent, MessageError>> + Send>;
+
+/// Subscribes a new connection to the given events
+pub fn listen(subscriptions: &[Subscription]) -> Result<Events, Error> {
+    connect().context(I3Connect)?.listen(subscriptions)
+}
+
+fn i3_event(event: Event) -> WmEvent {
+    match event {
+        Event::WindowEvent(info) => WmEvent::Window {
+            change: info.change,
+            id: info.container.id,
+            container: Some(Box::new(info.container)),
+        },
+        Event::WorkspaceEvent(info) => WmEvent::Workspace {
+            change: info.change,
+            current: info.current.map(Box::new),
+        },
+        Event::OutputEvent(_) => WmEvent::Output,
+        Event::TickEvent(info) => WmEvent::Tick(info.payload),
+        Event::ShutdownEvent(info) => WmEvent::Shutdown {
+            restart: matches!(info.change, ShutdownChange::Restart),
+        },
+        _ => WmEvent::Other,
+    }
+}
+
+#[cfg(feature = "sway")]
+mod sway {
+    use std::{
+        collections::{HashMap, HashSet},
+        io,
+    };
+
+    use i3ipc::{
+        event::inner::{WindowChange, WorkspaceChange},
+        reply::{
+            CommandOutcome, CommandReply, Node, NodeBorder, NodeLayout, NodeType, WindowProperty,
+            Workspace, Workspaces,
+        },
+        MessageError, Subscription,
+    };
+    use snafu::ResultExt;
+
+    use super::{Events, Tree, WmBackend, WmEvent};
+    use crate::{apply::opacity_cmd, opacity::Opacity, Error, I3Comm, I3Connect};
+
+    pub fn io_error(e: swayipc::Error) -> io::Error {
+        match e {
+            swayipc::Error::Io(e) => e,
+            e => io::Error::new(io::ErrorKind::Other, e.to_string()),
+        }
+    }
+
+    fn message_error(e: swayipc::Error) -> MessageError {
+        MessageError::Receive(io_error(e))
+    }
+
+    fn rect(rect: swayipc::Rect) -> (i32, i32, i32, i32) {
+        (rect.x, rect.y, rect.width, rect.height)
+    }
+
+    fn node(node: swayipc::Node) -> Node {
+        let mut properties = HashMap::new();
+        if let Some(props) = node.window_properties {
+            let props = vec![
+                (WindowProperty::Title, props.title),
+                (WindowProperty::Instance, props.instance),
+                (WindowProperty::Class, props.class),
+                (WindowProperty::WindowRole, props.window_role),
+            ];
+            for (prop, value) in props {
+                if let Some(value) = value {
+                    properties.insert(prop, value);
+                }
+            }
+        }
+        // native wayland clients only have an app_id, it fills the same role as the class
+        if let Some(app_id) = node.app_id {
+            properties.entry(WindowProperty::Class).or_insert(app_id);
+        }
+
+        Node {
+            focus: node.focus,
+            nodes: node.nodes.into_iter().map(self::node).collect(),
+            floating_nodes: node.floating_nodes.into_iter().map(self::node).collect(),
+            id: node.id,
+            name: node.name,
+            nodetype: match node.node_type {
+                swayipc::NodeType::Root => NodeType::Root,
+                swayipc::NodeType::Output => NodeType::Output,
+                swayipc::NodeType::Workspace => NodeType::Workspace,
+                swayipc::NodeType::Con => NodeType::Con,
+                swayipc::NodeType::FloatingCon => NodeType::FloatingCon,
+                swayipc::NodeType::Dockarea => NodeType::DockArea,
+                _ => NodeType::Unknown,
+            },
+            border: match node.border {
+                swayipc::NodeBorder::Normal => NodeBorder::Normal,
+                swayipc::NodeBorder::Pixel => NodeBorder::Pixel,
+                _ => NodeBorder::None,
+            },
+            current_border_width: node.current_border_width,
+            layout: match node.layout {
+                swayipc::NodeLayout::SplitH => NodeLayout::SplitH,
+                swayipc::NodeLayout::SplitV => NodeLayout::SplitV,
+                swayipc::NodeLayout::Stacked => NodeLayout::Stacked,
+                swayipc::NodeLayout::Tabbed => NodeLayout::Tabbed,
+                swayipc::NodeLayout::Output => NodeLayout::Output,
+                swayipc::NodeLayout::Dockarea => NodeLayout::DockArea,
+                _ => NodeLayout::Unknown,
+            },
+            percent: node.percent,
+            rect: rect(node.rect),
+            window_rect: rect(node.window_rect),
+            deco_rect: rect(node.deco_rect),
+            geometry: rect(node.geometry),
+            window: node.window.map(|window| window as i32),
+            window_properties: if properties.is_empty() {
+                None
+            } else {
+                Some(properties)
+            },
+            urgent: node.urgent,
+            focused: node.focused,
+        }
+    }
+
+    fn tree(root: swayipc::Node) -> Tree {
+        fn collect(
+            node: &swayipc::Node,
+            marks: &mut HashMap<i64, Vec<String>>,
+            fullscreen: &mut HashSet<i64>,
+        ) {
+            if !node.marks.is_empty() {
+                marks.insert(node.id, node.marks.clone());
+            }
+            let container = matches!(
+                node.node_type,
+                swayipc::NodeType::Con | swayipc::NodeType::FloatingCon
+            );
+            if container && node.fullscreen_mode.map_or(false, |mode| mode != 0) {
+                fullscreen.insert(node.id);
+            }
+            for child in node.nodes.iter().chain(&node.floating_nodes) {
+                collect(child, marks, fullscreen);
+            }
+        }
+        let mut marks = HashMap::new();
+        let mut fullscreen = HashSet::new();
+        collect(&root, &mut marks, &mut fullscreen);
+        Tree {
+            root: node(root),
+            marks,
+            fullscreen,
+        }
+    }
+
+    fn workspaces(workspaces: Vec<swayipc::Workspace>) -> Workspaces {
+        Workspaces {
+            workspaces: workspaces
+                .into_iter()
+                .map(|workspace| Workspace {
+                    num: workspace.num,
+                    name: workspace.name,
+                    visible: workspace.visible,
+                    focused: workspace.focused,
+                    urgent: workspace.urgent,
+                    rect: rect(workspace.rect),
+                    output: workspace.output,
+                })
+                .collect(),
+        }
+    }
+
+    fn command_reply(outcomes: Vec<Result<(), swayipc::Error>>) -> CommandReply {
+        CommandReply {
+            outcomes: outcomes
+                .into_iter()
+                .map(|outcome| match outcome {
+                    Ok(()) => CommandOutcome {
+                        success: true,
+                        error: None,
+                    },
+                    Err(e) => CommandOutcome {
+                        success: false,
+                        error: Some(e.to_string()),
+                    },
+                })
+                .collect(),
+        }
+    }
+
+    fn window_change(change: swayipc::WindowChange) -> WindowChange {
+        match change {
+            swayipc::WindowChange::New => WindowChange::New,
+            swayipc::WindowChange::Close => WindowChange::Close,
+            swayipc::WindowChange::Focus => WindowChange::Focus,
+            swayipc::WindowChange::Title => WindowChange::Title,
+            swayipc::WindowChange::FullscreenMode => WindowChange::FullscreenMode,
+            swayipc::WindowChange::Move => WindowChange::Move,
+            swayipc::WindowChange::Floating => WindowChange::Floating,
+            swayipc::WindowChange::Urgent => WindowChange::Urgent,
+            swayipc::WindowChange::Mark => WindowChange::Mark,
+            _ => WindowChange::Unknown,
+        }
+    }
+
+    fn workspace_change(change: swayipc::WorkspaceChange) -> WorkspaceChange {
+        match change {
+            swayipc::WorkspaceChange::Init => WorkspaceChange::Init,
+            swayipc::WorkspaceChange::Empty => WorkspaceChange::Empty,
+            swayipc::WorkspaceChange::Focus => WorkspaceChange::Focus,
+            swayipc::WorkspaceChange::Move => WorkspaceChange::Move,
+            swayipc::WorkspaceChange::Rename => WorkspaceChange::Rename,
+            swayipc::WorkspaceChange::Urgent => WorkspaceChange::Urgent,
+            swayipc::WorkspaceChange::Reload => WorkspaceChange::Reload,
+            _ => WorkspaceChange::Unknown,
+        }
+    }
+
+    fn event(event: swayipc::Event) -> WmEvent {
+        match event {
+            swayipc::Event::Window(event) => WmEvent::Window {
+                change: window_change(event.change),
+                id: event.container.id,
+                container: Some(Box::new(node(event.container))),
+            },
+            swayipc::Event::Workspace(event) => WmEvent::Workspace {
+                change: workspace_change(event.change),
+                current: event.current.map(|current| Box::new(node(current))),
+            },
+            swayipc::Event::Output(_) => WmEvent::Output,
+            swayipc::Event::Tick(event) => WmEvent::Tick(event.payload),
+            // sway can't restart in place
+            swayipc::Event::Shutdown(_) => WmEvent::Shutdown { restart: false },
+            _ => WmEvent::Other,
+        }
+    }
+
+    fn listen(subscriptions: &[Subscription]) -> Result<Events, Error> {
+        let events: Vec<_> = subscriptions
+            .iter()
+            .filter_map(|subscription| match subscription {
+                Subscription::Window => Some(swayipc::EventType::Window),
+                Subscription::Tick => Some(swayipc::EventType::Tick),
+                Subscription::Shutdown => Some(swayipc::EventType::Shutdown),
+                Subscription::Workspace => Some(swayipc::EventType::Workspace),
+                Subscription::Output => Some(swayipc::EventType::Output),
+                _ => None,
+            })
+            .collect();
+        let stream = swayipc::Connection::new()
+            .map_err(|e| i3ipc::EstablishError::SocketError(io_error(e)))
+            .context(I3Connect)?
+            .subscribe(&events)
+            .map_err(message_error)
+            .context(I3Comm)?;
+        Ok(Box::new(stream.map(|event| {
+            event.map(self::event).map_err(message_error)
+        })))
+    }
+
+    impl WmBackend for swayipc::Connection {
+        fn get_tree(&mut self) -> Result<Tree, MessageError> {
+            swayipc::Connection::get_tree(self)
+                .map(tree)
+                .map_err(message_error)
+        }
+
+        fn get_workspaces(&mut self) -> Result<Workspaces, MessageError> {
+            swayipc::Connection::get_workspaces(self)
+                .map(workspaces)
+                .map_err(message_error)
+        }
+
+        fn set_opacity(
+            &mut self,
+            windows: &[(i64, Opacity)],
+        ) -> Result<CommandReply, MessageError> {
+            self.run_command(&opacity_cmd(windows))
+                .map(command_reply)
+                .map_err(message_error)
+        }
+
+        fn send_tick(&mut self, payload: &str) -> Result<(), MessageError> {
+            swayipc::Connection::send_tick(self, payload)
+                .map(|_| ())
+                .map_err(message_error)
+        }
+
+        fn listen(&self, subscriptions: &[Subscription]) -> Result<Events, Error> {
+            self::listen(subscriptions)
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use serde_json::{json, Value};
+
+        use crate::i3::{AllWindows, WindowInfo};
+
+        /// Container in a sway get_tree reply with the fields every node has, overridden by
+        /// the ones in `fields`
+        fn container(id: i64, node_type: &str, fields: Value, nodes: Vec<Value>) -> Value {
+            let rect = json!({"x": 0, "y": 0, "width": 1920, "height": 1080});
+            let mut node = json!({
+                "id": id, "type": node_type, "name": null, "border": "pixel",
+                "current_border_width": 2, "layout": "splith", "percent": null,
+                "urgent": false, "focused": false, "focus": [], "sticky": false, "marks": [],
+                "rect": rect, "window_rect": rect, "deco_rect": rect, "geometry": rect,
+                "nodes": nodes, "floating_nodes": []
+            });
+            for (key, value) in fields.as_object().unwrap() {
+                node[key] = value.clone();
+            }
+            node
+        }
+
+        fn sway_tree() -> swayipc::Node {
+            let foot = container(
+                5,
+                "con",
+                json!({"name": "~", "app_id": "foot", "pid": 100, "focused": true,
+                       "marks": ["keep"], "fullscreen_mode": 0}),
+                vec![],
+            );
+            let firefox = container(
+                6,
+                "con",
+                json!({"name": "Mozilla Firefox", "app_id": null, "window": 4194307,
+                       "fullscreen_mode": 1,
+                       "window_properties": {"class": "firefox", "instance": "Navigator",
+                                             "title": "Mozilla Firefox", "transient_for": null}}),
+                vec![],
+            );
+            let workspace = container(
+                3,
+                "workspace",
+                json!({"name": "1", "num": 1, "fullscreen_mode": 1}),
+                vec![foot, firefox],
+            );
+            let output = container(2, "output", json!({"name": "eDP-1"}), vec![workspace]);
+            let root = container(1, "root", json!({"name": "root"}), vec![output]);
+            serde_json::from_value(root).unwrap()
+        }
+
+        #[test]
+        fn converts_sway_tree() {
+            let tree = tree(sway_tree());
+            assert!(matches!(tree.root.nodetype, NodeType::Root));
+            let workspace = &tree.root.nodes[0].nodes[0];
+            assert!(matches!(workspace.nodetype, NodeType::Workspace));
+            assert_eq!(workspace.name.as_deref(), Some("1"));
+
+            let ids: Vec<_> = AllWindows::new(tree.root.clone())
+                .map(|window| window.id)
+                .collect();
+            assert_eq!(ids, vec![5, 6]);
+            assert_eq!(tree.marked("keep"), vec![5].into_iter().collect());
+            assert_eq!(tree.fullscreen, vec![6].into_iter().collect());
+        }
+
+        #[test]
+        fn app_id_stands_in_for_class() {
+            let tree = tree(sway_tree());
+            let windows: Vec<_> = AllWindows::new(tree.root)
+                .map(|window| WindowInfo::from_node(&window))
+                .collect();
+
+            let foot = &windows[0];
+            assert!(foot.focused);
+            assert_eq!(foot.class.as_deref(), Some("foot"));
+            assert_eq!(foot.title.as_deref(), Some("~"));
+            assert_eq!(foot.instance, None);
+
+            let firefox = &windows[1];
+            assert_eq!(firefox.class.as_deref(), Some("firefox"));
+            assert_eq!(firefox.instance.as_deref(), Some("Navigator"));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RECT: &str = r#""rect": {"x": 0, "y": 0, "width": 1920, "height": 1080}"#;
+
+    /// i3's tree with an output and a workspace holding the given windows, each covering the
+    /// whole output. Like in i3 the workspace has `fullscreen_mode` 1.
+    fn tree(fullscreen_modes: &[u8]) -> Tree {
+        let windows: Vec<_> = fullscreen_modes
+            .iter()
+            .enumerate()
+            .map(|(i, mode)| {
+                format!(
+                    r#"{{"id": {}, "type": "con", {}, "fullscreen_mode": {}}}"#,
+                    i + 4,
+                    RECT,
+                    mode
+                )
+            })
+            .collect();
+        let json = format!(
+            r#"{{"id": 1, "type": "root", "fullscreen_mode": 0, "nodes": [
+                {{"id": 2, "type": "output", "name": "eDP-1", {rect}, "fullscreen_mode": 0,
+                  "nodes": [{{"id": 3, "type": "workspace", "name": "1", {rect},
+                              "fullscreen_mode": 1, "nodes": [{windows}]}}]}}]}}"#,
+            rect = RECT,
+            windows = windows.join(", ")
+        );
+        RawNode::parse(json.as_bytes()).unwrap().into_tree()
+    }
+
+    #[test]
+    fn finds_fullscreen_windows() {
+        assert_eq!(tree(&[0, 1]).fullscreen, vec![5].into_iter().collect());
+        assert!(tree(&[2]).has_fullscreen());
+    }
+
+    #[test]
+    fn workspaces_and_lone_tiled_windows_are_not_fullscreen() {
+        assert!(!tree(&[]).has_fullscreen());
+        assert!(!tree(&[0]).has_fullscreen());
+    }
+
+    #[test]
+    fn converts_i3_tree() {
+        let json = r#"{"id": 1, "type": "root", "nodes": [
+            {"id": 2, "type": "workspace", "name": "1", "layout": "tabbed", "focus": [3],
+             "nodes": [{"id": 3, "type": "con", "name": "vim", "window": 12,
+                        "focused": true, "marks": ["keep", "other"],
+                        "rect": {"x": 1, "y": 2, "width": 3, "height": 4},
+                        "window_properties": {"class": "URxvt", "title": "vim",
+                                              "transient_for": null}}],
+             "floating_nodes": [{"id": 4, "type": "floating_con", "marks": ["keep"]}]}]}"#;
+        let tree = RawNode::parse(json.as_bytes()).unwrap().into_tree();
+        assert_eq!(tree.marked("keep"), vec![3, 4].into_iter().collect());
+        assert!(tree.marked("missing").is_empty());
+
+        let workspace = &tree.root.nodes[0];
+        assert!(matches!(workspace.nodetype, NodeType::Workspace));
+        assert!(matches!(workspace.layout, NodeLayout::Tabbed));
+        assert_eq!(workspace.focus, vec![3]);
+        assert!(matches!(
+            workspace.floating_nodes[0].nodetype,
+            NodeType::FloatingCon
+        ));
+        let window = &workspace.nodes[0];
+        assert!(window.focused);
+        assert_eq!(window.window, Some(12));
+        assert_eq!(window.rect, (1, 2, 3, 4));
+        let properties = window.window_properties.as_ref().unwrap();
+        assert_eq!(properties.len(), 2);
+        assert_eq!(properties[&WindowProperty::Class], "URxvt");
+    }
+
+    #[test]
+    fn forgets_closed_windows() {
+        let mut tree = tree(&[1]);
+        tree.marks.insert(4, vec!["keep".to_owned()]);
+        tree.remove_window(4);
+        assert!(!tree.has_fullscreen());
+        assert!(tree.marked("keep").is_empty());
+        assert!(tree.root.nodes[0].nodes[0].nodes.is_empty());
+    }
+}
@@ -0,0 +1,82 @@
+use std::thread::{self, JoinHandle};
+
+use crossbeam_channel as chan;
+
+/// Threads feeding the daemon's event loop, stopped and joined when the daemon exits.
+/// Workers poll their `Cancelled` token whenever they wake up, threads blocked on io need a waker
+/// registered with `on_stop` that pokes them.
+pub struct Workers {
+    cancel: Option<chan::Sender<()>>,
+    cancelled: chan::Receiver<()>,
+    threads: Vec<(String, JoinHandle<()>)>,
+    wakers: Vec<Box<dyn FnOnce() + Send>>,
+}
+
+/// Disconnects once the workers should stop, nothing is ever sent through it
+#[derive(Clone)]
+pub struct Cancelled(chan::Receiver<()>);
+
+impl Cancelled {
+    pub fn is_cancelled(&self) -> bool {
+        self.0.try_recv() == Err(chan::TryRecvError::Disconnected)
+    }
+
+    /// Becomes ready on cancellation, for `select!`
+    pub fn channel(&self) -> &chan::Receiver<()> {
+        &self.0
+    }
+}
+
+impl Workers {
+    pub fn new() -> Self {
+        let (cancel, cancelled) = chan::bounded(0);
+        Self {
+            cancel: Some(cancel),
+            cancelled,
+            threads: Vec::new(),
+            wakers: Vec::new(),
+        }
+    }
+
+    pub fn spawn<F>(&mut self, name: &str, f: F)
+    where
+        F: FnOnce(Cancelled) + Send + 'static,
+    {
+        let cancelled = Cancelled(self.cancelled.clone());
+        let handle = thread::Builder::new()
+            .name(name.to_owned())
+            .spawn(move || f(cancelled))
+            .expect("Can't spawn thread");
+        self.threads.push((name.to_owned(), handle));
+    }
+
+    /// Runs `wake` on stop, after the workers got cancelled
+    pub fn on_stop<F>(&mut self, wake: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.wakers.push(Box::new(wake));
+    }
+
+    /// Cancels and wakes all workers and waits for them to exit.
+    /// Receivers of channels the workers send to have to be dropped before, a worker blocked on a
+    /// full channel can't notice the cancellation.
+    pub fn stop(mut self) {
+        self.cancel.take();
+        for wake in self.wakers.drain(..) {
+            wake();
+        }
+        for (name, handle) in self.threads.drain(..) {
+            log::debug!("Waiting for {} thread", name);
+            if handle.join().is_err() {
+                log::error!("{} thread panicked", name);
+            }
+        }
+    }
+}
+
+impl Default for Workers {
+    fn default() -> Self {
+        Self::new()
+    }
+}
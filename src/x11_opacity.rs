@@ -0,0 +1,109 @@
+//! Sets `_NET_WM_WINDOW_OPACITY` on the client windows directly for compositors that read it,
+//! e.g. picom, instead of going through the window manager's opacity command.
+
+use crate::opacity::Opacity;
+
+#[cfg(feature = "x11")]
+pub use self::x11::X11Opacity;
+
+#[cfg(not(feature = "x11"))]
+pub use self::stub::X11Opacity;
+
+#[cfg(not(feature = "x11"))]
+mod stub {
+    use std::error::Error;
+
+    use crate::opacity::Opacity;
+
+    pub struct X11Opacity;
+
+    impl X11Opacity {
+        pub fn connect() -> Result<Self, Box<dyn Error>> {
+            Err("transparentd was built without the x11 feature".into())
+        }
+
+        pub fn set_opacity(
+            &self,
+            _: &[(u32, Opacity)],
+        ) -> Result<Vec<Result<(), String>>, Box<dyn Error>> {
+            unreachable!("can't connect without the x11 feature")
+        }
+    }
+}
+
+/// Value of the property, the full range of a CARDINAL is fully opaque
+#[cfg_attr(not(feature = "x11"), allow(dead_code))]
+fn cardinal(opacity: Opacity) -> u32 {
+    (opacity.value() * f64::from(u32::MAX)).round() as u32
+}
+
+#[cfg(feature = "x11")]
+mod x11 {
+    use std::error::Error;
+
+    use x11rb::{
+        connection::Connection,
+        protocol::xproto::{AtomEnum, ConnectionExt, PropMode},
+        rust_connection::RustConnection,
+        wrapper::ConnectionExt as _,
+    };
+
+    use crate::opacity::Opacity;
+
+    pub struct X11Opacity {
+        conn: RustConnection,
+        atom: u32,
+    }
+
+    impl X11Opacity {
+        pub fn connect() -> Result<Self, Box<dyn Error>> {
+            let (conn, _) = x11rb::connect(None)?;
+            let atom = conn
+                .intern_atom(false, b"_NET_WM_WINDOW_OPACITY")?
+                .reply()?
+                .atom;
+            Ok(Self { conn, atom })
+        }
+
+        /// Outcome for each window, fails as a whole only if the connection broke
+        pub fn set_opacity(
+            &self,
+            windows: &[(u32, Opacity)],
+        ) -> Result<Vec<Result<(), String>>, Box<dyn Error>> {
+            // send everything before waiting for errors so it's one round trip
+            let mut cookies = Vec::with_capacity(windows.len());
+            for &(window, opacity) in windows {
+                let cookie = if opacity == Opacity::max() {
+                    // compositors treat a missing property as opaque
+                    self.conn.delete_property(window, self.atom)?
+                } else {
+                    self.conn.change_property32(
+                        PropMode::REPLACE,
+                        window,
+                        self.atom,
+                        AtomEnum::CARDINAL,
+                        &[super::cardinal(opacity)],
+                    )?
+                };
+                cookies.push(cookie);
+            }
+            self.conn.flush()?;
+
+            Ok(cookies
+                .into_iter()
+                .map(|cookie| cookie.check().map_err(|e| e.to_string()))
+                .collect())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn opaque_is_the_full_range() {
+        assert_eq!(cardinal(Opacity::max()), u32::MAX);
+        assert_eq!(cardinal(Opacity::min()), 0);
+    }
+}